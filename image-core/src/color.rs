@@ -0,0 +1,325 @@
+//! Conversion of images with embedded ICC profiles to sRGB, as they are stored untagged.
+
+use image::{DynamicImage, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+use qcms::{DataType, Intent, Transform};
+
+/// An embedded ICC profile, which pixels are converted from.
+pub(crate) struct Profile {
+  profile: Box<qcms::Profile>,
+  srgb: Box<qcms::Profile>,
+  /// Color space signature of the profile
+  space: [u8; 4],
+}
+
+impl Profile {
+  /// Parse an embedded profile, `None` if it is invalid, unsupported or already sRGB.
+  pub fn new(icc: &[u8]) -> Option<Self> {
+    let space = icc.get(16..20)?.try_into().unwrap();
+    let profile = qcms::Profile::new_from_slice(icc, false)?;
+    let mut srgb = qcms::Profile::new_sRGB();
+    srgb.precache_output_transform();
+    let profile = Self {
+      profile,
+      srgb,
+      space,
+    };
+    (!profile.is_srgb()).then_some(profile)
+  }
+
+  /// Whether converting with the profile changes colors by at most 1, as it does for the many
+  /// variants of sRGB profiles, which would only add rounding errors.
+  fn is_srgb(&self) -> bool {
+    let (input, samples, expected): (_, Vec<u8>, Vec<u8>) = match &self.space {
+      b"RGB " => {
+        let steps = || (0..=255).step_by(15);
+        let samples: Vec<_> = steps()
+          .flat_map(|r| steps().flat_map(move |g| steps().flat_map(move |b| [r, g, b])))
+          .collect();
+        (DataType::RGB8, samples.clone(), samples)
+      }
+      b"GRAY" => (
+        DataType::Gray8,
+        (0..=255).collect(),
+        (0..=255).flat_map(|value| [value; 3]).collect(),
+      ),
+      _ => return false,
+    };
+    let Some(transform) = self.transform(input, DataType::RGB8) else {
+      return false;
+    };
+    let mut output = vec![0; expected.len()];
+    transform.convert(&samples, &mut output);
+    output
+      .iter()
+      .zip(&expected)
+      .all(|(&actual, &expected)| actual.abs_diff(expected) <= 1)
+  }
+
+  /// The transform of `input` pixels to sRGB, if the profile is for their color space.
+  fn transform(&self, input: DataType, output: DataType) -> Option<Transform> {
+    let space = match input {
+      DataType::Gray8 | DataType::GrayA8 => b"GRAY",
+      DataType::CMYK => b"CMYK",
+      _ => b"RGB ",
+    };
+    if self.space != *space {
+      return None;
+    }
+    Transform::new_to(&self.profile, &self.srgb, input, output, Intent::default())
+  }
+
+  /// Convert an image to sRGB, returning it as is if the profile isn't for its color space.
+  ///
+  /// Images with more than 8 bits per channel are converted at 8 bits.
+  pub fn to_srgb(&self, image: DynamicImage) -> DynamicImage {
+    let color = image.color();
+    match (&self.space, color.has_color(), color.has_alpha()) {
+      (b"RGB ", true, false) => {
+        let mut rgb = image.into_rgb8();
+        if let Some(transform) = self.transform(DataType::RGB8, DataType::RGB8) {
+          transform.apply(&mut rgb);
+        }
+        DynamicImage::ImageRgb8(rgb)
+      }
+      (b"RGB ", true, true) => DynamicImage::ImageRgba8(self.to_srgb_rgba(image.into_rgba8())),
+      (b"GRAY", false, false) => {
+        let luma = image.into_luma8();
+        let Some(transform) = self.transform(DataType::Gray8, DataType::RGB8) else {
+          return DynamicImage::ImageLuma8(luma);
+        };
+        let mut rgb = RgbImage::new(luma.width(), luma.height());
+        transform.convert(&luma, &mut rgb);
+        // grays stay gray in sRGB
+        let luma = GrayImage::from_fn(luma.width(), luma.height(), |x, y| {
+          image::Luma([rgb.get_pixel(x, y)[0]])
+        });
+        DynamicImage::ImageLuma8(luma)
+      }
+      (b"GRAY", false, true) => {
+        let luma = image.into_luma_alpha8();
+        let Some(transform) = self.transform(DataType::GrayA8, DataType::RGBA8) else {
+          return DynamicImage::ImageLumaA8(luma);
+        };
+        let mut rgba = RgbaImage::new(luma.width(), luma.height());
+        transform.convert(&luma, &mut rgba);
+        let luma = GrayAlphaImage::from_fn(luma.width(), luma.height(), |x, y| {
+          let [value, _, _, alpha] = rgba.get_pixel(x, y).0;
+          image::LumaA([value, alpha])
+        });
+        DynamicImage::ImageLumaA8(luma)
+      }
+      _ => image,
+    }
+  }
+
+  /// Convert a frame of an animation to sRGB.
+  pub fn to_srgb_rgba(&self, mut frame: RgbaImage) -> RgbaImage {
+    if let Some(transform) = self.transform(DataType::RGBA8, DataType::RGBA8) {
+      transform.apply(&mut frame);
+    }
+    frame
+  }
+}
+
+/// Convert CMYK pixels, with 0 as no ink, to sRGB with their profile or naively without one.
+pub(crate) fn cmyk_to_srgb(cmyk: &[u8], profile: Option<&Profile>) -> Vec<u8> {
+  let mut rgb = vec![0; cmyk.len() / 4 * 3];
+  if let Some(transform) =
+    profile.and_then(|profile| profile.transform(DataType::CMYK, DataType::RGB8))
+  {
+    transform.convert(cmyk, &mut rgb);
+    return rgb;
+  }
+
+  for (cmyk, rgb) in cmyk.chunks_exact(4).zip(rgb.chunks_exact_mut(3)) {
+    let white = 255 - u16::from(cmyk[3]);
+    for (ink, value) in cmyk[..3].iter().zip(rgb) {
+      *value = ((255 - u16::from(*ink)) * white / 255) as u8;
+    }
+  }
+  rgb
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+  use image::{ImageBuffer, Luma, LumaA, Rgb, Rgba};
+
+  use super::*;
+
+  /// The colorants of the sRGB primaries, adapted to D50.
+  const SRGB_PRIMARIES: [[f64; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+  ];
+  const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+  /// An ICC profile for a color space, with `XYZ` as connection space.
+  fn icc(class: &[u8; 4], space: &[u8; 4], tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut header = vec![0; 128];
+    header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    header[12..16].copy_from_slice(class);
+    header[16..20].copy_from_slice(space);
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    header[68..80].copy_from_slice(&xyz(D50)[8..]);
+
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = vec![];
+    let start = 128 + 4 + 12 * tags.len();
+    for (signature, tag) in tags {
+      table.extend_from_slice(*signature);
+      table.extend_from_slice(&((start + data.len()) as u32).to_be_bytes());
+      table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+      data.extend_from_slice(tag);
+      data.resize((data.len() + 3) / 4 * 4, 0);
+    }
+
+    let mut profile = [header, table, data].concat();
+    let len = (profile.len() as u32).to_be_bytes();
+    profile[..4].copy_from_slice(&len);
+    profile
+  }
+
+  fn xyz(value: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for value in value {
+      tag.extend_from_slice(&((value * 65536.0).round() as i32).to_be_bytes());
+    }
+    tag
+  }
+
+  fn gamma(gamma: f64) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0\0\0\0\x01".to_vec();
+    tag.extend_from_slice(&((gamma * 256.0).round() as u16).to_be_bytes());
+    tag
+  }
+
+  /// The sRGB transfer function, as a table.
+  fn srgb_curve() -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend_from_slice(&1024u32.to_be_bytes());
+    for i in 0..1024 {
+      let value = f64::from(i) / 1023.0;
+      let linear = if value <= 0.04045 {
+        value / 12.92
+      } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+      };
+      tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    tag
+  }
+
+  /// An RGB profile with the colorants of the sRGB primaries in `order`, so that for example
+  /// `[1, 2, 0]` makes red green, and a gamma of 2.2 or the sRGB transfer function.
+  pub fn rgb_profile(order: [usize; 3], srgb_curve: bool) -> Vec<u8> {
+    let curve = if srgb_curve {
+      self::srgb_curve()
+    } else {
+      gamma(2.2)
+    };
+    let tags = [
+      (b"wtpt", xyz(D50)),
+      (b"rXYZ", xyz(SRGB_PRIMARIES[order[0]])),
+      (b"gXYZ", xyz(SRGB_PRIMARIES[order[1]])),
+      (b"bXYZ", xyz(SRGB_PRIMARIES[order[2]])),
+      (b"rTRC", curve.clone()),
+      (b"gTRC", curve.clone()),
+      (b"bTRC", curve),
+    ];
+    icc(b"mntr", b"RGB ", &tags)
+  }
+
+  /// A linear gray profile.
+  pub fn gray_profile() -> Vec<u8> {
+    icc(
+      b"mntr",
+      b"GRAY",
+      &[(b"wtpt", xyz(D50)), (b"kTRC", gamma(1.0))],
+    )
+  }
+
+  /// A CMYK profile of an odd printer, where cyan ink prints red.
+  pub fn cmyk_profile() -> Vec<u8> {
+    let red = SRGB_PRIMARIES[0];
+    let mut lut = b"mft2\0\0\0\0\x04\x03\x02\0".to_vec();
+    for i in 0..9 {
+      let value: i32 = if i % 4 == 0 { 0x10000 } else { 0 };
+      lut.extend_from_slice(&value.to_be_bytes());
+    }
+    // linear input and output tables of 2 entries
+    lut.extend_from_slice(&[0, 2, 0, 2]);
+    let linear = [0, 0, 0xff, 0xff];
+    lut.extend_from_slice(&linear.repeat(4));
+    // the grid is indexed by cyan, magenta, yellow then black
+    for point in 0..16 {
+      let (cyan, black) = (point & 8 != 0, point & 1 != 0);
+      let color = match (cyan, black) {
+        (_, true) => [0.0; 3],
+        (true, false) => red,
+        (false, false) => D50,
+      };
+      for value in color {
+        lut.extend_from_slice(&((value * 32768.0).round() as u16).to_be_bytes());
+      }
+    }
+    lut.extend_from_slice(&linear.repeat(3));
+    icc(b"prtr", b"CMYK", &[(b"wtpt", xyz(D50)), (b"A2B0", lut)])
+  }
+
+  fn assert_near(actual: &[u8], expected: &[u8]) {
+    let near = actual
+      .iter()
+      .zip(expected)
+      .all(|(&a, &e)| a.abs_diff(e) <= 2);
+    assert!(near, "{actual:?} != {expected:?}");
+  }
+
+  #[test]
+  fn converts_to_srgb() {
+    let profile = Profile::new(&rgb_profile([1, 2, 0], false)).unwrap();
+    let image = RgbImage::from_fn(3, 1, |x, _| {
+      let mut pixel = Rgb([0; 3]);
+      pixel[x as usize] = 255;
+      pixel
+    });
+    let converted = profile.to_srgb(DynamicImage::ImageRgb8(image)).into_rgb8();
+    assert_near(converted.as_raw(), &[0, 255, 0, 0, 0, 255, 255, 0, 0]);
+
+    let frame = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 100]));
+    assert_near(profile.to_srgb_rgba(frame).as_raw(), &[0, 255, 0, 100]);
+    // 16 bit images are converted at 8 bits
+    let image = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(1, 1, Rgb([0, 0, 65535])));
+    let converted = profile.to_srgb(image);
+    assert_near(converted.as_rgb8().unwrap().as_raw(), &[255, 0, 0]);
+
+    // linear gray is brighter in sRGB
+    let profile = Profile::new(&gray_profile()).unwrap();
+    let image = GrayImage::from_pixel(1, 1, Luma([128]));
+    let converted = profile.to_srgb(DynamicImage::ImageLuma8(image));
+    assert_near(converted.as_luma8().unwrap().as_raw(), &[188]);
+    let image = GrayAlphaImage::from_pixel(1, 1, LumaA([128, 7]));
+    let converted = profile.to_srgb(DynamicImage::ImageLumaA8(image));
+    assert_near(converted.as_luma_alpha8().unwrap().as_raw(), &[188, 7]);
+    // the profile doesn't apply to other color spaces
+    let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([128; 3])));
+    assert_eq!(profile.to_srgb(image.clone()), image);
+
+    let profile = Profile::new(&cmyk_profile()).unwrap();
+    let cmyk = [0, 0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 255];
+    assert_near(
+      &cmyk_to_srgb(&cmyk, Some(&profile)),
+      &[255, 255, 255, 255, 0, 0, 0, 0, 0],
+    );
+    assert_eq!(
+      cmyk_to_srgb(&cmyk, None),
+      [255, 255, 255, 0, 255, 255, 0, 0, 0]
+    );
+
+    let srgb = Profile::new(&rgb_profile([0, 1, 2], true));
+    assert!(srgb.is_none(), "sRGB profiles aren't converted from");
+    assert!(Profile::new(&rgb_profile([0, 1, 2], false)).is_some());
+    assert!(Profile::new(b"not a profile").is_none());
+  }
+}
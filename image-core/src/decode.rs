@@ -0,0 +1,508 @@
+use std::io::Cursor;
+
+use image::{
+  codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+  error::{DecodingError, UnsupportedError, UnsupportedErrorKind},
+  io::Reader as ImageReader,
+  AnimationDecoder, DynamicImage, GrayImage, ImageBuffer, ImageDecoder, ImageError, ImageFormat,
+  RgbImage,
+};
+use image_hasher::{HashSequence, Hasher};
+use jpeg_decoder::PixelFormat;
+
+use crate::color::{self, Profile};
+use crate::{Details, Error, Frames, Hash, Orientation};
+
+/// Animations with more frames than this are rejected.
+const MAX_FRAMES: usize = 1000;
+
+/// Limits on the size of decoded images, checked against the headers before decoding.
+///
+/// JPEG images over the limits are decoded at 1/2, 1/4 or 1/8 of their size if that is within
+/// them, other images are rejected with [`Error::TooLarge`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+  /// Maximum width and height
+  pub max_dimension: u32,
+  /// Maximum number of pixels
+  pub max_pixels: u64,
+  /// Maximum size of the decoded pixels in bytes, frames of animations count as 4 bytes per
+  /// pixel
+  pub max_alloc: u64,
+}
+
+impl Default for Limits {
+  /// [`DEFAULT_LIMITS`](crate::DEFAULT_LIMITS).
+  fn default() -> Self {
+    crate::DEFAULT_LIMITS
+  }
+}
+
+impl Limits {
+  fn check(&self, (width, height): (u32, u32), bytes: u64) -> Result<(), Error> {
+    if width > self.max_dimension
+      || height > self.max_dimension
+      || u64::from(width) * u64::from(height) > self.max_pixels
+      || bytes > self.max_alloc
+    {
+      return Err(Error::TooLarge(Details::size((width, height))));
+    }
+    Ok(())
+  }
+
+  fn check_decoder<'a>(&self, decoder: &impl ImageDecoder<'a>) -> Result<(), Error> {
+    self.check(decoder.dimensions(), decoder.total_bytes())
+  }
+
+  /// Check the size of the RGBA frames of an animation.
+  fn check_frames<'a>(&self, decoder: &impl ImageDecoder<'a>) -> Result<(), Error> {
+    let (width, height) = decoder.dimensions();
+    self.check((width, height), u64::from(width) * u64::from(height) * 4)
+  }
+}
+
+/// A still image, or the frames of an animation, with their embedded color profile.
+enum Decoded {
+  Image(DynamicImage, Option<Profile>),
+  Frames(image::Frames<'static>, Option<Profile>),
+}
+
+/// Decode an image in sRGB and `orientation`, reading every frame of animated GIF, APNG and
+/// WebP files.
+///
+/// Animations are returned as their first frame, along with the hashes of all their frames
+/// unless only the first one is wanted. Errors have the format and size of the image, as far
+/// as they were read.
+pub(crate) fn decode(
+  reader: ImageReader<Cursor<Vec<u8>>>,
+  hasher: &Hasher<Hash>,
+  wanted: Frames,
+  limits: &Limits,
+  orientation: Orientation,
+) -> Result<(DynamicImage, Option<HashSequence<Hash>>), Error> {
+  let mut details = Details {
+    format: reader.format().map(|format| format.to_mime_type()),
+    ..Details::default()
+  };
+  let decoded = decode_format(reader, limits, &mut details).and_then(|decoded| match decoded {
+    Decoded::Image(image, profile) => {
+      let image = match profile {
+        Some(profile) => profile.to_srgb(image),
+        None => image,
+      };
+      Ok((orientation.apply(image), None))
+    }
+    Decoded::Frames(frames, profile) => decode_frames(frames, hasher, wanted, profile, orientation),
+  });
+  decoded.map_err(|err| err.with_details(details))
+}
+
+fn decode_format(
+  reader: ImageReader<Cursor<Vec<u8>>>,
+  limits: &Limits,
+  details: &mut Details,
+) -> Result<Decoded, Error> {
+  match reader.format() {
+    Some(ImageFormat::Gif) => {
+      let decoder = GifDecoder::new(reader.into_inner())?;
+      details.set_size(decoder.dimensions());
+      limits.check_frames(&decoder)?;
+      Ok(Decoded::Frames(decoder.into_frames(), None))
+    }
+    Some(ImageFormat::Png) => {
+      let mut decoder = PngDecoder::new(reader.into_inner())?;
+      details.set_size(decoder.dimensions());
+      let profile = profile(&mut decoder);
+      if !decoder.is_apng() {
+        limits.check_decoder(&decoder)?;
+        let image = DynamicImage::from_decoder(decoder)?;
+        return Ok(Decoded::Image(image, profile));
+      }
+      limits.check_frames(&decoder)?;
+      Ok(Decoded::Frames(decoder.apng().into_frames(), profile))
+    }
+    Some(ImageFormat::WebP) => {
+      // the decoder decodes the whole file when it is created, so files whose size can't be
+      // checked first are rejected
+      let cursor = reader.into_inner();
+      let size = webp_dimensions(cursor.get_ref()).ok_or_else(|| {
+        ImageError::Decoding(DecodingError::new(
+          ImageFormat::WebP.into(),
+          "invalid header",
+        ))
+      })?;
+      details.set_size(size);
+      limits.check(size, u64::from(size.0) * u64::from(size.1) * 4)?;
+      let mut decoder = WebPDecoder::new(cursor)?;
+      let profile = profile(&mut decoder);
+      if !decoder.has_animation() {
+        let image = DynamicImage::from_decoder(decoder)?;
+        return Ok(Decoded::Image(image, profile));
+      }
+      Ok(Decoded::Frames(decoder.into_frames(), profile))
+    }
+    Some(ImageFormat::Jpeg) => decode_jpeg(reader.into_inner(), limits, details),
+    Some(format) => {
+      let image = decode_other(reader.into_inner(), format, limits, details)?;
+      Ok(Decoded::Image(image, None))
+    }
+    None => Err(Error::UnknownFormat),
+  }
+}
+
+/// The embedded color profile of an image, if it isn't sRGB.
+fn profile<'a>(decoder: &mut impl ImageDecoder<'a>) -> Option<Profile> {
+  Profile::new(&decoder.icc_profile()?)
+}
+
+/// Decode a JPEG image, at a reduced scale if it is over the limits.
+///
+/// CMYK images are converted to sRGB with their profile, as `image` ignores it.
+fn decode_jpeg(
+  cursor: Cursor<Vec<u8>>,
+  limits: &Limits,
+  details: &mut Details,
+) -> Result<Decoded, Error> {
+  // the values of CMYK images are stored inverted when there is an Adobe segment, which the
+  // decoder always undoes
+  let inverted = !jpeg_segments(cursor.get_ref())
+    .any(|(marker, data)| marker == 0xee && data.starts_with(b"Adobe"));
+  let mut decoder = jpeg_decoder::Decoder::new(cursor);
+  decoder.read_info().map_err(jpeg_error)?;
+  let info = decoder.info().unwrap();
+  let (width, height) = (u32::from(info.width), u32::from(info.height));
+  details.set_size((width, height));
+
+  let bytes_per_pixel = info.pixel_format.pixel_bytes() as u64;
+  let bytes = |(width, height): (u32, u32)| u64::from(width) * u64::from(height) * bytes_per_pixel;
+  let (width, height) = if limits
+    .check((width, height), bytes((width, height)))
+    .is_ok()
+  {
+    (width, height)
+  } else {
+    let scaled = |denom: u32| ((width + denom - 1) / denom, (height + denom - 1) / denom);
+    let size = [2, 4, 8]
+      .into_iter()
+      .map(scaled)
+      .find(|&size| limits.check(size, bytes(size)).is_ok());
+    let Some((scaled_width, scaled_height)) = size else {
+      return Err(Error::TooLarge(Details::size((width, height))));
+    };
+
+    // the scaled size is at most `u16::MAX` as JPEG dimensions are
+    let (width, height) = decoder
+      .scale(scaled_width as u16, scaled_height as u16)
+      .map_err(jpeg_error)?;
+    let size = (u32::from(width), u32::from(height));
+    limits.check(size, bytes(size))?;
+    size
+  };
+
+  decoder.set_max_decoding_buffer_size(usize::try_from(limits.max_alloc).unwrap_or(usize::MAX));
+  let profile = decoder.icc_profile().and_then(|icc| Profile::new(&icc));
+  let mut data = decoder.decode().map_err(jpeg_error)?;
+  let image = match info.pixel_format {
+    PixelFormat::L8 => GrayImage::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+    PixelFormat::L16 => {
+      let data = data
+        .chunks_exact(2)
+        .map(|value| u16::from_ne_bytes([value[0], value[1]]))
+        .collect();
+      ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+    }
+    PixelFormat::RGB24 => RgbImage::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+    PixelFormat::CMYK32 => {
+      if inverted {
+        data.iter_mut().for_each(|value| *value = 255 - *value);
+      }
+      let rgb = color::cmyk_to_srgb(&data, profile.as_ref());
+      RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+    }
+  };
+  let image = image.ok_or_else(|| {
+    ImageError::Decoding(DecodingError::new(
+      ImageFormat::Jpeg.into(),
+      "wrong size of decoded pixels",
+    ))
+  })?;
+  Ok(Decoded::Image(image, profile))
+}
+
+/// Convert a JPEG error like `image` does.
+fn jpeg_error(err: jpeg_decoder::Error) -> ImageError {
+  match err {
+    jpeg_decoder::Error::Io(err) => ImageError::IoError(err),
+    jpeg_decoder::Error::Unsupported(feature) => {
+      ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        ImageFormat::Jpeg.into(),
+        UnsupportedErrorKind::GenericFeature(format!("{feature:?}")),
+      ))
+    }
+    err => ImageError::Decoding(DecodingError::new(ImageFormat::Jpeg.into(), err)),
+  }
+}
+
+/// The marker and data of the segments of a JPEG file before its image data.
+pub(crate) fn jpeg_segments(file: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+  let mut pos = 2;
+  std::iter::from_fn(move || {
+    let [0xff, marker, len_hi, len_lo, ..] = *file.get(pos..)? else {
+      return None;
+    };
+    // the image data has no length
+    if marker == 0xda {
+      return None;
+    }
+    let len = usize::from(len_hi) << 8 | usize::from(len_lo);
+    let data = file.get(pos + 4..pos + 2 + len)?;
+    pos += 2 + len;
+    Some((marker, data))
+  })
+}
+
+/// Decode an image in a format without special handling.
+fn decode_other(
+  mut cursor: Cursor<Vec<u8>>,
+  format: ImageFormat,
+  limits: &Limits,
+  details: &mut Details,
+) -> Result<DynamicImage, Error> {
+  let size = ImageReader::with_format(&mut cursor, format).into_dimensions()?;
+  details.set_size(size);
+  // up to 16 bits per channel of RGBA, the actual size is checked by `image`
+  limits.check(size, 0)?;
+  cursor.set_position(0);
+
+  let mut reader = ImageReader::with_format(cursor, format);
+  let mut image_limits = image::io::Limits::default();
+  image_limits.max_alloc = Some(limits.max_alloc);
+  reader.limits(image_limits);
+  reader.decode().map_err(|err| match err {
+    ImageError::Limits(_) => Error::TooLarge(Details::size(size)),
+    err => err.into(),
+  })
+}
+
+/// Read the size of a WebP image from its header.
+fn webp_dimensions(file: &[u8]) -> Option<(u32, u32)> {
+  if file.get(..4)? != b"RIFF" || file.get(8..12)? != b"WEBP" {
+    return None;
+  }
+  let chunk = file.get(12..16)?;
+  let data = file.get(20..)?;
+  let le = |bytes: &[u8]| {
+    bytes
+      .iter()
+      .rev()
+      .fold(0, |acc, &b| acc << 8 | u32::from(b))
+  };
+
+  match chunk {
+    b"VP8 " => {
+      // frame tag, start code, 14 bits of width and height each
+      let data = data.get(..10)?;
+      (data[3..6] == [0x9d, 0x01, 0x2a])
+        .then(|| (le(&data[6..8]) & 0x3fff, le(&data[8..10]) & 0x3fff))
+    }
+    b"VP8L" => {
+      let data = data.get(..5)?;
+      let bits = le(&data[1..5]);
+      (data[0] == 0x2f).then_some(((bits & 0x3fff) + 1, (bits >> 14 & 0x3fff) + 1))
+    }
+    b"VP8X" => {
+      let data = data.get(..10)?;
+      Some((le(&data[4..7]) + 1, le(&data[7..10]) + 1))
+    }
+    _ => None,
+  }
+}
+
+fn decode_frames(
+  frames: image::Frames,
+  hasher: &Hasher<Hash>,
+  wanted: Frames,
+  profile: Option<Profile>,
+  orientation: Orientation,
+) -> Result<(DynamicImage, Option<HashSequence<Hash>>), Error> {
+  let mut first = None;
+  let mut hashes = vec![];
+
+  let limit = match wanted {
+    Frames::All => MAX_FRAMES + 1,
+    Frames::First => 1,
+  };
+  for frame in frames.take(limit) {
+    if hashes.len() == MAX_FRAMES {
+      return Err(Error::TooManyFrames(Details::default()));
+    }
+
+    let mut frame = frame?.into_buffer();
+    if let Some(profile) = &profile {
+      frame = profile.to_srgb_rgba(frame);
+    }
+    let frame = orientation.apply_rgba(frame);
+    if wanted == Frames::All {
+      hashes.push(hasher.hash_image(&frame));
+    }
+    if first.is_none() {
+      first = Some(frame);
+    }
+  }
+
+  let Some(first) = first else {
+    return Err(ImageError::IoError(std::io::ErrorKind::UnexpectedEof.into()).into());
+  };
+  let image = DynamicImage::ImageRgba8(first);
+
+  // single frame GIFs are the most common kind of GIF
+  if hashes.len() <= 1 {
+    return Ok((image, None));
+  }
+
+  Ok((image, Some(HashSequence::from_hashes(hashes))))
+}
+
+#[cfg(test)]
+mod test {
+  use image::RgbImage;
+
+  use super::*;
+  use crate::color::test::cmyk_profile;
+
+  /// A baseline CMYK JPEG with a flat 8x8 block for each color, stored inverted with an Adobe
+  /// segment as Photoshop does, and as is without one.
+  fn cmyk_jpeg(colors: &[[u8; 4]], adobe: bool, icc: Option<&[u8]>) -> Vec<u8> {
+    let mut file = vec![0xff, 0xd8];
+    let mut segment = |marker: u8, data: &[u8]| {
+      file.extend_from_slice(&[0xff, marker]);
+      file.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+      file.extend_from_slice(data);
+    };
+    if adobe {
+      segment(0xee, b"Adobe\0\x64\0\0\0\0\0");
+    }
+    if let Some(icc) = icc {
+      segment(0xe2, &[b"ICC_PROFILE\0\x01\x01", icc].concat());
+    }
+    // quantization by 1, so that DC coefficients are 8 times the values
+    let mut quantization = [1; 65];
+    quantization[0] = 0;
+    segment(0xdb, &quantization);
+    let mut frame = vec![8, 0, 8];
+    frame.extend_from_slice(&(colors.len() as u16 * 8).to_be_bytes());
+    frame.push(4);
+    for id in 1..=4 {
+      frame.extend_from_slice(&[id, 0x11, 0]);
+    }
+    segment(0xc0, &frame);
+    // 4 bit codes for the DC categories, and only the end of block for AC
+    let mut counts = [0; 16];
+    counts[3] = 12;
+    segment(
+      0xc4,
+      &[&[0x00], &counts[..], &(0..12).collect::<Vec<_>>()].concat(),
+    );
+    let mut counts = [0; 16];
+    counts[0] = 1;
+    segment(0xc4, &[&[0x10], &counts[..], &[0]].concat());
+    segment(0xda, &[4, 1, 0, 2, 0, 3, 0, 4, 0, 0, 63, 0]);
+
+    let mut bits = vec![];
+    let mut push =
+      |value: i32, len: u32| bits.extend((0..len).rev().map(|bit| value >> bit & 1 == 1));
+    let mut previous = [0; 4];
+    for color in colors {
+      for (previous, &value) in previous.iter_mut().zip(color) {
+        let value = if adobe { 255 - value } else { value };
+        let dc = (i32::from(value) - 128) * 8;
+        let diff = dc - *previous;
+        *previous = dc;
+        let category = 32 - diff.unsigned_abs().leading_zeros();
+        push(category as i32, 4);
+        push(
+          if diff < 0 {
+            diff + (1 << category) - 1
+          } else {
+            diff
+          },
+          category,
+        );
+        push(0, 1);
+      }
+    }
+    bits.resize((bits.len() + 7) / 8 * 8, true);
+    for byte in bits.chunks(8) {
+      let byte = byte.iter().fold(0, |acc, &bit| acc << 1 | u8::from(bit));
+      file.push(byte);
+      if byte == 0xff {
+        file.push(0);
+      }
+    }
+    file.extend_from_slice(&[0xff, 0xd9]);
+    file
+  }
+
+  #[test]
+  fn decodes_cmyk_jpegs() {
+    let colors = [
+      [0, 0, 0, 0],
+      [255, 0, 0, 0],
+      [0, 0, 0, 255],
+      [0, 128, 255, 0],
+    ];
+    let decode = |file: Vec<u8>| {
+      let decoded = decode_jpeg(
+        Cursor::new(file),
+        &Limits::default(),
+        &mut Details::default(),
+      );
+      let Ok(Decoded::Image(DynamicImage::ImageRgb8(image), _)) = decoded else {
+        panic!("not decoded as RGB");
+      };
+      image
+    };
+    let assert_colors = |image: RgbImage, expected: [[u8; 3]; 4]| {
+      let actual: Vec<_> = (0..4).map(|i| image.get_pixel(i * 8 + 4, 4).0).collect();
+      let near = actual
+        .iter()
+        .flatten()
+        .zip(expected.iter().flatten())
+        .all(|(a, e)| a.abs_diff(*e) <= 2);
+      assert!(near, "{actual:?} != {expected:?}");
+    };
+
+    let icc = cmyk_profile();
+    for adobe in [true, false] {
+      let naive = [[255; 3], [0, 255, 255], [0; 3], [255, 127, 0]];
+      assert_colors(decode(cmyk_jpeg(&colors, adobe, None)), naive);
+      // cyan prints red, and magenta and yellow are ignored
+      let converted = [[255; 3], [255, 0, 0], [0; 3], [255; 3]];
+      assert_colors(decode(cmyk_jpeg(&colors, adobe, Some(&icc))), converted);
+    }
+  }
+
+  #[test]
+  fn reads_webp_dimensions() {
+    let webp = |chunk: &[u8], data: &[u8]| {
+      let mut file = b"RIFF\0\0\0\0WEBP".to_vec();
+      file.extend_from_slice(chunk);
+      file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+      file.extend_from_slice(data);
+      file
+    };
+    let vp8 = webp(
+      b"VP8 ",
+      &[0, 0, 0, 0x9d, 0x01, 0x2a, 0x20, 0x03, 0x58, 0x42],
+    );
+    assert_eq!(webp_dimensions(&vp8), Some((800, 600)));
+    let vp8x = webp(b"VP8X", &[0, 0, 0, 0, 0x1f, 0x03, 0, 0x57, 0x02, 0]);
+    assert_eq!(webp_dimensions(&vp8x), Some((800, 600)));
+    let bomb = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/bomb.webp")).unwrap();
+    assert_eq!(webp_dimensions(&bomb), Some((16383, 16383)));
+
+    assert_eq!(webp_dimensions(&vp8[..20]), None);
+    assert_eq!(webp_dimensions(&webp(b"VP8 ", &[0; 10])), None);
+    assert_eq!(webp_dimensions(b"RIFF\0\0\0\0WAVEfmt "), None);
+  }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+
+use image::{
+  codecs::{
+    png::{CompressionType, FilterType, PngEncoder},
+    webp::WebPEncoder,
+  },
+  DynamicImage, ImageEncoder, ImageError, ImageFormat,
+};
+use serde::{Deserialize, Serialize};
+
+/// How hard PNG images are compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Compression {
+  Fast,
+  Default,
+  Best,
+}
+
+/// The format images are stored in, written as `png`, `png:<fast|default|best>`, `webp`, or
+/// `avif` with an optional quality like `avif:90`.
+///
+/// The sha of an upload is always of the pixels before encoding, which AVIF doesn't preserve.
+/// Encoding AVIF requires the `avif` feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Codec {
+  Png(Compression),
+  /// Lossless WebP
+  WebP,
+  /// AVIF at a quality from 1 to 100, lossy even at 100
+  Avif(u8),
+}
+
+impl Default for Codec {
+  /// Fast PNG, as images were always stored.
+  fn default() -> Self {
+    Codec::Png(Compression::Fast)
+  }
+}
+
+impl Codec {
+  /// Quality of `avif` without one.
+  pub const AVIF_QUALITY: u8 = 90;
+
+  /// Extension of the stored key.
+  pub fn extension(self) -> &'static str {
+    match self {
+      Codec::Png(_) => "png",
+      Codec::WebP => "webp",
+      Codec::Avif(_) => "avif",
+    }
+  }
+
+  /// Whether this build can encode the codec.
+  pub fn is_supported(self) -> bool {
+    !matches!(self, Codec::Avif(_)) || cfg!(feature = "avif")
+  }
+
+  pub fn content_type(self) -> &'static str {
+    match self {
+      Codec::Png(_) => "image/png",
+      Codec::WebP => "image/webp",
+      Codec::Avif(_) => "image/avif",
+    }
+  }
+
+  /// Encode a normalized image, in 8 bit gray or RGB with or without alpha.
+  pub fn encode(self, image: &DynamicImage) -> Result<Vec<u8>, ImageError> {
+    let mut buf = vec![];
+    let (width, height, color) = (image.width(), image.height(), image.color());
+    match self {
+      Codec::Png(compression) => {
+        let compression = match compression {
+          Compression::Fast => CompressionType::Fast,
+          Compression::Default => CompressionType::Default,
+          Compression::Best => CompressionType::Best,
+        };
+        PngEncoder::new_with_quality(
+          &mut Cursor::new(&mut buf),
+          compression,
+          FilterType::default(),
+        )
+        .write_image(image.as_bytes(), width, height, color)?;
+      }
+      Codec::WebP => {
+        WebPEncoder::new_lossless(&mut buf).write_image(image.as_bytes(), width, height, color)?;
+      }
+      Codec::Avif(quality) => buf = encode_avif(image, quality)?,
+    }
+    Ok(buf)
+  }
+}
+
+#[cfg(feature = "avif")]
+fn encode_avif(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, ImageError> {
+  use image::error::EncodingError;
+  use ravif::{Encoder, Img, RGBA8};
+
+  let pixels: Vec<_> = image
+    .to_rgba8()
+    .pixels()
+    .map(|pixel| RGBA8::new(pixel[0], pixel[1], pixel[2], pixel[3]))
+    .collect();
+  let (width, height) = (image.width() as usize, image.height() as usize);
+  let encoded = Encoder::new()
+    .with_quality(f32::from(quality))
+    .with_alpha_quality(f32::from(quality))
+    .encode_rgba(Img::new(&pixels[..], width, height))
+    .map_err(|err| ImageError::Encoding(EncodingError::new(ImageFormat::Avif.into(), err)))?;
+  Ok(encoded.avif_file)
+}
+
+#[cfg(not(feature = "avif"))]
+fn encode_avif(_image: &DynamicImage, _quality: u8) -> Result<Vec<u8>, ImageError> {
+  use image::error::{UnsupportedError, UnsupportedErrorKind};
+
+  Err(ImageError::Unsupported(
+    UnsupportedError::from_format_and_kind(
+      ImageFormat::Avif.into(),
+      UnsupportedErrorKind::Format(ImageFormat::Avif.into()),
+    ),
+  ))
+}
+
+impl fmt::Display for Codec {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Codec::Png(Compression::Fast) => f.write_str("png:fast"),
+      Codec::Png(Compression::Default) => f.write_str("png:default"),
+      Codec::Png(Compression::Best) => f.write_str("png:best"),
+      Codec::WebP => f.write_str("webp"),
+      Codec::Avif(quality) => write!(f, "avif:{quality}"),
+    }
+  }
+}
+
+impl FromStr for Codec {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (name, param) = match s.trim().split_once(':') {
+      Some((name, param)) => (name, Some(param)),
+      None => (s.trim(), None),
+    };
+    let invalid = || format!("invalid codec {s:?}");
+    match (name, param) {
+      ("png", None | Some("fast")) => Ok(Codec::Png(Compression::Fast)),
+      ("png", Some("default")) => Ok(Codec::Png(Compression::Default)),
+      ("png", Some("best")) => Ok(Codec::Png(Compression::Best)),
+      ("webp", None) => Ok(Codec::WebP),
+      ("avif", None) => Ok(Codec::Avif(Codec::AVIF_QUALITY)),
+      ("avif", Some(quality)) => match quality.parse() {
+        Ok(quality @ 1..=100) => Ok(Codec::Avif(quality)),
+        _ => Err(invalid()),
+      },
+      _ => Err(invalid()),
+    }
+  }
+}
+
+impl TryFrom<String> for Codec {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    value.parse()
+  }
+}
+
+impl From<Codec> for String {
+  fn from(codec: Codec) -> Self {
+    codec.to_string()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use image::{ImageBuffer, Rgba};
+
+  use super::*;
+
+  #[test]
+  fn parses_codecs() {
+    for (s, codec) in [
+      ("png", Codec::Png(Compression::Fast)),
+      ("png:best", Codec::Png(Compression::Best)),
+      (" png:default ", Codec::Png(Compression::Default)),
+      ("webp", Codec::WebP),
+      ("avif", Codec::Avif(90)),
+      ("avif:60", Codec::Avif(60)),
+    ] {
+      assert_eq!(s.parse(), Ok(codec));
+      assert_eq!(codec.to_string().parse(), Ok(codec));
+    }
+    for s in ["", "jpeg", "png:9", "webp:lossy", "avif:0", "avif:101"] {
+      assert!(s.parse::<Codec>().is_err(), "{s}");
+    }
+    assert_eq!(Codec::default().to_string(), "png:fast");
+  }
+
+  #[test]
+  fn encodes_images() {
+    let image = DynamicImage::ImageRgba8(ImageBuffer::from_fn(64, 48, |x, y| {
+      Rgba([
+        (x * 4) as u8,
+        (y * 5) as u8,
+        128,
+        if x < 8 { 0 } else { 255 },
+      ])
+    }));
+    for codec in ["png", "png:best", "webp"] {
+      let codec: Codec = codec.parse().unwrap();
+      let data = codec.encode(&image).unwrap();
+      let format = image::guess_format(&data).unwrap();
+      assert_eq!(format.to_mime_type(), codec.content_type());
+      // lossless codecs store the pixels as is
+      assert_eq!(image::load_from_memory(&data).unwrap(), image, "{codec}");
+    }
+
+    #[cfg(feature = "avif")]
+    {
+      let data = Codec::Avif(90).encode(&image).unwrap();
+      assert_eq!(&data[4..12], b"ftypavif");
+    }
+  }
+}
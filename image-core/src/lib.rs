@@ -0,0 +1,1017 @@
+//! The upload processing of `wk-image`, independent of where it runs: decode, convert to sRGB,
+//! orient, normalize, hash, encode and store.
+//!
+//! `wk-image` is an adapter for Cloudflare Workers around [`ImagePipeline::upload`]. Everything
+//! that computes what `wk-image` would store uses this crate, so the results are identical.
+
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::fmt;
+use std::io::{self, Cursor};
+use std::panic::{self, AssertUnwindSafe};
+
+use image::{io::Reader as ImageReader, DynamicImage, ImageError, ImageFormat};
+use image_hasher::{HashBytes, HashSequence, Hasher, HasherConfig, ImageHash};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+pub use image_hasher::{ParseConfigError, Rect};
+
+pub use crate::decode::Limits;
+pub use crate::encode::{Codec, Compression};
+pub use crate::orientation::Orientation;
+pub use crate::resize::{normalize, resize, MAX_SIZE};
+pub use crate::store::{BlobStore, MemoryStore, StoreError};
+
+mod color;
+mod decode;
+mod encode;
+mod orientation;
+mod resize;
+mod store;
+
+/// Hashes of up to 100 bits, stored inline.
+pub type Hash = image_hasher::hash_bits!(100);
+
+/// Used if `wk-image`'s `HASHER` variable isn't set; must match the hashes already stored in D1,
+/// which were computed without deterministic mode.
+pub const DEFAULT_HASHER: &str = "dct-gradient:10x10:lanczos3";
+
+/// Used if `wk-image`'s `MAX_IMAGE_*` variables aren't set, and by the other services unless
+/// configured otherwise, so they all store the same images.
+///
+/// Admits 12 megapixel phone photos (4032x3024) and 300 dpi A4 or Letter scans (about 8.7
+/// megapixels), with up to 30000 pixels on a side for long strips. Decoded as RGB such a photo
+/// takes 37 MB; an upload holds at most two copies of the pixels besides the file and the
+/// encoded image, which fits in the 128 MB of `wk-image`'s Durable Object.
+pub const DEFAULT_LIMITS: Limits = Limits {
+  max_dimension: 30_000,
+  max_pixels: 12_600_000,
+  max_alloc: 48 << 20,
+};
+
+/// Consecutive frames within this distance of the last keyframe are not keyframes.
+const KEYFRAME_THRESHOLD: u32 = 4;
+
+/// Which frames of an animation are decoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Frames {
+  /// Every frame, to hash the animation
+  #[default]
+  All,
+  /// Only the first frame, which is the one that is stored
+  First,
+}
+
+/// How an upload is processed.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+  /// MIME type of the upload, the format is guessed from the contents if this is empty
+  pub mime: Option<String>,
+  pub frames: Frames,
+  /// Codec to store the image in instead of the pipeline's
+  pub codec: Option<Codec>,
+  /// Codecs to also encode the image in, to report the sizes in [`Upload::sizes`]
+  pub compare: Vec<Codec>,
+}
+
+impl Options {
+  /// Options from the `mime`, `codec` and `compare` fields of an upload form, where `compare`
+  /// is a comma separated list of codecs. Empty fields are ignored.
+  pub fn from_form(
+    mime: Option<String>,
+    codec: Option<&str>,
+    compare: Option<&str>,
+  ) -> Result<Self, Error> {
+    let parse = |codec: &str| match codec.parse::<Codec>() {
+      Ok(codec) if codec.is_supported() => Ok(codec),
+      _ => Err(Error::InvalidCodec),
+    };
+    Ok(Self {
+      mime,
+      frames: Frames::default(),
+      codec: codec
+        .filter(|codec| !codec.trim().is_empty())
+        .map(parse)
+        .transpose()?,
+      compare: compare
+        .into_iter()
+        .flat_map(|compare| compare.split(','))
+        .filter(|codec| !codec.trim().is_empty())
+        .map(parse)
+        .collect::<Result<_, _>>()?,
+    })
+  }
+}
+
+/// The response of `wk-image` to an upload.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upload {
+  pub key: String,
+  pub width: u32,
+  pub height: u32,
+  pub size: usize,
+  /// Codec the image is stored in
+  #[serde(default)]
+  pub codec: Codec,
+  /// Encoded size of the image in the stored codec and in each compared one, if any
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub sizes: BTreeMap<Codec, usize>,
+  pub hash: String,
+  /// Config the hashes were computed with, see `HasherConfig`'s `FromStr` impl
+  pub hasher: String,
+  /// Part of the stored image that was hashed, if `HASHER` trims borders
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub crop: Option<Rect>,
+  /// EXIF orientation that was applied to the upload before anything else, if it wasn't
+  /// [`Orientation::Normal`]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub orientation: Option<Orientation>,
+  pub sha: String,
+  /// Number of frames in the uploaded file, only the first one is stored
+  pub frames: usize,
+  /// Aggregate hash of the keyframes of an animation
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sequence_hash: Option<String>,
+  /// Hashes of the keyframes of an animation
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub keyframe_hashes: Vec<String>,
+}
+
+/// A decoded and normalized upload, before it is encoded.
+pub struct Processed {
+  /// The pixels that are stored
+  pub image: DynamicImage,
+  /// sha256 of the pixels, in hex
+  pub sha: String,
+  pub hash: ImageHash<Hash>,
+  /// Part of the image that was hashed, if the hasher trims borders
+  pub crop: Option<Rect>,
+  /// Orientation applied to the decoded pixels, if it wasn't [`Orientation::Normal`]
+  pub orientation: Option<Orientation>,
+  /// Number of frames in the upload, `1` if only the first one was decoded
+  pub frames: usize,
+  /// The keyframes of an animation
+  pub keyframes: Option<HashSequence<Hash>>,
+  /// Codec the image is stored in
+  pub codec: Codec,
+}
+
+impl Processed {
+  /// Where the image is stored.
+  pub fn key(&self) -> String {
+    format!("upload/{}.{}", self.sha, self.codec.extension())
+  }
+
+  /// Encode the image as it is stored.
+  pub fn encode(&self) -> Result<Vec<u8>, Error> {
+    self.codec.encode(&self.image).map_err(Error::Encode)
+  }
+
+  /// The response for the upload, with the size of the encoded image.
+  pub fn to_upload(&self, hasher: &str, size: usize) -> Upload {
+    let keyframes = self.keyframes.as_ref();
+    Upload {
+      key: self.key(),
+      width: self.image.width(),
+      height: self.image.height(),
+      size,
+      codec: self.codec,
+      sizes: BTreeMap::new(),
+      hash: encode_hash(&self.hash),
+      hasher: hasher.to_owned(),
+      crop: self.crop,
+      orientation: self.orientation,
+      sha: self.sha.clone(),
+      frames: self.frames,
+      sequence_hash: keyframes
+        .and_then(HashSequence::aggregate)
+        .map(|hash| encode_hash(&hash)),
+      keyframe_hashes: keyframes.map_or_else(Vec::new, |keyframes| {
+        keyframes.frames().iter().map(encode_hash).collect()
+      }),
+    }
+  }
+}
+
+/// Hex encode a hash as in [`Upload`].
+pub fn encode_hash<B: HashBytes>(hash: &ImageHash<B>) -> String {
+  hex::encode(hash.as_bytes())
+}
+
+/// What was known about an upload when it was rejected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Details {
+  /// MIME type of the format the upload was decoded as
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub format: Option<&'static str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub width: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub height: Option<u32>,
+}
+
+impl Details {
+  fn size(size: (u32, u32)) -> Self {
+    let mut details = Self::default();
+    details.set_size(size);
+    details
+  }
+
+  fn set_size(&mut self, (width, height): (u32, u32)) {
+    self.width = Some(width);
+    self.height = Some(height);
+  }
+
+  /// Fill in what isn't known yet from `other`.
+  fn or(self, other: Details) -> Self {
+    Self {
+      format: self.format.or(other.format),
+      width: self.width.or(other.width),
+      height: self.height.or(other.height),
+    }
+  }
+}
+
+/// Why an upload could not be processed, with the messages `wk-image` responds with.
+///
+/// Serialized as the JSON body of error responses: `{"code", "message", "details"}`, where
+/// `code` is one of the stable [`Error::code`]s.
+#[derive(Debug)]
+pub enum Error {
+  /// The request isn't a valid `multipart/form-data` body
+  InvalidForm,
+  /// The form has no `file`
+  NoFile,
+  /// The request body is over the size limit of the server
+  FileTooLarge,
+  InvalidMime,
+  /// The `codec` or `compare` field isn't a supported [`Codec`]
+  InvalidCodec,
+  UnknownFormat,
+  TooManyFrames(Details),
+  /// The image is over the [`Limits`] of the pipeline
+  TooLarge(Details),
+  Image(ImageError, Details),
+  Encode(ImageError),
+  Store(StoreError),
+  /// Processing panicked, with the panic message
+  Panic(String),
+}
+
+impl Error {
+  /// The HTTP status `wk-image` responds with.
+  pub fn status(&self) -> u16 {
+    match self {
+      Error::FileTooLarge | Error::TooLarge(_) => 413,
+      Error::Encode(_) | Error::Store(_) | Error::Panic(_) => 500,
+      _ => 400,
+    }
+  }
+
+  /// A stable identifier of the kind of error, for clients to match on.
+  ///
+  /// Only files cut short are reported as `truncated-image`, depending on the format some of
+  /// them are reported as `invalid-image`.
+  pub fn code(&self) -> &'static str {
+    match self {
+      Error::InvalidForm => "invalid-form",
+      Error::NoFile => "no-file",
+      Error::FileTooLarge => "file-too-large",
+      Error::InvalidMime => "invalid-mime",
+      Error::InvalidCodec => "invalid-codec",
+      Error::UnknownFormat => "unknown-format",
+      Error::TooManyFrames(_) => "too-many-frames",
+      Error::TooLarge(_) => "image-too-large",
+      Error::Image(ImageError::Unsupported(_), _) => "unsupported-format",
+      Error::Image(ImageError::IoError(err), _) if err.kind() == io::ErrorKind::UnexpectedEof => {
+        "truncated-image"
+      }
+      Error::Image(..) => "invalid-image",
+      Error::Encode(_) => "encode-failed",
+      Error::Store(_) => "store-failed",
+      Error::Panic(_) => "internal",
+    }
+  }
+
+  /// What was known about the upload, if it got as far as decoding.
+  pub fn details(&self) -> Details {
+    match self {
+      Error::TooManyFrames(details) | Error::TooLarge(details) | Error::Image(_, details) => {
+        *details
+      }
+      _ => Details::default(),
+    }
+  }
+
+  /// Add what is known about the upload to errors of decoding it.
+  fn with_details(mut self, known: Details) -> Self {
+    if let Error::TooManyFrames(details) | Error::TooLarge(details) | Error::Image(_, details) =
+      &mut self
+    {
+      *details = details.or(known);
+    }
+    self
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Error::InvalidForm => "Invalid form data",
+      Error::NoFile => "No file found",
+      Error::FileTooLarge => "File too large",
+      Error::InvalidMime => "Invalid MIME type",
+      Error::InvalidCodec => "Invalid codec",
+      Error::UnknownFormat => "Could not guess image format",
+      Error::TooManyFrames(_) => "Too many frames",
+      Error::TooLarge(_) => "Image too large",
+      Error::Image(..) => match self.code() {
+        "unsupported-format" => "Unsupported image format",
+        "truncated-image" => "Truncated image",
+        _ => "Invalid image",
+      },
+      Error::Encode(_) => "Could not encode image",
+      Error::Store(_) => "Could not store image",
+      Error::Panic(_) => "Internal error",
+    })
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Image(err, _) | Error::Encode(err) => Some(err),
+      Error::Store(err) => Some(&**err),
+      _ => None,
+    }
+  }
+}
+
+impl Serialize for Error {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut body = serializer.serialize_struct("Error", 3)?;
+    body.serialize_field("code", self.code())?;
+    body.serialize_field("message", &self.to_string())?;
+    body.serialize_field("details", &self.details())?;
+    body.end()
+  }
+}
+
+impl From<ImageError> for Error {
+  fn from(err: ImageError) -> Self {
+    Self::Image(err, Details::default())
+  }
+}
+
+/// Turn a panic of `f` into [`Error::Panic`], where panics unwind.
+fn catch_panic<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+  panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+    let message = match payload.downcast::<String>() {
+      Ok(message) => *message,
+      Err(payload) => payload
+        .downcast_ref::<&str>()
+        .map_or_else(String::new, |message| (*message).to_owned()),
+    };
+    Err(Error::Panic(message))
+  })
+}
+
+/// Processes uploads with a hasher.
+pub struct ImagePipeline {
+  hasher: Hasher<Hash>,
+  spec: String,
+  limits: Limits,
+  codec: Codec,
+}
+
+impl ImagePipeline {
+  /// Create a pipeline hashing with the given config string, e.g. [`DEFAULT_HASHER`].
+  pub fn new(spec: &str) -> Result<Self, ParseConfigError> {
+    let config: HasherConfig<Hash> = spec.parse()?;
+    Ok(Self {
+      hasher: config.to_hasher(),
+      spec: config.to_string(),
+      limits: DEFAULT_LIMITS,
+      codec: Codec::default(),
+    })
+  }
+
+  /// Decode images within `limits` instead of [`DEFAULT_LIMITS`].
+  pub fn with_limits(mut self, limits: Limits) -> Self {
+    self.limits = limits;
+    self
+  }
+
+  pub fn limits(&self) -> &Limits {
+    &self.limits
+  }
+
+  /// Store images with `codec` unless an upload asks for another one.
+  pub fn with_codec(mut self, codec: Codec) -> Self {
+    self.codec = codec;
+    self
+  }
+
+  pub fn codec(&self) -> Codec {
+    self.codec
+  }
+
+  pub fn hasher(&self) -> &Hasher<Hash> {
+    &self.hasher
+  }
+
+  /// The canonical config string of the hasher, as in [`Upload::hasher`].
+  pub fn spec(&self) -> &str {
+    &self.spec
+  }
+
+  /// Decode, orient, normalize and hash an uploaded file.
+  pub fn process(&self, file: Vec<u8>, options: &Options) -> Result<Processed, Error> {
+    let orientation =
+      Orientation::read(&file).filter(|&orientation| orientation != Orientation::Normal);
+    let cursor = Cursor::new(file);
+    let reader = match options.mime.as_deref() {
+      Some(mime) if !mime.is_empty() => {
+        let format = ImageFormat::from_mime_type(mime).ok_or(Error::InvalidMime)?;
+        ImageReader::with_format(cursor, format)
+      }
+      _ => ImageReader::new(cursor)
+        .with_guessed_format()
+        .map_err(|_| Error::UnknownFormat)?,
+    };
+
+    let (image, sequence) = decode::decode(
+      reader,
+      &self.hasher,
+      options.frames,
+      &self.limits,
+      orientation.unwrap_or(Orientation::Normal),
+    )?;
+    let frames = sequence.as_ref().map_or(1, HashSequence::len);
+    let keyframes = sequence.map(|sequence| sequence.keyframes(KEYFRAME_THRESHOLD));
+
+    let image = normalize(image);
+    let sha = hex::encode(Sha256::digest(image.as_bytes()));
+    let (hash, crop) = self.hasher.hash_image_with_crop(&image);
+
+    Ok(Processed {
+      image,
+      sha,
+      hash,
+      crop,
+      orientation,
+      frames,
+      keyframes,
+      codec: options.codec.unwrap_or(self.codec),
+    })
+  }
+
+  /// Process and encode an uploaded file, returning the response and the encoded image without
+  /// storing it.
+  ///
+  /// Panics are returned as [`Error::Panic`], unless panics abort like in `wk-image`.
+  pub fn encode(&self, file: Vec<u8>, options: &Options) -> Result<(Upload, Vec<u8>), Error> {
+    catch_panic(|| {
+      let processed = self.process(file, options)?;
+      let data = processed.encode()?;
+      let mut upload = processed.to_upload(&self.spec, data.len());
+      if !options.compare.is_empty() {
+        upload.sizes.insert(processed.codec, data.len());
+        for &codec in &options.compare {
+          if let Entry::Vacant(entry) = upload.sizes.entry(codec) {
+            entry.insert(codec.encode(&processed.image).map_err(Error::Encode)?.len());
+          }
+        }
+      }
+      Ok((upload, data))
+    })
+  }
+
+  /// Process an uploaded file and store it in `store`.
+  pub async fn upload<S: BlobStore + ?Sized>(
+    &self,
+    store: &S,
+    file: Vec<u8>,
+    options: &Options,
+  ) -> Result<Upload, Error> {
+    let (upload, data) = self.encode(file, options)?;
+    store
+      .put(&upload.key, data, upload.codec.content_type())
+      .await
+      .map_err(Error::Store)?;
+    Ok(upload)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::fmt::Write;
+
+  use futures_executor::block_on;
+  use image::{codecs::webp::WebPEncoder, ImageBuffer, ImageEncoder, Rgb, Rgba};
+
+  use super::*;
+
+  const TESTDATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata");
+
+  fn encode(image: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+    let mut buf = vec![];
+    image.write_to(&mut Cursor::new(&mut buf), format).unwrap();
+    buf
+  }
+
+  fn gradient(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+      Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 128])
+    }))
+  }
+
+  /// Compare with a file in `testdata`, or rewrite it if `UPDATE_GOLDEN` is set.
+  fn golden(file: &str, actual: &str) {
+    let path = format!("{TESTDATA}/{file}");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+      std::fs::write(&path, actual).unwrap();
+    }
+    assert_eq!(actual, std::fs::read_to_string(&path).unwrap());
+  }
+
+  /// Hashes of `testdata/vector.png`, which other implementations of the hashing are checked
+  /// against. Run with `UPDATE_GOLDEN=1` to rewrite them after an intentional change.
+  #[test]
+  fn test_vectors() {
+    let image = image::open(format!("{TESTDATA}/vector.png")).unwrap();
+
+    let mut actual = String::from("# file hasher hash, as computed by `hash_image`\n");
+    for spec in [
+      DEFAULT_HASHER,
+      "dct-gradient:10x10:lanczos3:deterministic",
+      "dct-mean:8x8:deterministic:msb",
+      "double-gradient:8x8:trim(8):deterministic",
+      "blockhash:8x8:msb",
+    ] {
+      let hasher = spec.parse::<HasherConfig<Hash>>().unwrap().to_hasher();
+      let (hash, _) = hasher.hash_image_with_crop(&image);
+      writeln!(actual, "vector.png {spec} {}", encode_hash(&hash)).unwrap();
+    }
+    golden("vectors.txt", &actual);
+  }
+
+  /// Results of processing the uploads in `testdata`, except for the storage.
+  #[test]
+  fn test_uploads() {
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+
+    let mut actual = String::from("# file width height sha hash, with `DEFAULT_HASHER`\n");
+    for file in ["vector.png", "large.jpg", "alpha.png", "anim.gif"] {
+      let file_data = std::fs::read(format!("{TESTDATA}/{file}")).unwrap();
+      let upload = pipeline
+        .process(file_data, &Options::default())
+        .unwrap()
+        .to_upload(pipeline.spec(), 0);
+      let Upload {
+        width,
+        height,
+        sha,
+        hash,
+        ..
+      } = upload;
+      writeln!(actual, "{file} {width} {height} {sha} {hash}").unwrap();
+    }
+    golden("uploads.txt", &actual);
+  }
+
+  #[test]
+  fn matches_the_hasher() {
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+    let image = gradient(320, 200);
+    let (upload, data) = pipeline
+      .encode(encode(&image, ImageFormat::Png), &Options::default())
+      .unwrap();
+
+    assert_eq!(
+      upload.hash,
+      encode_hash(&pipeline.hasher().hash_image(&image))
+    );
+    assert_eq!(upload.sha, hex::encode(Sha256::digest(image.as_bytes())));
+    assert_eq!(upload.key, format!("upload/{}.png", upload.sha));
+    assert_eq!((upload.width, upload.height), (320, 200));
+    assert_eq!(upload.hasher, "dct-gradient:10x10");
+    assert_eq!(upload.frames, 1);
+    assert_eq!(upload.size, data.len());
+    assert!(upload.crop.is_none());
+    assert_eq!(image::load_from_memory(&data).unwrap(), image);
+
+    // the stored pixels don't depend on the uploaded format
+    let options = Options {
+      mime: Some("image/bmp".into()),
+      ..Options::default()
+    };
+    let (bmp, _) = pipeline
+      .encode(encode(&image, ImageFormat::Bmp), &options)
+      .unwrap();
+    assert_eq!((bmp.sha, bmp.hash), (upload.sha, upload.hash));
+  }
+
+  #[test]
+  fn hashes_animations() {
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+    let file = std::fs::read(format!("{TESTDATA}/anim.gif")).unwrap();
+
+    let all = pipeline
+      .process(file.clone(), &Options::default())
+      .unwrap()
+      .to_upload(pipeline.spec(), 0);
+    assert_eq!(all.frames, 3);
+    assert!(all.sequence_hash.is_some());
+    assert!(!all.keyframe_hashes.is_empty());
+
+    let options = Options {
+      frames: Frames::First,
+      ..Options::default()
+    };
+    let first = pipeline
+      .process(file, &options)
+      .unwrap()
+      .to_upload(pipeline.spec(), 0);
+    assert_eq!(first.frames, 1);
+    assert_eq!((first.sequence_hash, first.keyframe_hashes), (None, vec![]));
+    assert_eq!((first.sha, first.hash), (all.sha, all.hash));
+  }
+
+  #[test]
+  fn scales_down_large_images() {
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+    let image = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(9000, 30, Rgba([1, 2, 3, 255])));
+    let processed = pipeline
+      .process(encode(&image, ImageFormat::Png), &Options::default())
+      .unwrap();
+    assert_eq!(
+      (processed.image.width(), processed.image.height()),
+      (6000, 20)
+    );
+  }
+
+  /// Files in `testdata` with headers claiming sizes far over the limits, and barely any data.
+  #[test]
+  fn rejects_bombs() {
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+    for (file, format, size) in [
+      ("bomb.png", "image/png", 60000),
+      ("bomb.gif", "image/gif", 65535),
+      ("bomb.webp", "image/webp", 16383),
+      ("bomb.jpg", "image/jpeg", 65000),
+    ] {
+      let file_data = std::fs::read(format!("{TESTDATA}/{file}")).unwrap();
+      let err = pipeline
+        .process(file_data, &Options::default())
+        .err()
+        .unwrap();
+      assert!(matches!(err, Error::TooLarge(_)), "{file}: {err:?}");
+      assert_eq!(
+        err.details(),
+        Details {
+          format: Some(format),
+          width: Some(size),
+          height: Some(size),
+        }
+      );
+      assert_eq!(
+        (err.status(), err.to_string()),
+        (413, "Image too large".into())
+      );
+    }
+
+    // images decoded by `image` itself are limited too
+    let pipeline = pipeline.with_limits(Limits {
+      max_alloc: 1 << 20,
+      ..Limits::default()
+    });
+    let bmp = encode(&gradient(1000, 1000), ImageFormat::Bmp);
+    let err = pipeline.process(bmp, &Options::default()).err().unwrap();
+    assert!(matches!(err, Error::TooLarge(_)));
+    assert_eq!(err.details().width, Some(1000));
+  }
+
+  #[test]
+  fn scales_down_large_jpegs() {
+    let jpeg = encode(&gradient(2048, 1536), ImageFormat::Jpeg);
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+    let full = pipeline.process(jpeg.clone(), &Options::default()).unwrap();
+    assert_eq!((full.image.width(), full.image.height()), (2048, 1536));
+
+    let limits = Limits {
+      max_pixels: 1_000_000,
+      ..Limits::default()
+    };
+    let pipeline = pipeline.with_limits(limits);
+    let scaled = pipeline.process(jpeg.clone(), &Options::default()).unwrap();
+    assert_eq!((scaled.image.width(), scaled.image.height()), (1024, 768));
+
+    let limits = Limits {
+      max_dimension: 200,
+      ..limits
+    };
+    let pipeline = pipeline.with_limits(limits);
+    let err = pipeline.process(jpeg, &Options::default()).err().unwrap();
+    assert!(matches!(err, Error::TooLarge(_)));
+    assert_eq!(
+      (err.details().width, err.details().height),
+      (Some(2048), Some(1536))
+    );
+  }
+
+  #[test]
+  fn orients_uploads() {
+    use crate::orientation::test::{exif, jpeg_with_app1, webp_with_chunks, xmp};
+
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+    let process = |file: &[u8]| {
+      pipeline
+        .process(file.to_vec(), &Options::default())
+        .unwrap()
+    };
+    let check = |plain: &[u8], oriented: &[u8], orientation: Orientation| {
+      let expected = orientation.apply(image::load_from_memory(plain).unwrap());
+      let processed = process(oriented);
+      assert_eq!(processed.orientation, Some(orientation));
+      assert_eq!(processed.image.to_rgb8(), expected.to_rgb8());
+      assert_eq!(
+        processed.hash,
+        process(&encode(&expected, ImageFormat::Png)).hash
+      );
+      let upload = processed.to_upload(pipeline.spec(), 0);
+      assert_eq!(
+        serde_json::to_value(upload).unwrap()["orientation"],
+        orientation as u16
+      );
+    };
+
+    let image = gradient(64, 48);
+    let jpeg = encode(&image, ImageFormat::Jpeg);
+    let with_exif = jpeg_with_app1(&jpeg, b"Exif\0\0", &exif(6));
+    check(&jpeg, &with_exif, Orientation::Rotate90);
+    let with_xmp = jpeg_with_app1(&jpeg, b"http://ns.adobe.com/xap/1.0/\0", &xmp(5));
+    check(&jpeg, &with_xmp, Orientation::Transpose);
+    assert_eq!(process(&with_exif).image.width(), 48);
+
+    let mut webp = vec![];
+    WebPEncoder::new_lossless(&mut webp)
+      .write_image(image.as_bytes(), 64, 48, image.color())
+      .unwrap();
+    let with_exif = webp_with_chunks(&webp, (64, 48), &[(b"EXIF", &exif(3))]);
+    check(&webp, &with_exif, Orientation::Rotate180);
+
+    let tiff = |tag: u16, value: &[u8]| {
+      let mut tiff = vec![];
+      let mut encoder = tiff::encoder::TiffEncoder::new(Cursor::new(&mut tiff)).unwrap();
+      let mut frame = encoder
+        .new_image::<tiff::encoder::colortype::RGB8>(64, 48)
+        .unwrap();
+      if tag == 274 {
+        frame
+          .encoder()
+          .write_tag(tiff::tags::Tag::Orientation, u16::from(value[0]))
+          .unwrap();
+      } else {
+        frame
+          .encoder()
+          .write_tag(tiff::tags::Tag::Unknown(tag), value)
+          .unwrap();
+      }
+      frame.write_data(image.as_bytes()).unwrap();
+      tiff
+    };
+    let plain = encode(&image, ImageFormat::Tiff);
+    check(&plain, &tiff(274, &[8]), Orientation::Rotate270);
+    check(&plain, &tiff(700, &xmp(2)), Orientation::FlipHorizontal);
+
+    // normal orientations aren't reported
+    let normal = process(&jpeg_with_app1(&jpeg, b"Exif\0\0", &exif(1)));
+    assert_eq!(normal.orientation, None);
+    assert_eq!(normal.sha, process(&jpeg).sha);
+  }
+
+  #[test]
+  fn converts_colors() {
+    use crate::color::test::rgb_profile;
+    use crate::orientation::test::{jpeg_with_segment, webp_with_chunks};
+
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+    let process = |file: Vec<u8>| pipeline.process(file, &Options::default()).unwrap();
+    let with_icc =
+      |jpeg: &[u8], icc: &[u8]| jpeg_with_segment(jpeg, 0xe2, b"ICC_PROFILE\0\x01\x01", icc);
+    let is_green = |processed: &Processed| {
+      let [r, g, b] = processed.image.to_rgb8().get_pixel(8, 8).0;
+      r < 8 && g > 247 && b < 8
+    };
+
+    // red in a profile where it is green
+    let image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(16, 16, Rgb([255, 0, 0])));
+    let swapped = rgb_profile([1, 2, 0], false);
+    let jpeg = encode(&image, ImageFormat::Jpeg);
+    assert!(is_green(&process(with_icc(&jpeg, &swapped))));
+    assert!(!is_green(&process(jpeg.clone())));
+
+    let mut webp = vec![];
+    WebPEncoder::new_lossless(&mut webp)
+      .write_image(image.as_bytes(), 16, 16, image.color())
+      .unwrap();
+    let processed = process(webp_with_chunks(&webp, (16, 16), &[(b"ICCP", &swapped)]));
+    assert!(is_green(&processed));
+    let green = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(16, 16, Rgb([0, 255, 0])));
+    // lossless, so only the color conversion may be off, by at most 2 per channel
+    for px in processed.image.to_rgb8().pixels() {
+      let diff = px.0.iter().zip([0, 255, 0]).map(|(&c, e)| c.abs_diff(e));
+      assert!(diff.max() <= Some(2), "{px:?}");
+    }
+    assert_eq!(
+      processed.hash,
+      process(encode(&green, ImageFormat::Png)).hash
+    );
+
+    // sRGB profiles are left as is
+    let srgb = rgb_profile([0, 1, 2], true);
+    assert_eq!(
+      process(with_icc(&jpeg, &srgb)).sha,
+      process(jpeg.clone()).sha
+    );
+  }
+
+  #[test]
+  fn stores_uploads() {
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+    let store = MemoryStore::default();
+    let file = encode(&gradient(64, 48), ImageFormat::Png);
+
+    let upload = block_on(pipeline.upload(&store, file.clone(), &Options::default())).unwrap();
+    let (data, content_type) = store.get(&upload.key).unwrap();
+    assert_eq!(content_type, "image/png");
+    assert_eq!(upload.codec, Codec::Png(Compression::Fast));
+    assert_eq!(upload.size, data.len());
+    assert_eq!(
+      (upload.clone(), data),
+      pipeline.encode(file, &Options::default()).unwrap()
+    );
+
+    // nothing is stored for invalid uploads
+    assert!(block_on(pipeline.upload(&store, b"GIF89a".to_vec(), &Options::default())).is_err());
+    assert_eq!(store.len(), 1);
+  }
+
+  #[test]
+  fn stores_codecs() {
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER)
+      .unwrap()
+      .with_codec(Codec::WebP);
+    let store = MemoryStore::default();
+    let image = gradient(64, 48);
+    let file = encode(&image, ImageFormat::Png);
+
+    let upload = block_on(pipeline.upload(&store, file.clone(), &Options::default())).unwrap();
+    assert_eq!(upload.key, format!("upload/{}.webp", upload.sha));
+    let (data, content_type) = store.get(&upload.key).unwrap();
+    assert_eq!(content_type, "image/webp");
+    // lossless WebP is always decoded with alpha
+    assert_eq!(
+      image::load_from_memory(&data).unwrap().to_rgb8(),
+      image.to_rgb8()
+    );
+    assert!(upload.sizes.is_empty());
+
+    // uploads can choose another codec and compare sizes
+    let options =
+      Options::from_form(None, Some("png:best"), Some("webp, png:fast,png:best")).unwrap();
+    let (png, data) = pipeline.encode(file, &options).unwrap();
+    assert_eq!(
+      (png.codec, &*png.key),
+      (
+        Codec::Png(Compression::Best),
+        &*format!("upload/{}.png", upload.sha)
+      )
+    );
+    assert_eq!(png.sha, upload.sha);
+    assert_eq!(
+      png.sizes,
+      BTreeMap::from([
+        (
+          Codec::Png(Compression::Fast),
+          Codec::default().encode(&image).unwrap().len()
+        ),
+        (Codec::Png(Compression::Best), data.len()),
+        (Codec::WebP, upload.size),
+      ])
+    );
+    let json = serde_json::to_value(&png).unwrap();
+    assert_eq!(json["codec"], "png:best");
+    assert_eq!(json["sizes"]["webp"], upload.size);
+    assert_eq!(
+      serde_json::from_value::<Upload>(json).unwrap().sizes,
+      png.sizes
+    );
+
+    let invalid = |codec, compare| Options::from_form(None, codec, compare).err().unwrap();
+    assert!(matches!(invalid(Some("jpeg"), None), Error::InvalidCodec));
+    assert_eq!(invalid(None, Some("webp,png:9")).code(), "invalid-codec");
+    assert_eq!(invalid(None, Some("webp,png:9")).status(), 400);
+    let empty = Options::from_form(None, Some(""), Some(" ")).unwrap();
+    assert_eq!((empty.codec, empty.compare), (None, vec![]));
+  }
+
+  #[test]
+  fn errors() {
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER).unwrap();
+    let png = encode(&gradient(8, 8), ImageFormat::Png);
+
+    let err = |file: &[u8], mime: Option<&str>| {
+      let options = Options {
+        mime: mime.map(str::to_owned),
+        ..Options::default()
+      };
+      pipeline.process(file.to_vec(), &options).err().unwrap()
+    };
+    assert!(matches!(err(&png, Some("text/plain")), Error::InvalidMime));
+    assert!(matches!(err(b"not an image", None), Error::UnknownFormat));
+    assert!(matches!(err(&png, Some("image/jpeg")), Error::Image(..)));
+    assert_eq!(err(&png[..20], None).to_string(), "Invalid image");
+    assert_eq!(err(&png[..20], None).status(), 400);
+    assert_eq!(Error::Store("full".into()).status(), 500);
+
+    let codes = |file: &[u8], mime| {
+      let err = err(file, mime);
+      (err.code(), err.status(), err.details())
+    };
+    assert_eq!(
+      codes(b"not an image", None),
+      ("unknown-format", 400, Details::default())
+    );
+    assert_eq!(
+      codes(&png, Some("image/avif")),
+      (
+        "unsupported-format",
+        400,
+        Details {
+          format: Some("image/avif"),
+          ..Details::default()
+        }
+      )
+    );
+    let jpeg = encode(&gradient(64, 48), ImageFormat::Jpeg);
+    assert_eq!(
+      codes(&jpeg[..jpeg.len() / 2], None),
+      (
+        "truncated-image",
+        400,
+        Details {
+          format: Some("image/jpeg"),
+          width: Some(64),
+          height: Some(48),
+        }
+      )
+    );
+    // WebP images are only decoded if their size can be read from the header
+    let webp = b"RIFF\x1a\0\0\0WEBPVP8 \x0a\0\0\0\0\0\0\0\0\0\0\0\0\0";
+    assert_eq!(
+      codes(webp, None),
+      (
+        "invalid-image",
+        400,
+        Details {
+          format: Some("image/webp"),
+          ..Details::default()
+        }
+      )
+    );
+    assert_eq!(Error::Store("full".into()).code(), "store-failed");
+
+    assert!(ImagePipeline::new("gradient:8").is_err());
+  }
+
+  #[test]
+  fn serializes_errors() {
+    let err = Error::TooLarge(Details {
+      format: Some("image/png"),
+      width: Some(60000),
+      height: Some(60000),
+    });
+    assert_eq!(
+      serde_json::to_string(&err).unwrap(),
+      r#"{"code":"image-too-large","message":"Image too large","details":{"format":"image/png","width":60000,"height":60000}}"#
+    );
+    assert_eq!(
+      serde_json::to_string(&Error::NoFile).unwrap(),
+      r#"{"code":"no-file","message":"No file found","details":{}}"#
+    );
+  }
+
+  #[test]
+  fn catches_panics() {
+    let err = catch_panic::<()>(|| panic!("at {}", 1)).err().unwrap();
+    assert!(matches!(&err, Error::Panic(message) if message == "at 1"));
+    assert_eq!((err.code(), err.status()), ("internal", 500));
+    assert!(matches!(
+      catch_panic::<()>(|| panic!("static")),
+      Err(Error::Panic(message)) if message == "static"
+    ));
+    assert!(matches!(catch_panic(|| Ok(1)), Ok(1)));
+  }
+}
@@ -0,0 +1,283 @@
+//! The orientation uploads are displayed in, from their EXIF or XMP metadata.
+
+use std::io::Cursor;
+
+use exif::{Context, In, Reader, Tag, Value};
+use image::{DynamicImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::decode::jpeg_segments;
+
+/// How the pixels of an image are transformed to display it, as the values of the EXIF
+/// `Orientation` tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum Orientation {
+  Normal = 1,
+  FlipHorizontal = 2,
+  Rotate180 = 3,
+  FlipVertical = 4,
+  /// Flipped along the diagonal from the top left
+  Transpose = 5,
+  /// Rotated 90° clockwise
+  Rotate90 = 6,
+  /// Flipped along the diagonal from the top right
+  Transverse = 7,
+  Rotate270 = 8,
+}
+
+impl Orientation {
+  /// Read the orientation of a JPEG, PNG, WebP, TIFF or HEIF file from its EXIF metadata,
+  /// falling back on its XMP metadata except for HEIF.
+  pub fn read(file: &[u8]) -> Option<Self> {
+    let exif = Reader::new()
+      .read_from_container(&mut Cursor::new(file))
+      .ok();
+    let from_exif = exif.as_ref().and_then(|exif| {
+      let field = exif.get_field(Tag::Orientation, In::PRIMARY)?;
+      Self::try_from(u16::try_from(field.value.get_uint(0)?).ok()?).ok()
+    });
+    from_exif.or_else(|| {
+      // the XMP of TIFF files is in a tag of the first IFD
+      let tiff_xmp = exif
+        .as_ref()
+        .and_then(|exif| exif.get_field(Tag(Context::Tiff, 700), In::PRIMARY))
+        .and_then(|field| match &field.value {
+          Value::Byte(xmp) | Value::Undefined(xmp, _) => Some(&xmp[..]),
+          _ => None,
+        });
+      xmp_orientation(tiff_xmp.or_else(|| find_xmp(file))?)
+    })
+  }
+
+  /// Transform an image to display it.
+  pub fn apply(self, image: DynamicImage) -> DynamicImage {
+    match self {
+      Orientation::Normal => image,
+      Orientation::FlipHorizontal => image.fliph(),
+      Orientation::Rotate180 => image.rotate180(),
+      Orientation::FlipVertical => image.flipv(),
+      Orientation::Transpose => image.rotate90().fliph(),
+      Orientation::Rotate90 => image.rotate90(),
+      Orientation::Transverse => image.rotate270().fliph(),
+      Orientation::Rotate270 => image.rotate270(),
+    }
+  }
+
+  /// Transform a frame of an animation to display it.
+  pub(crate) fn apply_rgba(self, frame: RgbaImage) -> RgbaImage {
+    match self {
+      Orientation::Normal => frame,
+      _ => self.apply(DynamicImage::ImageRgba8(frame)).into_rgba8(),
+    }
+  }
+}
+
+impl TryFrom<u16> for Orientation {
+  type Error = String;
+
+  fn try_from(value: u16) -> Result<Self, Self::Error> {
+    Ok(match value {
+      1 => Orientation::Normal,
+      2 => Orientation::FlipHorizontal,
+      3 => Orientation::Rotate180,
+      4 => Orientation::FlipVertical,
+      5 => Orientation::Transpose,
+      6 => Orientation::Rotate90,
+      7 => Orientation::Transverse,
+      8 => Orientation::Rotate270,
+      _ => return Err(format!("invalid orientation {value}")),
+    })
+  }
+}
+
+impl From<Orientation> for u16 {
+  fn from(orientation: Orientation) -> Self {
+    orientation as u16
+  }
+}
+
+/// The XMP packet of a JPEG or WebP file.
+fn find_xmp(file: &[u8]) -> Option<&[u8]> {
+  const JPEG_XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+  if file.starts_with(&[0xff, 0xd8]) {
+    jpeg_segments(file).find_map(|(marker, segment)| {
+      (marker == 0xe1 && segment.starts_with(JPEG_XMP)).then(|| &segment[JPEG_XMP.len()..])
+    })
+  } else if file.get(..4)? == b"RIFF" && file.get(8..12)? == b"WEBP" {
+    let mut pos = 12;
+    while let Some(header) = file.get(pos..pos + 8) {
+      let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+      let chunk = file.get(pos + 8..pos + 8 + len)?;
+      if &header[..4] == b"XMP " {
+        return Some(chunk);
+      }
+      // chunks are padded to an even size
+      pos += 8 + len + len % 2;
+    }
+    None
+  } else {
+    None
+  }
+}
+
+/// The `tiff:Orientation` property of an XMP packet, as an attribute or an element.
+fn xmp_orientation(xmp: &[u8]) -> Option<Orientation> {
+  const PROPERTY: &[u8] = b"tiff:Orientation";
+
+  let start = xmp
+    .windows(PROPERTY.len())
+    .position(|window| window == PROPERTY)?
+    + PROPERTY.len();
+  let value = xmp[start..]
+    .iter()
+    .skip_while(|&&b| matches!(b, b'=' | b'"' | b'\'' | b'>') || b.is_ascii_whitespace())
+    .take_while(|b| b.is_ascii_digit())
+    .fold(0u16, |acc, &b| {
+      acc.saturating_mul(10).saturating_add(u16::from(b - b'0'))
+    });
+  Orientation::try_from(value).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+  use image::{ImageBuffer, Rgba};
+
+  use super::*;
+
+  /// EXIF metadata with only an orientation, in little endian TIFF.
+  pub fn exif(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+    tiff.extend_from_slice(&orientation.to_le_bytes());
+    tiff.extend_from_slice(&[0; 6]);
+    tiff
+  }
+
+  /// An XMP packet with an orientation.
+  pub fn xmp(orientation: u16) -> Vec<u8> {
+    format!(
+      r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?><x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:tiff="http://ns.adobe.com/tiff/1.0/" tiff:Orientation="{orientation}"/></rdf:RDF></x:xmpmeta><?xpacket end="w"?>"#
+    )
+    .into_bytes()
+  }
+
+  /// Insert an APP1 segment after the start of a JPEG file.
+  pub fn jpeg_with_app1(jpeg: &[u8], prefix: &[u8], data: &[u8]) -> Vec<u8> {
+    jpeg_with_segment(jpeg, 0xe1, prefix, data)
+  }
+
+  /// Insert a segment after the start of a JPEG file.
+  pub fn jpeg_with_segment(jpeg: &[u8], marker: u8, prefix: &[u8], data: &[u8]) -> Vec<u8> {
+    let len = (2 + prefix.len() + data.len()) as u16;
+    let mut file = jpeg[..2].to_vec();
+    file.extend_from_slice(&[0xff, marker]);
+    file.extend_from_slice(&len.to_be_bytes());
+    file.extend_from_slice(prefix);
+    file.extend_from_slice(data);
+    file.extend_from_slice(&jpeg[2..]);
+    file
+  }
+
+  /// Turn a simple WebP file into an extended one with metadata chunks.
+  pub fn webp_with_chunks(webp: &[u8], size: (u32, u32), chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut flags = 0;
+    let mut body = vec![];
+    for (name, data) in chunks {
+      flags |= match *name {
+        b"ICCP" => 0x20,
+        b"EXIF" => 0x08,
+        b"XMP " => 0x04,
+        _ => 0,
+      };
+      body.extend_from_slice(*name);
+      body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+      body.extend_from_slice(data);
+      if data.len() % 2 == 1 {
+        body.push(0);
+      }
+    }
+
+    let mut file = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0".to_vec();
+    file.extend_from_slice(&[flags, 0, 0, 0]);
+    file.extend_from_slice(&(size.0 - 1).to_le_bytes()[..3]);
+    file.extend_from_slice(&(size.1 - 1).to_le_bytes()[..3]);
+    // the image chunk of the simple file
+    file.extend_from_slice(&webp[12..]);
+    file.extend_from_slice(&body);
+    let len = (file.len() - 8) as u32;
+    file[4..8].copy_from_slice(&len.to_le_bytes());
+    file
+  }
+
+  #[test]
+  fn reads_orientations() {
+    for orientation in 1..=8 {
+      let expected = Orientation::try_from(orientation).ok();
+      let jpeg = jpeg_with_app1(b"\xff\xd8\xff\xd9", b"Exif\0\0", &exif(orientation));
+      assert_eq!(Orientation::read(&jpeg), expected);
+      let jpeg = jpeg_with_app1(
+        b"\xff\xd8\xff\xd9",
+        b"http://ns.adobe.com/xap/1.0/\0",
+        &xmp(orientation),
+      );
+      assert_eq!(Orientation::read(&jpeg), expected);
+      assert_eq!(Orientation::read(&exif(orientation)), expected);
+    }
+
+    let webp = b"RIFF\x0c\0\0\0WEBPVP8L\0\0\0\0";
+    let with_exif = webp_with_chunks(webp, (3, 2), &[(b"EXIF", &exif(6))]);
+    assert_eq!(Orientation::read(&with_exif), Some(Orientation::Rotate90));
+    let with_xmp = webp_with_chunks(webp, (3, 2), &[(b"XMP ", &xmp(3))]);
+    assert_eq!(Orientation::read(&with_xmp), Some(Orientation::Rotate180));
+    // EXIF has priority
+    let with_both = webp_with_chunks(webp, (3, 2), &[(b"EXIF", &exif(8)), (b"XMP ", &xmp(3))]);
+    assert_eq!(Orientation::read(&with_both), Some(Orientation::Rotate270));
+
+    assert_eq!(
+      xmp_orientation(b"<tiff:Orientation>7</tiff:Orientation>"),
+      Some(Orientation::Transverse)
+    );
+    assert_eq!(Orientation::read(&exif(9)), None);
+    assert_eq!(Orientation::read(b"GIF89a"), None);
+    assert_eq!(Orientation::read(b"\xff\xd8\xff\xe1\xff\xff"), None);
+  }
+
+  #[test]
+  fn applies_orientations() {
+    // the pixels are their position in the displayed image
+    let displayed = ImageBuffer::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+    let stored = |orientation: Orientation, width, height| {
+      ImageBuffer::from_fn(width, height, |x, y| {
+        let (dx, dy) = match orientation {
+          Orientation::Normal => (x, y),
+          Orientation::FlipHorizontal => (2 - x, y),
+          Orientation::Rotate180 => (2 - x, 1 - y),
+          Orientation::FlipVertical => (x, 1 - y),
+          Orientation::Transpose => (y, x),
+          Orientation::Rotate90 => (2 - y, x),
+          Orientation::Transverse => (2 - y, 1 - x),
+          Orientation::Rotate270 => (y, 1 - x),
+        };
+        Rgba([dx as u8, dy as u8, 0, 255])
+      })
+    };
+
+    for orientation in (1..=8).map(|value| Orientation::try_from(value).unwrap()) {
+      let (width, height) = match orientation as u16 {
+        1..=4 => (3, 2),
+        _ => (2, 3),
+      };
+      let image = DynamicImage::ImageRgba8(stored(orientation, width, height));
+      assert_eq!(
+        orientation.apply(image).into_rgba8(),
+        displayed,
+        "{orientation:?}"
+      );
+      assert_eq!(
+        orientation.apply_rgba(stored(orientation, width, height)),
+        displayed
+      );
+    }
+  }
+}
@@ -0,0 +1,103 @@
+use std::num::NonZeroU32;
+
+use fast_image_resize as fr;
+use image::DynamicImage;
+
+/// Images are scaled down to fit in a square of this size.
+pub const MAX_SIZE: u32 = 6000;
+
+/// Convert an image to a pixel format that is stored as is, and scale it to less than
+/// 6000x6000.
+pub fn normalize(image: DynamicImage) -> DynamicImage {
+  let image = match image {
+    DynamicImage::ImageRgb8(_) => image,
+    DynamicImage::ImageRgba8(_) => image,
+    DynamicImage::ImageLuma8(_) => image,
+    DynamicImage::ImageLumaA8(_) => image,
+    _ => DynamicImage::ImageRgba8(image.to_rgba8()),
+  };
+
+  let (width, height) = (image.width(), image.height());
+  if width <= MAX_SIZE && height <= MAX_SIZE {
+    return image;
+  }
+
+  let widthf: f64 = width as f64;
+  let heightf: f64 = height as f64;
+  let max = MAX_SIZE as f64;
+
+  let (nwidth, nheight) = if widthf > heightf {
+    (MAX_SIZE, (max / widthf * heightf).round() as u32)
+  } else {
+    ((max / heightf * widthf).round() as u32, MAX_SIZE)
+  };
+
+  resize(image, nwidth, nheight)
+}
+
+/// Resize an image with Lanczos3, taking alpha into account.
+///
+/// # Panics
+///
+/// If the image isn't in one of the pixel formats [`normalize`] returns, or a size is zero.
+pub fn resize(image: DynamicImage, width: u32, height: u32) -> DynamicImage {
+  let pixel_type = match image {
+    DynamicImage::ImageRgb8(_) => fr::PixelType::U8x3,
+    DynamicImage::ImageRgba8(_) => fr::PixelType::U8x4,
+    DynamicImage::ImageLuma8(_) => fr::PixelType::U8,
+    DynamicImage::ImageLumaA8(_) => fr::PixelType::U8x2,
+    _ => unreachable!(),
+  };
+
+  let mut src_img = fr::Image::from_vec_u8(
+    NonZeroU32::new(image.width()).unwrap(),
+    NonZeroU32::new(image.height()).unwrap(),
+    image.into_bytes(),
+    pixel_type,
+  )
+  .unwrap();
+
+  // multiple RGB channels of source image by alpha channel
+  let alpha_mul_div = fr::MulDiv::default();
+  if pixel_type == fr::PixelType::U8x4 || pixel_type == fr::PixelType::U8x2 {
+    alpha_mul_div
+      .multiply_alpha_inplace(&mut src_img.view_mut())
+      .unwrap();
+  }
+
+  let mut dst_img = fr::Image::new(
+    NonZeroU32::new(width).unwrap(),
+    NonZeroU32::new(height).unwrap(),
+    src_img.pixel_type(),
+  );
+
+  let mut dst_view = dst_img.view_mut();
+
+  let mut resizer = fr::Resizer::new(fr::ResizeAlg::Convolution(fr::FilterType::Lanczos3));
+  resizer.resize(&src_img.view(), &mut dst_view).unwrap();
+
+  if pixel_type == fr::PixelType::U8x4 || pixel_type == fr::PixelType::U8x2 {
+    alpha_mul_div.divide_alpha_inplace(&mut dst_view).unwrap();
+  }
+
+  let (width, height, buf) = (
+    dst_img.width().get(),
+    dst_img.height().get(),
+    dst_img.into_vec(),
+  );
+  match pixel_type {
+    fr::PixelType::U8x3 => {
+      DynamicImage::ImageRgb8(image::RgbImage::from_vec(width, height, buf).unwrap())
+    }
+    fr::PixelType::U8x4 => {
+      DynamicImage::ImageRgba8(image::RgbaImage::from_vec(width, height, buf).unwrap())
+    }
+    fr::PixelType::U8 => {
+      DynamicImage::ImageLuma8(image::GrayImage::from_vec(width, height, buf).unwrap())
+    }
+    fr::PixelType::U8x2 => {
+      DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_vec(width, height, buf).unwrap())
+    }
+    _ => unreachable!(),
+  }
+}
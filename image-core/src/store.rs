@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+/// Why a [`BlobStore`] failed, e.g. an I/O or HTTP error.
+pub type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Where processed images are stored, e.g. an R2 bucket in `wk-image`.
+///
+/// Futures don't have to be `Send`, as the ones of JS APIs aren't.
+#[async_trait(?Send)]
+pub trait BlobStore {
+  /// Store `data` under `key`, replacing anything stored there before.
+  async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), StoreError>;
+}
+
+/// Keeps blobs in memory, for tests.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+  blobs: Mutex<HashMap<String, (Vec<u8>, String)>>,
+}
+
+impl MemoryStore {
+  /// The data and content type stored under `key`.
+  pub fn get(&self, key: &str) -> Option<(Vec<u8>, String)> {
+    self.blobs.lock().unwrap().get(key).cloned()
+  }
+
+  /// Number of stored blobs.
+  pub fn len(&self) -> usize {
+    self.blobs.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+#[async_trait(?Send)]
+impl BlobStore for MemoryStore {
+  async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), StoreError> {
+    self
+      .blobs
+      .lock()
+      .unwrap()
+      .insert(key.to_owned(), (data, content_type.to_owned()));
+    Ok(())
+  }
+}
@@ -12,10 +12,101 @@ use crate::{HashBytes, Image};
 
 const FLOAT_EQ_MARGIN: f32 = 0.001;
 
+/// Blockhash as computed by the reference implementation.
 pub fn blockhash<I: Image, B: HashBytes>(img: &I, width: u32, height: u32) -> B {
   assert_eq!(width % 4, 0, "width must be multiple of 4");
   assert_eq!(height % 4, 0, "height must be multiple of 4");
 
+  let (iwidth, iheight) = img.dimensions();
+  let mut blocks = vec![0f64; (width * height) as usize];
+
+  // Block dimensions, in pixels
+  let (block_width, block_height) = (
+    f64::from(iwidth) / f64::from(width),
+    f64::from(iheight) / f64::from(height),
+  );
+  let (even_x, even_y) = (iwidth % width == 0, iheight % height == 0);
+
+  img.foreach_pixel8(|x, y, px| {
+    let value = f64::from(total_value(px));
+
+    let (top, bottom, weight_top) = block_weights(y, iheight, block_height, even_y);
+    let (left, right, weight_left) = block_weights(x, iwidth, block_width, even_x);
+    let (weight_bottom, weight_right) = (1. - weight_top, 1. - weight_left);
+
+    let mut add_to_block = block_adder(&mut blocks, width);
+    add_to_block(left, top, value * weight_top * weight_left);
+    add_to_block(right, top, value * weight_top * weight_right);
+    add_to_block(left, bottom, value * weight_bottom * weight_left);
+    add_to_block(right, bottom, value * weight_bottom * weight_right);
+  });
+
+  let half_block_value = block_width * block_height * 256. * 3. / 2.;
+
+  // compare against the medians of four horizontal bands
+  let band_len = blocks.len() / 4;
+  BitSet::from_bools(blocks.chunks(band_len).flat_map(|band| {
+    let median = median_f64(band);
+    // with images dominated by black or white many blocks may equal the median; to avoid
+    // hashes of all zeros or ones, output 0 if the median is in the lower half, 1 otherwise
+    band.iter().map(move |&block| {
+      block > median || ((block - median).abs() < 1. && median > half_block_value)
+    })
+  }))
+}
+
+/// The two blocks along one axis that the pixel at `pos` is split between, and the weight of
+/// the first one.
+fn block_weights(pos: u32, len: u32, block_len: f64, even: bool) -> (u32, u32, f64) {
+  let pos_f = f64::from(pos);
+  let first = (pos_f / block_len).floor() as u32;
+  if even {
+    return (first, first, 1.);
+  }
+
+  let next = (pos_f + 1.) % block_len;
+  // the integer part is 0 on the borders of blocks and the image
+  let second = if next.trunc() > 0. || pos + 1 == len {
+    first
+  } else {
+    (pos_f / block_len).ceil() as u32
+  };
+
+  (first, second, 1. - next.fract())
+}
+
+/// The value of a pixel as the sum of its RGB channels; fully transparent pixels are white.
+#[inline(always)]
+fn total_value(chans: &[u8]) -> u32 {
+  match chans.len() {
+    4 if chans[3] == 0 => 255 * 3,
+    3 | 4 => chans[..3].iter().map(|&x| x as u32).sum(),
+    2 if chans[1] == 0 => 255 * 3,
+    // gray images are converted to RGB by the reference implementation
+    1 | 2 => chans[0] as u32 * 3,
+    channels => panic!("Unsupported channel count in image: {channels}"),
+  }
+}
+
+fn median_f64(data: &[f64]) -> f64 {
+  let mut sorted = data.to_owned();
+  sorted.sort_by(|l, r| l.partial_cmp(r).unwrap_or(Ordering::Less));
+  let mid = sorted.len() / 2;
+  if sorted.len() % 2 == 0 {
+    (sorted[mid - 1] + sorted[mid]) / 2.
+  } else {
+    sorted[mid]
+  }
+}
+
+/// Blockhash as computed before it was made conformant with the reference implementation.
+///
+/// The fractional block weights are wrong for image sizes that aren't divisible by the hash
+/// size, the medians are taken over bands of 4 rows and never interpolated.
+pub fn legacy_blockhash<I: Image, B: HashBytes>(img: &I, width: u32, height: u32) -> B {
+  assert_eq!(width % 4, 0, "width must be multiple of 4");
+  assert_eq!(height % 4, 0, "height must be multiple of 4");
+
   let (iwidth, iheight) = img.dimensions();
 
   // Skip the floating point math if it's unnecessary
@@ -43,6 +134,7 @@ macro_rules! gen_hash {
       / (2u32 as $valty);
 
     let medians: Vec<$valty> = $blocks.chunks(group_len).map(get_median).collect();
+    let eq_fn = $eq_fn;
 
     BitSet::from_bools(
       $blocks
@@ -51,7 +143,7 @@ macro_rules! gen_hash {
         .flat_map(|(blocks, median)| {
           blocks
             .iter()
-            .map(move |&block| block > median || ($eq_fn(block, median) && median > cmp_factor))
+            .map(move |&block| block > median || (eq_fn(block, median) && median > cmp_factor))
         }),
     )
   }};
@@ -85,6 +177,7 @@ fn blockhash_slow<I: Image, B: HashBytes>(img: &I, hwidth: u32, hheight: u32) ->
     let block_x = x / block_width;
     let block_y = y / block_height;
 
+    // NOTE: parses as `x + (1. % block_width)`, kept so legacy hashes don't change
     let x_mod = x + 1. % block_width;
     let y_mod = y + 1. % block_height;
 
@@ -252,3 +345,122 @@ fn median_of_3<T: PartialOrd>(mut x: T, mut y: T, mut z: T) -> T {
 
   y
 }
+
+#[cfg(all(test, feature = "formats"))]
+mod test {
+  use crate::{BitOrder, HashAlg, HasherConfig};
+
+  /// Check against hashes computed by a port of the reference implementation, see
+  /// `testdata/blockhash/generate.py`. This only guards against regressions, the port is checked
+  /// against upstream by [`upstream_hashes`].
+  #[test]
+  fn reference_hashes() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/blockhash");
+    let expected = std::fs::read_to_string(format!("{dir}/hashes.txt")).unwrap();
+
+    let mut checked = 0;
+    for line in expected.lines() {
+      let [file, bits, hash]: [&str; 3] = line.split(' ').collect::<Vec<_>>().try_into().unwrap();
+      let bits: u32 = bits.parse().unwrap();
+
+      let image = image::open(format!("{dir}/{file}")).unwrap();
+      let hasher = HasherConfig::new()
+        .hash_alg(HashAlg::Blockhash)
+        .hash_size(bits, bits)
+        .bit_order(BitOrder::MsbFirst)
+        .to_hasher();
+      let actual: String = hasher
+        .hash_image(&image)
+        .as_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+
+      assert_eq!(actual, hash, "{file} at {bits} bits");
+      checked += 1;
+    }
+    assert!(checked > 0);
+  }
+
+  #[test]
+  fn by_definition() {
+    // 4x4 blocks of 4x4 pixels; a band is a row of blocks, and each block is set if it is above
+    // the median of its band, or equal to it while the median is over half the maximum
+    let grays: [[Option<u8>; 4]; 4] = [
+      [Some(0), Some(255), Some(0), Some(255)],
+      [Some(10), Some(20), Some(30), Some(40)],
+      [Some(200); 4],
+      [None, None, Some(50), Some(50)],
+    ];
+    let image = image::RgbaImage::from_fn(16, 16, |x, y| {
+      match grays[y as usize / 4][x as usize / 4] {
+        Some(gray) => [gray, gray, gray, 255].into(),
+        // transparent pixels count as white
+        None => [0, 0, 0, 0].into(),
+      }
+    });
+    let hash = HasherConfig::new()
+      .hash_alg(HashAlg::Blockhash)
+      .hash_size(4, 4)
+      .bit_order(BitOrder::MsbFirst)
+      .to_hasher()
+      .hash_image(&image);
+    assert_eq!(hash.as_bytes(), [0b0101_0011, 0b1111_1100]);
+  }
+
+  /// Check against the output of the reference `blockhash` tool, lines of `HASH  FILE` with
+  /// square hashes and files relative to the directory in `BLOCKHASH_UPSTREAM`, e.g. the
+  /// `testdata` of https://github.com/commonsmachinery/blockhash with its expected hashes:
+  ///
+  /// ```sh
+  /// BLOCKHASH_UPSTREAM=path/to/blockhash/testdata cargo test -p image_hasher -- --ignored
+  /// ```
+  #[test]
+  #[ignore = "needs the upstream test data in `BLOCKHASH_UPSTREAM`"]
+  fn upstream_hashes() {
+    let dir = std::env::var("BLOCKHASH_UPSTREAM").expect("`BLOCKHASH_UPSTREAM` isn't set");
+
+    let mut checked = 0;
+    for entry in std::fs::read_dir(&dir).unwrap() {
+      let path = entry.unwrap().path();
+      if path.extension().map_or(true, |ext| ext != "txt") {
+        continue;
+      }
+      for line in std::fs::read_to_string(&path).unwrap().lines() {
+        let Some((hash, file)) = line.split_once(char::is_whitespace) else {
+          continue;
+        };
+        let bits = ((hash.len() * 4) as f64).sqrt() as u32;
+        assert_eq!((bits * bits) as usize, hash.len() * 4, "{line}");
+
+        let image = image::open(format!("{dir}/{}", file.trim())).unwrap();
+        let hasher = HasherConfig::new()
+          .hash_alg(HashAlg::Blockhash)
+          .hash_size(bits, bits)
+          .bit_order(BitOrder::MsbFirst)
+          .to_hasher();
+        let actual: String = hasher
+          .hash_image(&image)
+          .as_bytes()
+          .iter()
+          .map(|b| format!("{b:02x}"))
+          .collect();
+        assert_eq!(actual, hash, "{}: {line}", path.display());
+        checked += 1;
+      }
+    }
+    assert!(checked > 0, "no hashes in {dir}");
+  }
+
+  #[test]
+  fn legacy_differs() {
+    // the legacy weights are only wrong for sizes that aren't divisible by the hash size
+    let image = image::GrayImage::from_fn(67, 45, |x, y| [((x * 7 + y * y) % 256) as u8].into());
+    let config = |alg| HasherConfig::new().hash_alg(alg).hash_size(16, 16);
+    let hash = config(HashAlg::Blockhash).to_hasher().hash_image(&image);
+    let legacy = config(HashAlg::LegacyBlockhash)
+      .to_hasher()
+      .hash_image(&image);
+    assert_ne!(hash, legacy);
+  }
+}
@@ -72,15 +72,26 @@ pub enum HashAlg {
   ///
   /// The algorithm is described in a high level here:
   /// https://github.com/commonsmachinery/blockhash-rfc/blob/master/main.md
+  ///
+  /// Hashes are meant to match the reference implementations when the hash size is
+  /// `bits x bits` and [`BitOrder::MsbFirst`](crate::BitOrder::MsbFirst) is used; the ignored
+  /// `upstream_hashes` test checks this against the reference test data.
   Blockhash,
+
+  /// The Blockhash algorithm as implemented by previous versions of this crate.
+  ///
+  /// Weighs the pixels of images whose size isn't divisible by the hash size incorrectly and
+  /// differs from the reference in other details, only use this to compare against stored
+  /// hashes.
+  LegacyBlockhash,
 }
 
-fn next_multiple_of_2(x: u32) -> u32 {
-  (x + 1) & !1
+fn next_multiple_of_2(x: u32) -> Option<u32> {
+  Some(x.checked_add(1)? & !1)
 }
 
-fn next_multiple_of_4(x: u32) -> u32 {
-  (x + 3) & !3
+fn next_multiple_of_4(x: u32) -> Option<u32> {
+  Some(x.checked_add(3)? & !3)
 }
 
 impl HashAlg {
@@ -97,6 +108,15 @@ impl HashAlg {
       return match post_gauss {
         Borrowed(img) => blockhash::blockhash(img, width, height),
         Owned(img) => blockhash::blockhash(&img, width, height),
+        Gray(img) => blockhash::blockhash(&img, width, height),
+      };
+    }
+
+    if *self == LegacyBlockhash {
+      return match post_gauss {
+        Borrowed(img) => blockhash::legacy_blockhash(img, width, height),
+        Owned(img) => blockhash::legacy_blockhash(&img, width, height),
+        Gray(img) => blockhash::legacy_blockhash(&img, width, height),
       };
     }
 
@@ -118,22 +138,25 @@ impl HashAlg {
         B::from_bools(double_gradient_hash(floats, rowstride))
       }
       (DoubleGradient, Bytes(ref bytes)) => B::from_bools(double_gradient_hash(bytes, rowstride)),
-      (Blockhash, _) => unreachable!(),
+      (Blockhash | LegacyBlockhash, _) => unreachable!(),
     }
   }
 
-  pub(crate) fn round_hash_size(&self, width: u32, height: u32) -> (u32, u32) {
+  /// The hash size the algorithm uses, `None` if rounding it up overflows.
+  pub(crate) fn round_hash_size(&self, width: u32, height: u32) -> Option<(u32, u32)> {
     match *self {
-      DoubleGradient => (next_multiple_of_2(width), next_multiple_of_2(height)),
-      Blockhash => (next_multiple_of_4(width), next_multiple_of_4(height)),
-      _ => (width, height),
+      DoubleGradient => Some((next_multiple_of_2(width)?, next_multiple_of_2(height)?)),
+      Blockhash | LegacyBlockhash => {
+        Some((next_multiple_of_4(width)?, next_multiple_of_4(height)?))
+      }
+      _ => Some((width, height)),
     }
   }
 
   pub(crate) fn resize_dimensions(&self, width: u32, height: u32) -> (u32, u32) {
     match *self {
       Mean => (width, height),
-      Blockhash => panic!("Blockhash algorithm does not resize"),
+      Blockhash | LegacyBlockhash => panic!("Blockhash algorithm does not resize"),
       Gradient => (width + 1, height),
       VertGradient => (width, height + 1),
       DoubleGradient => (width / 2 + 1, height / 2 + 1),
@@ -0,0 +1,113 @@
+//! Evaluate hash configurations against synthetic distortions of a corpus of images
+//!
+//! Usage: `hash_eval <corpus dir> [--out <dir>] [--size <width>x<height>]`
+//!
+//! Writes `report.json`, `summary.csv`, `histograms.csv` and `curves.csv` to the output
+//! directory (default: the current directory) and prints the recommended thresholds.
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::{env, io};
+
+use image_hasher::eval::{Distortion, Evaluation};
+
+const USAGE: &str = "usage: hash_eval <corpus dir> [--out <dir>] [--size <width>x<height>]";
+
+fn main() -> Result<(), String> {
+  let mut args = env::args().skip(1);
+
+  let mut corpus = None;
+  let mut out = PathBuf::from(".");
+  let mut size = (10, 10);
+  while let Some(arg) = args.next() {
+    match &*arg {
+      "--out" => out = args.next().ok_or(USAGE)?.into(),
+      "--size" => {
+        let arg = args.next().ok_or(USAGE)?;
+        size = parse_size(&arg).ok_or_else(|| format!("invalid size: {arg}"))?;
+      }
+      _ if corpus.is_none() => corpus = Some(PathBuf::from(arg)),
+      _ => return Err(USAGE.into()),
+    }
+  }
+  let corpus = corpus.ok_or(USAGE)?;
+
+  let mut files = vec![];
+  collect_files(&corpus, &mut files)
+    .map_err(|e| format!("failed to read {}: {}", corpus.display(), e))?;
+  files.sort();
+
+  let mut eval = Evaluation::new(
+    Evaluation::default_configs(size.0, size.1),
+    Distortion::defaults(),
+  );
+  for path in &files {
+    // skip anything that isn't an image
+    match image::open(path) {
+      Ok(image) => eval.add_image(&image),
+      Err(e) => eprintln!("skipping {}: {}", path.display(), e),
+    }
+  }
+
+  if eval.len() < 2 {
+    return Err(format!(
+      "need at least 2 images, found {} in {}",
+      eval.len(),
+      corpus.display()
+    ));
+  }
+
+  let report = eval.report();
+
+  fs::create_dir_all(&out).map_err(|e| format!("failed to create {}: {}", out.display(), e))?;
+  let create = |name: &str| {
+    let path = out.join(name);
+    File::create(&path)
+      .map(BufWriter::new)
+      .map_err(|e| format!("failed to create {}: {}", path.display(), e))
+  };
+
+  serde_json::to_writer_pretty(create("report.json")?, &report).map_err(|e| e.to_string())?;
+  let (summary, histograms, curves) = (
+    create("summary.csv")?,
+    create("histograms.csv")?,
+    create("curves.csv")?,
+  );
+  report
+    .write_summary_csv(summary)
+    .and_then(|_| report.write_histograms_csv(histograms))
+    .and_then(|_| report.write_curves_csv(curves))
+    .map_err(|e| e.to_string())?;
+
+  println!(
+    "{} images, {} distortions",
+    report.images,
+    report.distortions.len()
+  );
+  for c in &report.configs {
+    println!(
+      "{}: threshold {} (auc {:.4}, intra mean {:.2}, inter mean {:.2})",
+      c.config, c.recommended_threshold, c.auc, c.intra_mean, c.inter_mean
+    );
+  }
+
+  Ok(())
+}
+
+fn parse_size(s: &str) -> Option<(u32, u32)> {
+  let (width, height) = s.split_once('x')?;
+  Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_dir() {
+      collect_files(&path, files)?;
+    } else {
+      files.push(path);
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,177 @@
+use clap::{Args, ValueEnum};
+use image_hasher::{BitOrder, FilterType, GaussMode, HashAlg, Hasher, HasherConfig};
+
+/// Larger hash sides are of no use and could overflow or exhaust memory in the hasher.
+const MAX_SIDE: u32 = 1024;
+
+/// Options selecting how images are hashed.
+#[derive(Args, Debug, Clone)]
+pub struct ConfigArgs {
+  /// Hash algorithm
+  #[arg(long, value_enum, default_value_t = Alg::Gradient)]
+  alg: Alg,
+
+  /// Hash size in bits, at most 1024 on each side
+  #[arg(long, value_name = "WIDTHxHEIGHT", default_value = "8x8", value_parser = parse_size)]
+  size: (u32, u32),
+
+  /// Filter used to resize images
+  #[arg(long, value_enum, default_value_t = Filter::Lanczos3)]
+  filter: Filter,
+
+  /// Enable DCT preprocessing
+  #[arg(long)]
+  dct: bool,
+
+  /// Crop near-uniform borders, whose luma varies by at most this much, before hashing
+  #[arg(long, value_name = "TOLERANCE")]
+  trim_borders: Option<u8>,
+
+  /// Enable Difference of Gaussians preprocessing with the given sigmas, e.g. `5,10`
+  #[arg(long, value_name = "SIGMA_A,SIGMA_B", value_parser = parse_sigmas)]
+  dog: Option<(f32, f32)>,
+
+  /// Approximate the Difference of Gaussians with box blurs on the luma plane
+  #[arg(long, requires = "dog")]
+  fast_dog: bool,
+
+  /// Downscale images to at most this size before the fast Difference of Gaussians
+  #[arg(long, value_name = "PIXELS", requires = "fast_dog")]
+  dog_max_size: Option<u32>,
+
+  /// Compute hashes that are bit-identical on every platform
+  #[arg(long)]
+  deterministic: bool,
+
+  /// Order in which bits are packed into the bytes of a hash
+  #[arg(long, value_enum, default_value_t = Order::Lsb)]
+  bit_order: Order,
+}
+
+impl ConfigArgs {
+  pub fn to_hasher(&self) -> Hasher {
+    self.to_config().to_hasher()
+  }
+
+  /// The bit order hashes are computed with, and read back in.
+  pub fn bit_order(&self) -> BitOrder {
+    self.bit_order.into()
+  }
+
+  pub fn to_config(&self) -> HasherConfig {
+    let mut config = HasherConfig::new()
+      .hash_alg(self.alg.into())
+      .hash_size(self.size.0, self.size.1)
+      .resize_filter(self.filter.into());
+
+    if self.dct {
+      config = config.preproc_dct();
+    }
+
+    if let Some(tolerance) = self.trim_borders {
+      config = config.preproc_trim_borders(tolerance);
+    }
+
+    if let Some((sigma_a, sigma_b)) = self.dog {
+      config = config.preproc_diff_gauss_sigmas(sigma_a, sigma_b);
+    }
+
+    if self.fast_dog {
+      config = config.gauss_mode(GaussMode::Fast {
+        max_size: self.dog_max_size,
+      });
+    }
+
+    if self.deterministic {
+      config = config.deterministic();
+    }
+
+    config = config.bit_order(self.bit_order.into());
+
+    config
+  }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum Alg {
+  Mean,
+  Gradient,
+  VertGradient,
+  DoubleGradient,
+  Blockhash,
+  LegacyBlockhash,
+}
+
+impl From<Alg> for HashAlg {
+  fn from(alg: Alg) -> Self {
+    match alg {
+      Alg::Mean => HashAlg::Mean,
+      Alg::Gradient => HashAlg::Gradient,
+      Alg::VertGradient => HashAlg::VertGradient,
+      Alg::DoubleGradient => HashAlg::DoubleGradient,
+      Alg::Blockhash => HashAlg::Blockhash,
+      Alg::LegacyBlockhash => HashAlg::LegacyBlockhash,
+    }
+  }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum Filter {
+  Box,
+  Bilinear,
+  Hamming,
+  CatmullRom,
+  Mitchell,
+  Lanczos3,
+}
+
+impl From<Filter> for FilterType {
+  fn from(filter: Filter) -> Self {
+    match filter {
+      Filter::Box => FilterType::Box,
+      Filter::Bilinear => FilterType::Bilinear,
+      Filter::Hamming => FilterType::Hamming,
+      Filter::CatmullRom => FilterType::CatmullRom,
+      Filter::Mitchell => FilterType::Mitchell,
+      Filter::Lanczos3 => FilterType::Lanczos3,
+    }
+  }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum Order {
+  /// Least significant bit first, as in previous versions
+  Lsb,
+  /// Most significant bit first, as in most other implementations
+  Msb,
+}
+
+impl From<Order> for BitOrder {
+  fn from(order: Order) -> Self {
+    match order {
+      Order::Lsb => BitOrder::LsbFirst,
+      Order::Msb => BitOrder::MsbFirst,
+    }
+  }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+  let parse = |s: &str| match s.parse() {
+    Ok(n) if (1..=MAX_SIDE).contains(&n) => Ok(n),
+    _ => Err(format!("invalid size: {s}, must be from 1 to {MAX_SIDE}")),
+  };
+
+  let (width, height) = s
+    .split_once('x')
+    .ok_or_else(|| format!("expected WIDTHxHEIGHT, found {s}"))?;
+  Ok((parse(width)?, parse(height)?))
+}
+
+fn parse_sigmas(s: &str) -> Result<(f32, f32), String> {
+  let parse = |s: &str| s.trim().parse().map_err(|_| format!("invalid sigma: {s}"));
+
+  let (sigma_a, sigma_b) = s
+    .split_once(',')
+    .ok_or_else(|| format!("expected SIGMA_A,SIGMA_B, found {s}"))?;
+  Ok((parse(sigma_a)?, parse(sigma_b)?))
+}
@@ -0,0 +1,209 @@
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use clap::ValueEnum;
+use image_hasher::ImageHash;
+use serde::Serialize;
+
+/// A hashed file considered for deduplication.
+#[derive(Serialize, Debug)]
+pub struct Member {
+  pub path: String,
+  pub width: u32,
+  pub height: u32,
+  /// File size in bytes
+  pub size: u64,
+  /// Distance to the kept file of the group
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub distance: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Group {
+  pub keep: Member,
+  pub duplicates: Vec<Member>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+  pub threshold: u32,
+  pub files: usize,
+  pub groups: Vec<Group>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plan {
+  /// A JSON report of the groups
+  Report,
+  /// A shell script moving the duplicates into `--dest`
+  Move,
+  /// A shell script replacing the duplicates with symlinks to the kept file
+  Symlink,
+}
+
+/// Group near-duplicates and pick the file to keep in each group.
+///
+/// Like `OriginalImage` in `services/models`, the version with the highest resolution is kept;
+/// ties go to the larger file, then to the first path.
+pub fn group(members: Vec<(Member, ImageHash)>, threshold: u32) -> Report {
+  let files = members.len();
+  let hashes: Vec<_> = members.iter().map(|(_, hash)| hash.clone()).collect();
+  let mut members: Vec<_> = members.into_iter().map(Some).collect();
+
+  let groups = image_hasher::cluster(&hashes, threshold)
+    .into_iter()
+    .filter(|indices| indices.len() > 1)
+    .map(|indices| {
+      let keep = *indices
+        .iter()
+        .max_by_key(|&&i| {
+          let (m, _) = members[i].as_ref().unwrap();
+          // `Reverse` so the first path wins among equals
+          (
+            u64::from(m.width) * u64::from(m.height),
+            m.size,
+            std::cmp::Reverse(i),
+          )
+        })
+        .unwrap();
+
+      let (keep_member, keep_hash) = members[keep].take().unwrap();
+      let duplicates = indices
+        .iter()
+        .filter(|&&i| i != keep)
+        .map(|&i| {
+          let (mut member, hash) = members[i].take().unwrap();
+          member.distance = Some(keep_hash.dist(&hash));
+          member
+        })
+        .collect();
+
+      Group {
+        keep: keep_member,
+        duplicates,
+      }
+    })
+    .collect();
+
+  Report {
+    threshold,
+    files,
+    groups,
+  }
+}
+
+/// Write a shell script carrying out `plan` for the groups in `report`.
+pub fn write_script<W: Write>(
+  mut out: W,
+  report: &Report,
+  plan: Plan,
+  dest: Option<&Path>,
+) -> io::Result<()> {
+  writeln!(out, "#!/bin/sh")?;
+  writeln!(out, "set -e")?;
+
+  for group in &report.groups {
+    writeln!(out)?;
+    writeln!(out, "# keep {}", group.keep.path)?;
+    for dup in &group.duplicates {
+      match plan {
+        Plan::Move => {
+          let dest = dest.expect("`--dest` is required for `--plan move`");
+          let target = dest.join(relative(Path::new(&dup.path)));
+          if let Some(parent) = target.parent() {
+            writeln!(out, "mkdir -p -- {}", quote(&parent.to_string_lossy()))?;
+          }
+          writeln!(
+            out,
+            "mv -- {} {}",
+            quote(&dup.path),
+            quote(&target.to_string_lossy())
+          )?;
+        }
+        Plan::Symlink => {
+          let keep = std::fs::canonicalize(&group.keep.path)?;
+          writeln!(
+            out,
+            "ln -sf -- {} {}",
+            quote(&keep.to_string_lossy()),
+            quote(&dup.path)
+          )?;
+        }
+        Plan::Report => unreachable!("reports are written as JSON"),
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Strip the root and any `..` from `path` so it can be recreated under another directory.
+fn relative(path: &Path) -> PathBuf {
+  path
+    .components()
+    .filter(|c| matches!(c, Component::Normal(_)))
+    .collect()
+}
+
+/// Quote `s` for a POSIX shell.
+fn quote(s: &str) -> String {
+  format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn member(path: &str, width: u32, height: u32, size: u64, hash: u8) -> (Member, ImageHash) {
+    let member = Member {
+      path: path.into(),
+      width,
+      height,
+      size,
+      distance: None,
+    };
+    (member, ImageHash::from_bytes(&[hash]).unwrap())
+  }
+
+  #[test]
+  fn keeps_highest_resolution() {
+    let report = group(
+      vec![
+        member("small.png", 100, 100, 5000, 0b0000),
+        member("other.png", 800, 800, 1000, 0b1111_1111),
+        member("large.jpg", 200, 200, 3000, 0b0001),
+        member("large.png", 200, 200, 9000, 0b0011),
+      ],
+      2,
+    );
+
+    assert_eq!(report.files, 4);
+    assert_eq!(report.groups.len(), 1);
+    let group = &report.groups[0];
+    assert_eq!(group.keep.path, "large.png");
+    let dups: Vec<_> = group
+      .duplicates
+      .iter()
+      .map(|d| (&*d.path, d.distance.unwrap()))
+      .collect();
+    assert_eq!(dups, [("small.png", 2), ("large.jpg", 1)]);
+  }
+
+  #[test]
+  fn script() {
+    let report = group(
+      vec![
+        member("a/it's.png", 10, 10, 1, 0),
+        member("/abs/b.png", 10, 10, 2, 0),
+      ],
+      0,
+    );
+
+    let mut out = vec![];
+    write_script(&mut out, &report, Plan::Move, Some(Path::new("dups"))).unwrap();
+    let script = String::from_utf8(out).unwrap();
+    assert!(script.contains("# keep /abs/b.png\n"));
+    assert!(script.contains("mkdir -p -- 'dups/a'\n"));
+    assert!(script.contains("mv -- 'a/it'\\''s.png' 'dups/a/it'\\''s.png'\n"));
+  }
+}
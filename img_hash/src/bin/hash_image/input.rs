@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+/// Extensions of the files picked up when walking a directory.
+const IMAGE_EXTENSIONS: &[&str] = &["bmp", "gif", "jpeg", "jpg", "png", "tif", "tiff", "webp"];
+
+/// Expand files, directories and glob patterns into a list of files.
+///
+/// Directories are walked recursively and only image files within them are returned, in
+/// order of their paths. Errors are collected so the remaining inputs can still be processed.
+pub fn expand(inputs: &[String]) -> (Vec<PathBuf>, Vec<String>) {
+  let mut files = vec![];
+  let mut errors = vec![];
+
+  for input in inputs {
+    let path = Path::new(input);
+    if path.exists() {
+      expand_path(path, &mut files, &mut errors);
+      continue;
+    }
+
+    if !input.contains(['*', '?', '[']) {
+      errors.push(format!("{input}: no such file or directory"));
+      continue;
+    }
+
+    match glob::glob(input) {
+      Ok(paths) => {
+        let len = files.len();
+        for path in paths {
+          match path {
+            Ok(path) => expand_path(&path, &mut files, &mut errors),
+            Err(e) => errors.push(e.to_string()),
+          }
+        }
+        if files.len() == len {
+          errors.push(format!("{input}: no matches"));
+        }
+      }
+      Err(e) => errors.push(format!("{input}: {e}")),
+    }
+  }
+
+  (files, errors)
+}
+
+fn expand_path(path: &Path, files: &mut Vec<PathBuf>, errors: &mut Vec<String>) {
+  if !path.is_dir() {
+    files.push(path.to_owned());
+    return;
+  }
+
+  for entry in WalkDir::new(path).sort_by_file_name() {
+    match entry {
+      Ok(entry) if entry.file_type().is_file() && is_image(entry.path()) => {
+        files.push(entry.into_path())
+      }
+      Ok(_) => {}
+      Err(e) => errors.push(e.to_string()),
+    }
+  }
+}
+
+fn is_image(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|ext| ext.to_str())
+    .map_or(false, |ext| {
+      IMAGE_EXTENSIONS
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(ext))
+    })
+}
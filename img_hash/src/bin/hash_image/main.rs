@@ -0,0 +1,417 @@
+//! Hash images, compare hashes, verify them against a manifest and find near-duplicates
+//!
+//! Exit codes follow `diff`: `0` on success, `1` if `verify` found a difference or the distance
+//! `compare` found exceeds its `--threshold`, and `2` if any input could not be read or the
+//! arguments were invalid.
+
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand};
+use image_hasher::{FileHash, FileHasher, HashCache, Hasher, ImageHash};
+
+use crate::config::ConfigArgs;
+use crate::dedupe::{Member, Plan};
+use crate::record::{read_manifest, Encoding, Format, Record, RecordWriter};
+
+mod config;
+mod dedupe;
+mod input;
+mod record;
+
+const EXIT_DIFFERENT: u8 = 1;
+const EXIT_ERROR: u8 = 2;
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Hash files, directories (recursively) and glob patterns
+  Hash(HashArgs),
+  /// Print the distance between two images or hashes
+  Compare(CompareArgs),
+  /// Recompute the hashes in a manifest written by `hash` and report the differences
+  Verify(VerifyArgs),
+  /// Group near-duplicate images and plan which ones to remove
+  Dedupe(DedupeArgs),
+}
+
+#[derive(Args, Debug)]
+struct HashArgs {
+  #[command(flatten)]
+  config: ConfigArgs,
+
+  /// Output format
+  #[arg(long, value_enum, default_value_t = Format::Text)]
+  format: Format,
+
+  /// Hash encoding
+  #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+  encoding: Encoding,
+
+  /// Reuse hashes of unchanged files from this cache file, creating it if needed
+  #[arg(long, value_name = "FILE")]
+  cache: Option<PathBuf>,
+
+  /// Files, directories or glob patterns to hash
+  #[arg(required = true)]
+  inputs: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct CompareArgs {
+  #[command(flatten)]
+  config: ConfigArgs,
+
+  /// Encoding of hashes given instead of images
+  #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+  encoding: Encoding,
+
+  /// Exit with code 1 if the distance is larger than this
+  #[arg(long)]
+  threshold: Option<u32>,
+
+  /// An image path or a hash
+  a: String,
+
+  /// An image path or a hash
+  b: String,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+  #[command(flatten)]
+  config: ConfigArgs,
+
+  /// Encoding of the hashes in the manifest
+  #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+  encoding: Encoding,
+
+  /// Accept recomputed hashes within this distance of the stored ones
+  #[arg(long, default_value_t = 0)]
+  max_distance: u32,
+
+  /// A manifest in any format written by `hash`
+  manifest: String,
+}
+
+#[derive(Args, Debug)]
+struct DedupeArgs {
+  #[command(flatten)]
+  config: ConfigArgs,
+
+  /// Group images whose hashes are within this distance of each other
+  #[arg(long, default_value_t = 4)]
+  threshold: u32,
+
+  /// What to print: a JSON report or a shell script acting on the duplicates
+  #[arg(long, value_enum, default_value_t = Plan::Report)]
+  plan: Plan,
+
+  /// Directory the duplicates are moved into, keeping their relative paths
+  #[arg(long, required_if_eq("plan", "move"))]
+  dest: Option<PathBuf>,
+
+  /// Reuse hashes of unchanged files from this cache file, creating it if needed
+  #[arg(long, value_name = "FILE")]
+  cache: Option<PathBuf>,
+
+  /// Files, directories or glob patterns to deduplicate
+  #[arg(required = true)]
+  inputs: Vec<String>,
+}
+
+fn main() -> ExitCode {
+  let cli = Cli::parse();
+
+  let result = match cli.command {
+    Command::Hash(args) => hash(args, io::stdout().lock()),
+    Command::Compare(args) => compare(args),
+    Command::Verify(args) => verify(args),
+    Command::Dedupe(args) => dedupe(args),
+  };
+
+  match result {
+    Ok(code) => ExitCode::from(code),
+    Err(e) => {
+      eprintln!("hash_image: {e}");
+      ExitCode::from(EXIT_ERROR)
+    }
+  }
+}
+
+fn hash(args: HashArgs, out: impl Write) -> Result<u8, String> {
+  let cache = open_cache(args.cache.as_deref())?;
+  let hasher = file_hasher(&args.config, cache.as_ref());
+  let (files, errors) = input::expand(&args.inputs);
+  let mut failed = !errors.is_empty();
+  for e in errors {
+    eprintln!("hash_image: {e}");
+  }
+
+  let mut out = RecordWriter::new(BufWriter::new(out), args.format);
+  for result in hash_files(&hasher, &files) {
+    match result {
+      Ok(file) => {
+        let record = Record {
+          path: file.path.display().to_string(),
+          hash: args.encoding.encode(&file.hash),
+          width: Some(file.width),
+          height: Some(file.height),
+        };
+        out.write(&record).map_err(|e| e.to_string())?;
+      }
+      Err(e) => {
+        eprintln!("hash_image: {e}");
+        failed = true;
+      }
+    }
+  }
+  out.flush().map_err(|e| e.to_string())?;
+
+  Ok(if failed { EXIT_ERROR } else { 0 })
+}
+
+fn compare(args: CompareArgs) -> Result<u8, String> {
+  let hasher = args.config.to_hasher();
+  let load = |input: &str| {
+    if Path::new(input).exists() {
+      hash_file(&hasher, Path::new(input)).map(|(hash, ..)| hash)
+    } else {
+      args.encoding.decode(input, args.config.bit_order())
+    }
+  };
+  let (a, b) = (load(&args.a)?, load(&args.b)?);
+
+  if a.as_bytes().len() != b.as_bytes().len() {
+    return Err(format!(
+      "hashes have different sizes: {} and {} bytes",
+      a.as_bytes().len(),
+      b.as_bytes().len()
+    ));
+  }
+
+  let dist = a.dist(&b);
+  writeln!(io::stdout(), "{dist}").map_err(|e| e.to_string())?;
+
+  Ok(match args.threshold {
+    Some(threshold) if dist > threshold => EXIT_DIFFERENT,
+    _ => 0,
+  })
+}
+
+fn verify(args: VerifyArgs) -> Result<u8, String> {
+  let hasher = args.config.to_hasher();
+  let manifest = fs::read_to_string(&args.manifest)
+    .map_err(|e| format!("failed to read {}: {e}", args.manifest))?;
+  let records = read_manifest(&manifest).map_err(|e| format!("{}: {e}", args.manifest))?;
+
+  let mut out = BufWriter::new(io::stdout().lock());
+  let (mut ok, mut changed, mut failed) = (0, 0, 0);
+  for record in records {
+    let decoded = args.encoding.decode(&record.hash, args.config.bit_order());
+    let result = decoded.and_then(|expected| {
+      let (actual, ..) = hash_file(&hasher, Path::new(&record.path))?;
+      Ok((expected, actual))
+    });
+
+    match result {
+      Ok((expected, actual)) if expected.as_bytes().len() != actual.as_bytes().len() => {
+        writeln!(out, "{}: hash size changed", record.path).map_err(|e| e.to_string())?;
+        changed += 1;
+      }
+      Ok((expected, actual)) if expected.dist(&actual) > args.max_distance => {
+        writeln!(
+          out,
+          "{}: expected {}, found {} (distance {})",
+          record.path,
+          record.hash,
+          args.encoding.encode(&actual),
+          expected.dist(&actual)
+        )
+        .map_err(|e| e.to_string())?;
+        changed += 1;
+      }
+      Ok(_) => ok += 1,
+      Err(e) => {
+        eprintln!("hash_image: {e}");
+        failed += 1;
+      }
+    }
+  }
+
+  out.flush().map_err(|e| e.to_string())?;
+  eprintln!("{ok} ok, {changed} changed, {failed} failed");
+
+  Ok(if failed > 0 {
+    EXIT_ERROR
+  } else if changed > 0 {
+    EXIT_DIFFERENT
+  } else {
+    0
+  })
+}
+
+fn dedupe(args: DedupeArgs) -> Result<u8, String> {
+  let cache = open_cache(args.cache.as_deref())?;
+  let hasher = file_hasher(&args.config, cache.as_ref());
+  let (files, errors) = input::expand(&args.inputs);
+  let mut failed = !errors.is_empty();
+  for e in errors {
+    eprintln!("hash_image: {e}");
+  }
+
+  let mut members = vec![];
+  for result in hash_files(&hasher, &files) {
+    match result {
+      Ok(file) => {
+        let member = Member {
+          path: file.path.display().to_string(),
+          width: file.width,
+          height: file.height,
+          size: file.size,
+          distance: None,
+        };
+        members.push((member, file.hash));
+      }
+      Err(e) => {
+        eprintln!("hash_image: {e}");
+        failed = true;
+      }
+    }
+  }
+
+  let report = dedupe::group(members, args.threshold);
+  let mut out = BufWriter::new(io::stdout().lock());
+  match args.plan {
+    Plan::Report => {
+      serde_json::to_writer_pretty(&mut out, &report).map_err(|e| e.to_string())?;
+      writeln!(out).map_err(|e| e.to_string())?;
+    }
+    plan => dedupe::write_script(&mut out, &report, plan, args.dest.as_deref())
+      .map_err(|e| e.to_string())?,
+  }
+  out.flush().map_err(|e| e.to_string())?;
+
+  let duplicates: usize = report.groups.iter().map(|g| g.duplicates.len()).sum();
+  eprintln!(
+    "{} files, {} groups, {duplicates} duplicates",
+    report.files,
+    report.groups.len()
+  );
+
+  Ok(if failed { EXIT_ERROR } else { 0 })
+}
+
+fn open_cache(path: Option<&Path>) -> Result<Option<HashCache>, String> {
+  path
+    .map(HashCache::open)
+    .transpose()
+    .map_err(|e| e.to_string())
+}
+
+fn file_hasher<'a>(config: &ConfigArgs, cache: Option<&'a HashCache>) -> FileHasher<'a> {
+  let config = config.to_config();
+  match cache {
+    Some(cache) => FileHasher::with_cache(&config, cache),
+    None => FileHasher::new(&config),
+  }
+}
+
+/// Hash `files` in batches so results can be written as they come in.
+fn hash_files<'a>(
+  hasher: &'a FileHasher,
+  files: &'a [PathBuf],
+) -> impl Iterator<Item = Result<FileHash, image_hasher::FileError>> + 'a {
+  files.chunks(256).flat_map(|chunk| hasher.hash_files(chunk))
+}
+
+fn hash_file(hasher: &Hasher, path: &Path) -> Result<(ImageHash, u32, u32), String> {
+  let image = image::open(path).map_err(|e| format!("failed to open {}: {e}", path.display()))?;
+  Ok((hasher.hash_image(&image), image.width(), image.height()))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn run(args: &[&str], out: &mut Vec<u8>) -> Result<u8, String> {
+    let cli = Cli::try_parse_from([&["hash_image"], args].concat()).unwrap();
+    match cli.command {
+      Command::Hash(args) => hash(args, out),
+      Command::Compare(args) => compare(args),
+      Command::Verify(args) => verify(args),
+      Command::Dedupe(args) => dedupe(args),
+    }
+  }
+
+  #[test]
+  fn rejects_invalid_sizes() {
+    for size in ["0x8", "4294967295x1", "1025x8", "8"] {
+      let args = [
+        "hash_image",
+        "hash",
+        "--alg",
+        "blockhash",
+        "--size",
+        size,
+        "a.png",
+      ];
+      let err = Cli::try_parse_from(args).unwrap_err();
+      assert_eq!(err.exit_code(), i32::from(EXIT_ERROR), "{size}");
+    }
+    assert!(Cli::try_parse_from(["hash_image", "hash", "--size", "1024x1", "a.png"]).is_ok());
+  }
+
+  #[test]
+  fn verifies_own_hashes() {
+    let dir = std::env::temp_dir().join(format!("hash_image-verify-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let image = dir.join("a.png");
+    image::RgbImage::from_fn(40, 30, |x, y| {
+      [(x * 6) as u8, (y * 8) as u8, (x ^ y) as u8].into()
+    })
+    .save(&image)
+    .unwrap();
+    let (image, manifest) = (image.to_str().unwrap(), dir.join("manifest.txt"));
+    let manifest = manifest.to_str().unwrap();
+
+    for order in ["lsb", "msb"] {
+      for encoding in ["hex", "base64"] {
+        let config = ["--bit-order", order, "--encoding", encoding];
+        let mut out = Vec::new();
+        assert_eq!(
+          run(&[&["hash"], &config[..], &[image]].concat(), &mut out),
+          Ok(0)
+        );
+        fs::write(manifest, &out).unwrap();
+        assert_eq!(
+          run(
+            &[&["verify"], &config[..], &[manifest]].concat(),
+            &mut Vec::new()
+          ),
+          Ok(0)
+        );
+
+        let hash = String::from_utf8(out).unwrap();
+        let hash = hash.split_whitespace().next().unwrap();
+        let compare = [
+          &["compare", "--threshold", "0"],
+          &config[..],
+          &[image, hash],
+        ]
+        .concat();
+        assert_eq!(run(&compare, &mut Vec::new()), Ok(0), "{order} {encoding}");
+      }
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,263 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use image_hasher::{BitOrder, ImageHash, InvalidBytesError};
+use serde::{Deserialize, Serialize};
+
+/// The hash of one file, as written by `hash` and read back by `verify`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+  pub path: String,
+  pub hash: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub width: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub height: Option<u32>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// `<hash>  <path>`, like `sha256sum`
+  Text,
+  /// One JSON object per line
+  Jsonl,
+  /// Comma separated values with a header
+  Csv,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+  Hex,
+  Base64,
+}
+
+impl Encoding {
+  pub fn encode(self, hash: &ImageHash) -> String {
+    match self {
+      Encoding::Hex => hash.as_bytes().iter().map(|b| format!("{b:02x}")).collect(),
+      Encoding::Base64 => hash.to_base64(),
+    }
+  }
+
+  /// Decode a hash computed with the given bit order.
+  pub fn decode(self, hash: &str, bit_order: BitOrder) -> Result<ImageHash, String> {
+    let hash = hash.trim();
+    let decoded = match self {
+      Encoding::Hex => {
+        let bytes = (0..hash.len())
+          .step_by(2)
+          .map(|i| {
+            hash
+              .get(i..i + 2)
+              .and_then(|b| u8::from_str_radix(b, 16).ok())
+          })
+          .collect::<Option<Vec<_>>>()
+          .ok_or_else(|| format!("invalid hex hash: {hash}"))?;
+        ImageHash::from_bytes_with_order(&bytes, bit_order)
+      }
+      Encoding::Base64 => ImageHash::<Box<[u8]>>::from_base64(hash)
+        .and_then(|hash| ImageHash::from_bytes_with_order(hash.as_bytes(), bit_order)),
+    };
+
+    decoded.map_err(|e| match e {
+      InvalidBytesError::Base64(_) => format!("invalid base64 hash: {hash}"),
+      e => format!("invalid hash {hash}: {e:?}"),
+    })
+  }
+}
+
+pub struct RecordWriter<W> {
+  out: W,
+  format: Format,
+  header_written: bool,
+}
+
+impl<W: Write> RecordWriter<W> {
+  pub fn new(out: W, format: Format) -> Self {
+    RecordWriter {
+      out,
+      format,
+      header_written: false,
+    }
+  }
+
+  pub fn write(&mut self, record: &Record) -> io::Result<()> {
+    match self.format {
+      Format::Text => writeln!(self.out, "{}  {}", record.hash, record.path),
+      Format::Jsonl => {
+        serde_json::to_writer(&mut self.out, record)?;
+        writeln!(self.out)
+      }
+      Format::Csv => {
+        if !self.header_written {
+          writeln!(self.out, "path,hash,width,height")?;
+          self.header_written = true;
+        }
+        let dim = |d: Option<u32>| d.map(|d| d.to_string()).unwrap_or_default();
+        writeln!(
+          self.out,
+          "{},{},{},{}",
+          csv_quote(&record.path),
+          record.hash,
+          dim(record.width),
+          dim(record.height)
+        )
+      }
+    }
+  }
+
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.out.flush()
+  }
+}
+
+/// Parse a manifest in any of the output [`Format`]s.
+pub fn read_manifest(contents: &str) -> Result<Vec<Record>, String> {
+  let mut lines = contents
+    .lines()
+    .enumerate()
+    .filter(|(_, line)| !line.trim().is_empty())
+    .peekable();
+
+  let Some(&(_, first)) = lines.peek() else {
+    return Ok(vec![]);
+  };
+
+  if first.starts_with('{') {
+    return lines
+      .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("line {}: {e}", i + 1)))
+      .collect();
+  }
+
+  if first.starts_with("path,") {
+    let header = split_csv_line(first);
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let (Some(path), Some(hash)) = (column("path"), column("hash")) else {
+      return Err("CSV manifest needs `path` and `hash` columns".into());
+    };
+    let (width, height) = (column("width"), column("height"));
+
+    return lines
+      .skip(1)
+      .map(|(i, line)| {
+        let fields = split_csv_line(line);
+        let get = |col: usize| {
+          fields
+            .get(col)
+            .cloned()
+            .ok_or_else(|| format!("line {}: missing column", i + 1))
+        };
+        let dim = |col: Option<usize>| col.and_then(|col| fields.get(col)?.parse().ok());
+        Ok(Record {
+          path: get(path)?,
+          hash: get(hash)?,
+          width: dim(width),
+          height: dim(height),
+        })
+      })
+      .collect();
+  }
+
+  lines
+    .map(|(i, line)| {
+      let (hash, path) = line
+        .split_once("  ")
+        .ok_or_else(|| format!("line {}: expected `<hash>  <path>`", i + 1))?;
+      Ok(Record {
+        path: path.into(),
+        hash: hash.into(),
+        width: None,
+        height: None,
+      })
+    })
+    .collect()
+}
+
+fn csv_quote(field: &str) -> String {
+  if field.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.into()
+  }
+}
+
+fn split_csv_line(line: &str) -> Vec<String> {
+  let mut fields = vec![];
+  let mut field = String::new();
+  let mut quoted = false;
+  let mut chars = line.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match (c, quoted) {
+      ('"', true) if chars.peek() == Some(&'"') => {
+        field.push('"');
+        chars.next();
+      }
+      ('"', _) => quoted = !quoted,
+      (',', false) => fields.push(std::mem::take(&mut field)),
+      (c, _) => field.push(c),
+    }
+  }
+  fields.push(field);
+
+  fields
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn records() -> Vec<Record> {
+    vec![
+      Record {
+        path: "a/b.png".into(),
+        hash: "00ff".into(),
+        width: Some(10),
+        height: Some(20),
+      },
+      Record {
+        path: "with, \"comma\".jpg".into(),
+        hash: "0f0f".into(),
+        width: Some(1),
+        height: Some(2),
+      },
+    ]
+  }
+
+  #[test]
+  fn manifest_round_trip() {
+    for format in [Format::Text, Format::Jsonl, Format::Csv] {
+      let mut out = vec![];
+      let mut writer = RecordWriter::new(&mut out, format);
+      for record in records() {
+        writer.write(&record).unwrap();
+      }
+
+      let parsed = read_manifest(std::str::from_utf8(&out).unwrap()).unwrap();
+      assert_eq!(parsed.len(), 2, "{format:?}");
+      for (parsed, record) in parsed.iter().zip(records()) {
+        assert_eq!(parsed.path, record.path, "{format:?}");
+        assert_eq!(parsed.hash, record.hash, "{format:?}");
+        if format != Format::Text {
+          assert_eq!(parsed.width, record.width, "{format:?}");
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn hash_encoding() {
+    for order in [BitOrder::LsbFirst, BitOrder::MsbFirst] {
+      let hash = ImageHash::from_bytes_with_order(&[0xde, 0xad, 0x01], order).unwrap();
+      for encoding in [Encoding::Hex, Encoding::Base64] {
+        assert_eq!(
+          encoding.decode(&encoding.encode(&hash), order).unwrap(),
+          hash
+        );
+      }
+      assert_eq!(Encoding::Hex.encode(&hash), "dead01");
+    }
+    assert!(Encoding::Hex.decode("dea", BitOrder::LsbFirst).is_err());
+    assert!(Encoding::Hex.decode("zz", BitOrder::LsbFirst).is_err());
+  }
+}
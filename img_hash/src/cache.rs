@@ -0,0 +1,501 @@
+//! Hashing image files with an optional on-disk cache.
+//!
+//! Decoding dominates the time spent hashing a large archive, so [`FileHasher`] can remember
+//! the hashes it computed in a [`HashCache`] and skip decoding files it has already seen:
+//!
+//! ```rust,no_run
+//! use image_hasher::{FileHasher, HashCache, HasherConfig};
+//!
+//! let cache = HashCache::open("hashes.redb").unwrap();
+//! let hasher = FileHasher::with_cache(&HasherConfig::new(), &cache);
+//!
+//! for result in hasher.hash_files(["image1.png", "image2.png"]) {
+//!   let file = result.unwrap();
+//!   println!("{}: {}", file.path.display(), file.hash.to_base64());
+//! }
+//! ```
+//!
+//! Hashes are keyed by the SHA-256 of the file and a fingerprint of the [`HasherConfig`], so
+//! renamed or copied files are still hits and different configurations can share a cache.
+//! Each path additionally remembers the size, modification time and SHA-256 of the file when it
+//! was last hashed; as long as the size and modification time are unchanged the file isn't even
+//! read.
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use image::io::Reader;
+use image::ImageError;
+use redb::{Database, ReadableTable, TableDefinition};
+use sha2::{Digest, Sha256};
+
+use crate::{BitOrder, HashBytes, Hasher, HasherConfig, ImageHash};
+
+/// Changes whenever hashes computed by this crate change for the same configuration, so
+/// entries written by older versions are not used.
+const HASH_VERSION: u32 = 1;
+
+/// Number of files hashed per write transaction by [`FileHasher::hash_files`].
+const BATCH_SIZE: usize = 256;
+
+/// Canonical path => size (8 bytes), mtime in nanoseconds (16 bytes), SHA-256 (32 bytes)
+const FILES: TableDefinition<&str, &[u8]> = TableDefinition::new("files");
+/// SHA-256 ++ config fingerprint => width (4 bytes), height (4 bytes), hash bytes
+const HASHES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("hashes");
+
+/// A hashed image file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHash<B = Box<[u8]>> {
+  /// The path as given to the hasher
+  pub path: PathBuf,
+  /// The hash of the decoded image
+  pub hash: ImageHash<B>,
+  /// Width of the decoded image
+  pub width: u32,
+  /// Height of the decoded image
+  pub height: u32,
+  /// Size of the file in bytes
+  pub size: u64,
+  /// SHA-256 of the file contents
+  pub sha256: [u8; 32],
+  /// Whether the hash was read from the cache instead of decoding the image
+  pub cached: bool,
+}
+
+/// An error hashing a file.
+#[derive(Debug)]
+pub enum FileError {
+  /// The file could not be read
+  Io(PathBuf, io::Error),
+  /// The file could not be decoded
+  Image(PathBuf, ImageError),
+  /// The cache could not be read or written
+  Cache(redb::Error),
+}
+
+impl fmt::Display for FileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      FileError::Io(path, e) => write!(f, "failed to read {}: {e}", path.display()),
+      FileError::Image(path, e) => write!(f, "failed to open {}: {e}", path.display()),
+      FileError::Cache(e) => write!(f, "hash cache: {e}"),
+    }
+  }
+}
+
+impl std::error::Error for FileError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      FileError::Io(_, e) => Some(e),
+      FileError::Image(_, e) => Some(e),
+      FileError::Cache(e) => Some(e),
+    }
+  }
+}
+
+impl<E: Into<redb::Error>> From<E> for FileError {
+  fn from(e: E) -> Self {
+    FileError::Cache(e.into())
+  }
+}
+
+/// An on-disk cache of image hashes, stored in a single [redb](https://docs.rs/redb) file.
+///
+/// Only one process can open a cache at a time.
+pub struct HashCache {
+  db: Database,
+}
+
+impl HashCache {
+  /// Open the cache at `path`, creating it if it doesn't exist.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, FileError> {
+    let db = Database::create(path)?;
+
+    // create the tables so read transactions can open them
+    let txn = db.begin_write()?;
+    txn.open_table(FILES)?;
+    txn.open_table(HASHES)?;
+    txn.commit()?;
+
+    Ok(HashCache { db })
+  }
+}
+
+/// Hashes image files, optionally backed by a [`HashCache`].
+pub struct FileHasher<'a, B = Box<[u8]>> {
+  hasher: Hasher<B>,
+  cache: Option<(&'a HashCache, [u8; 32])>,
+}
+
+/// A file hashed during a batch, and whether its entries need to be written to the cache.
+struct Pending<B> {
+  file: FileHash<B>,
+  canonical: Option<(String, u128)>,
+}
+
+impl<'a, B: HashBytes> FileHasher<'a, B> {
+  /// Create a hasher that decodes every file.
+  pub fn new(config: &HasherConfig<B>) -> Self {
+    FileHasher {
+      hasher: config.to_hasher(),
+      cache: None,
+    }
+  }
+
+  /// Create a hasher that looks up and stores hashes in `cache`.
+  pub fn with_cache(config: &HasherConfig<B>, cache: &'a HashCache) -> Self {
+    FileHasher {
+      hasher: config.to_hasher(),
+      cache: Some((cache, fingerprint(config))),
+    }
+  }
+
+  /// Get the underlying [`Hasher`].
+  pub fn hasher(&self) -> &Hasher<B> {
+    &self.hasher
+  }
+
+  /// Hash a single file.
+  pub fn hash_file<P: AsRef<Path>>(&self, path: P) -> Result<FileHash<B>, FileError> {
+    self.hash_files([path]).pop().unwrap()
+  }
+
+  /// Hash many files, returning the results in the same order.
+  ///
+  /// With a cache, new entries are written in batches so an interrupted run keeps most of its
+  /// progress.
+  pub fn hash_files<P, I>(&self, paths: I) -> Vec<Result<FileHash<B>, FileError>>
+  where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = P>,
+  {
+    let paths: Vec<_> = paths.into_iter().collect();
+    let mut results = Vec::with_capacity(paths.len());
+
+    for chunk in paths.chunks(BATCH_SIZE) {
+      let pending: Vec<_> = chunk.iter().map(|path| self.hash(path.as_ref())).collect();
+      if let Err(e) = self.store(&pending) {
+        // `redb::Error` isn't `Clone`, so every file in the batch gets a copy of the message
+        let e = e.to_string();
+        results.extend(chunk.iter().map(|_| {
+          let e = io::Error::new(io::ErrorKind::Other, e.clone());
+          Err(FileError::Cache(redb::Error::Io(e)))
+        }));
+        continue;
+      }
+      results.extend(pending.into_iter().map(|p| p.map(|p| p.file)));
+    }
+
+    results
+  }
+
+  fn hash(&self, path: &Path) -> Result<Pending<B>, FileError> {
+    let io_err = |e| FileError::Io(path.to_owned(), e);
+
+    let metadata = fs::metadata(path).map_err(io_err)?;
+    let size = metadata.len();
+
+    let Some((cache, fingerprint)) = self.cache else {
+      let bytes = fs::read(path).map_err(io_err)?;
+      let sha256 = Sha256::digest(&bytes).into();
+      return Ok(Pending {
+        file: self.decode(path, &bytes, sha256)?,
+        canonical: None,
+      });
+    };
+
+    let canonical = fs::canonicalize(path).map_err(io_err)?;
+    let canonical = canonical.to_string_lossy().into_owned();
+    let mtime = metadata
+      .modified()
+      .ok()
+      .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+      .map_or(0, |d| d.as_nanos());
+
+    let txn = cache.db.begin_read()?;
+    let files = txn.open_table(FILES)?;
+    let hashes = txn.open_table(HASHES)?;
+
+    // an unchanged file is looked up without reading it
+    if let Some(entry) = files.get(&*canonical)? {
+      if let Some(sha256) = decode_file_entry(entry.value(), size, mtime) {
+        if let Some(entry) = hashes.get(&hash_key(&sha256, &fingerprint)[..])? {
+          let order = self.hasher.bit_order;
+          if let Some(file) = decode_hash_entry(path, entry.value(), order, size, sha256) {
+            return Ok(Pending {
+              file,
+              canonical: None,
+            });
+          }
+        }
+      }
+    }
+
+    let bytes = fs::read(path).map_err(io_err)?;
+    let sha256: [u8; 32] = Sha256::digest(&bytes).into();
+
+    // the content may have been hashed under another path
+    let file = match hashes.get(&hash_key(&sha256, &fingerprint)[..])? {
+      Some(entry) => decode_hash_entry(path, entry.value(), self.hasher.bit_order, size, sha256),
+      None => None,
+    };
+    let file = match file {
+      Some(file) => file,
+      None => self.decode(path, &bytes, sha256)?,
+    };
+
+    Ok(Pending {
+      file,
+      canonical: Some((canonical, mtime)),
+    })
+  }
+
+  fn decode(&self, path: &Path, bytes: &[u8], sha256: [u8; 32]) -> Result<FileHash<B>, FileError> {
+    let image_err = |e| FileError::Image(path.to_owned(), e);
+
+    let image = Reader::new(Cursor::new(bytes))
+      .with_guessed_format()
+      .map_err(|e| FileError::Io(path.to_owned(), e))?
+      .decode()
+      .map_err(image_err)?;
+
+    Ok(FileHash {
+      path: path.to_owned(),
+      hash: self.hasher.hash_image(&image),
+      width: image.width(),
+      height: image.height(),
+      size: bytes.len() as u64,
+      sha256,
+      cached: false,
+    })
+  }
+
+  fn store(&self, pending: &[Result<Pending<B>, FileError>]) -> Result<(), redb::Error> {
+    let Some((cache, fingerprint)) = self.cache else {
+      return Ok(());
+    };
+
+    let mut entries = pending
+      .iter()
+      .filter_map(|p| p.as_ref().ok())
+      .filter_map(|p| Some((&p.file, p.canonical.as_ref()?)))
+      .peekable();
+    if entries.peek().is_none() {
+      return Ok(());
+    }
+
+    let txn = cache.db.begin_write()?;
+    {
+      let mut files = txn.open_table(FILES)?;
+      let mut hashes = txn.open_table(HASHES)?;
+
+      for (file, (canonical, mtime)) in entries {
+        let mut entry = Vec::with_capacity(56);
+        entry.extend_from_slice(&file.size.to_le_bytes());
+        entry.extend_from_slice(&mtime.to_le_bytes());
+        entry.extend_from_slice(&file.sha256);
+        files.insert(&**canonical, &*entry)?;
+
+        if !file.cached {
+          let mut entry = Vec::with_capacity(8 + file.hash.as_bytes().len());
+          entry.extend_from_slice(&file.width.to_le_bytes());
+          entry.extend_from_slice(&file.height.to_le_bytes());
+          entry.extend_from_slice(file.hash.as_bytes());
+          hashes.insert(&hash_key(&file.sha256, &fingerprint)[..], &*entry)?;
+        }
+      }
+    }
+    txn.commit()?;
+
+    Ok(())
+  }
+}
+
+/// Identify everything that influences the hash of an image: the configuration and the version
+/// of the algorithms.
+fn fingerprint<B>(config: &HasherConfig<B>) -> [u8; 32] {
+  Sha256::new()
+    .chain_update(HASH_VERSION.to_le_bytes())
+    .chain_update(format!("{config:?}"))
+    .finalize()
+    .into()
+}
+
+fn hash_key(sha256: &[u8; 32], fingerprint: &[u8; 32]) -> [u8; 64] {
+  let mut key = [0; 64];
+  key[..32].copy_from_slice(sha256);
+  key[32..].copy_from_slice(fingerprint);
+  key
+}
+
+/// Get the SHA-256 of a `FILES` entry if the file is unchanged.
+fn decode_file_entry(entry: &[u8], size: u64, mtime: u128) -> Option<[u8; 32]> {
+  if entry.len() != 56 {
+    return None;
+  }
+
+  let entry_size = u64::from_le_bytes(entry[..8].try_into().unwrap());
+  let entry_mtime = u128::from_le_bytes(entry[8..24].try_into().unwrap());
+  if entry_size != size || entry_mtime != mtime {
+    return None;
+  }
+
+  entry[24..].try_into().ok()
+}
+
+/// Read a `HASHES` entry; the fingerprint includes the bit order, so it's the hasher's.
+fn decode_hash_entry<B: HashBytes>(
+  path: &Path,
+  entry: &[u8],
+  bit_order: BitOrder,
+  size: u64,
+  sha256: [u8; 32],
+) -> Option<FileHash<B>> {
+  if entry.len() < 8 {
+    return None;
+  }
+
+  Some(FileHash {
+    path: path.to_owned(),
+    hash: ImageHash::from_bytes_with_order(&entry[8..], bit_order).ok()?,
+    width: u32::from_le_bytes(entry[..4].try_into().unwrap()),
+    height: u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+    size,
+    sha256,
+    cached: true,
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use std::fs::{self, File};
+  use std::path::PathBuf;
+
+  use image::{ImageOutputFormat, Rgb, RgbImage};
+
+  use super::{FileHasher, HashCache};
+  use crate::{BitOrder, HashAlg, HasherConfig};
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("image_hasher-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn write_image(path: &PathBuf, width: u32, seed: u8) {
+    let image = RgbImage::from_fn(width, 24, |x, y| {
+      Rgb([
+        (x * 8) as u8 ^ seed,
+        (y * 10) as u8,
+        seed.wrapping_mul(x as u8),
+      ])
+    });
+    let mut file = File::create(path).unwrap();
+    image.write_to(&mut file, ImageOutputFormat::Png).unwrap();
+  }
+
+  #[test]
+  fn cache_hits_and_invalidation() {
+    let dir = temp_dir("cache");
+    let (a, b) = (dir.join("a.png"), dir.join("b.png"));
+    write_image(&a, 32, 1);
+    write_image(&b, 32, 2);
+
+    let cache = HashCache::open(dir.join("cache.redb")).unwrap();
+    let config = HasherConfig::new();
+    let hasher = FileHasher::with_cache(&config, &cache);
+    let uncached = FileHasher::new(&config);
+
+    let first: Vec<_> = hasher
+      .hash_files([&a, &b])
+      .into_iter()
+      .map(Result::unwrap)
+      .collect();
+    assert!(first.iter().all(|f| !f.cached));
+    assert_eq!(first[0], uncached.hash_file(&a).unwrap());
+    assert_eq!((first[0].width, first[0].height), (32, 24));
+
+    // unchanged files are read from the cache
+    let second: Vec<_> = hasher
+      .hash_files([&a, &b])
+      .into_iter()
+      .map(Result::unwrap)
+      .collect();
+    assert!(second.iter().all(|f| f.cached));
+    assert_eq!(
+      second.iter().map(|f| &f.hash).collect::<Vec<_>>(),
+      first.iter().map(|f| &f.hash).collect::<Vec<_>>()
+    );
+
+    // copies are found by their contents
+    let c = dir.join("c.png");
+    fs::copy(&a, &c).unwrap();
+    let copy = hasher.hash_file(&c).unwrap();
+    assert!(copy.cached);
+    assert_eq!(copy.hash, first[0].hash);
+
+    // a changed file is hashed again
+    write_image(&a, 48, 3);
+    let changed = hasher.hash_file(&a).unwrap();
+    assert!(!changed.cached);
+    assert_ne!(changed.sha256, first[0].sha256);
+    assert_eq!(changed, uncached.hash_file(&a).unwrap());
+
+    // a different config doesn't reuse the entries
+    let config = HasherConfig::new().hash_alg(HashAlg::Mean);
+    let other = FileHasher::with_cache(&config, &cache)
+      .hash_file(&b)
+      .unwrap();
+    assert!(!other.cached);
+
+    drop(cache);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn keeps_bit_order() {
+    let dir = temp_dir("cache-order");
+    let a = dir.join("a.png");
+    write_image(&a, 32, 1);
+
+    let cache = HashCache::open(dir.join("cache.redb")).unwrap();
+    let config = HasherConfig::new().bit_order(BitOrder::MsbFirst);
+    let hasher = FileHasher::with_cache(&config, &cache);
+    let first = hasher.hash_file(&a).unwrap();
+    let second = hasher.hash_file(&a).unwrap();
+    assert!(second.cached);
+    assert_eq!(second.hash.bit_order(), BitOrder::MsbFirst);
+    assert_eq!(second.hash, first.hash);
+
+    // the LSB-first hash of the same file is a separate entry
+    let lsb = FileHasher::with_cache(&HasherConfig::new(), &cache)
+      .hash_file(&a)
+      .unwrap();
+    assert!(!lsb.cached);
+    assert_eq!(lsb.hash.dist(&first.hash), 0);
+
+    drop(cache);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn errors_keep_order() {
+    let dir = temp_dir("cache-errors");
+    let a = dir.join("a.png");
+    write_image(&a, 32, 1);
+    fs::write(dir.join("bad.png"), b"not an image").unwrap();
+
+    let cache = HashCache::open(dir.join("cache.redb")).unwrap();
+    let hasher = FileHasher::with_cache(&HasherConfig::new(), &cache);
+    let results = hasher.hash_files([a, dir.join("bad.png"), dir.join("missing.png")]);
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1], Err(super::FileError::Image(..))));
+    assert!(matches!(results[2], Err(super::FileError::Io(..))));
+
+    drop(cache);
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
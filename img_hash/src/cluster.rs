@@ -0,0 +1,200 @@
+use crate::{HashBytes, ImageHash};
+
+/// A [BK-tree](https://en.wikipedia.org/wiki/BK-tree) for finding hashes within a distance of
+/// a query without comparing against every stored hash.
+///
+/// Hashes are identified by the order in which they were inserted, starting at 0.
+///
+/// ### Note
+/// All hashes must have been calculated with the same configuration; the distances between
+/// hashes of different sizes are meaningless.
+pub struct BkTree<'a, B = Box<[u8]>> {
+  nodes: Vec<BkNode<'a, B>>,
+}
+
+struct BkNode<'a, B> {
+  hash: &'a ImageHash<B>,
+  /// `(distance to this node, child index)`
+  children: Vec<(u32, usize)>,
+}
+
+impl<'a, B: HashBytes> BkTree<'a, B> {
+  /// Create an empty tree.
+  pub fn new() -> Self {
+    BkTree { nodes: vec![] }
+  }
+
+  /// Get the number of hashes in the tree.
+  pub fn len(&self) -> usize {
+    self.nodes.len()
+  }
+
+  /// Returns `true` if the tree contains no hashes.
+  pub fn is_empty(&self) -> bool {
+    self.nodes.is_empty()
+  }
+
+  /// Add a hash to the tree, returning its index.
+  pub fn insert(&mut self, hash: &'a ImageHash<B>) -> usize {
+    let idx = self.nodes.len();
+    self.nodes.push(BkNode {
+      hash,
+      children: vec![],
+    });
+
+    if idx == 0 {
+      return idx;
+    }
+
+    let mut cur = 0;
+    loop {
+      let dist = self.nodes[cur].hash.dist(hash);
+      match self.nodes[cur].children.iter().find(|&&(d, _)| d == dist) {
+        Some(&(_, child)) => cur = child,
+        None => {
+          self.nodes[cur].children.push((dist, idx));
+          return idx;
+        }
+      }
+    }
+  }
+
+  /// Find all hashes within `max_dist` of `hash`, as `(index, distance)` pairs in no
+  /// particular order.
+  pub fn find_within(&self, hash: &ImageHash<B>, max_dist: u32) -> Vec<(usize, u32)> {
+    let mut found = vec![];
+    if self.nodes.is_empty() {
+      return found;
+    }
+
+    let mut stack = vec![0];
+    while let Some(cur) = stack.pop() {
+      let node = &self.nodes[cur];
+      let dist = node.hash.dist(hash);
+      if dist <= max_dist {
+        found.push((cur, dist));
+      }
+
+      // by the triangle inequality, only children in this band can be within `max_dist`
+      let (lo, hi) = (dist.saturating_sub(max_dist), dist.saturating_add(max_dist));
+      stack.extend(
+        node
+          .children
+          .iter()
+          .filter(|&&(d, _)| lo <= d && d <= hi)
+          .map(|&(_, child)| child),
+      );
+    }
+
+    found
+  }
+}
+
+impl<'a, B: HashBytes> Default for BkTree<'a, B> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Group hashes so that every hash is within `threshold` of at least one other hash in
+/// its group.
+///
+/// Groups are transitive: if `a` is close to `b` and `b` to `c`, all three are grouped even if
+/// `a` and `c` are far apart. Returns the indices into `hashes` of every group, including
+/// groups of one, sorted by their smallest index.
+pub fn cluster<B: HashBytes>(hashes: &[ImageHash<B>], threshold: u32) -> Vec<Vec<usize>> {
+  let mut tree = BkTree::new();
+  let mut parents: Vec<usize> = (0..hashes.len()).collect();
+
+  for (i, hash) in hashes.iter().enumerate() {
+    // everything inserted before `i` has a smaller index, so each pair is only found once
+    for (j, _) in tree.find_within(hash, threshold) {
+      union(&mut parents, i, j);
+    }
+    tree.insert(hash);
+  }
+
+  let mut groups: Vec<Vec<usize>> = vec![vec![]; hashes.len()];
+  for i in 0..hashes.len() {
+    let root = find(&mut parents, i);
+    groups[root].push(i);
+  }
+
+  // roots are always the smallest index of their group
+  groups.retain(|group| !group.is_empty());
+  groups
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+  while parents[i] != i {
+    // path halving
+    parents[i] = parents[parents[i]];
+    i = parents[i];
+  }
+  i
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+  let (a, b) = (find(parents, a), find(parents, b));
+  // keep the smaller index as the root so groups come out in order
+  let (root, child) = if a < b { (a, b) } else { (b, a) };
+  parents[child] = root;
+}
+
+#[cfg(test)]
+mod test {
+  use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+  use super::{cluster, BkTree};
+  use crate::ImageHash;
+
+  fn random_hashes(len: usize) -> Vec<ImageHash> {
+    let mut rng = SmallRng::seed_from_u64(0xc0ffee);
+    (0..len)
+      .map(|_| ImageHash::from_bytes(&rng.gen::<[u8; 4]>()).unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn find_within_matches_linear_scan() {
+    let hashes = random_hashes(500);
+    let mut tree = BkTree::new();
+    for hash in &hashes {
+      tree.insert(hash);
+    }
+    assert_eq!(tree.len(), 500);
+
+    for query in &hashes[..20] {
+      for max_dist in [0, 4, 12] {
+        let mut found = tree.find_within(query, max_dist);
+        found.sort_unstable();
+        let expected: Vec<_> = hashes
+          .iter()
+          .enumerate()
+          .map(|(i, h)| (i, h.dist(query)))
+          .filter(|&(_, d)| d <= max_dist)
+          .collect();
+        assert_eq!(found, expected);
+      }
+    }
+  }
+
+  #[test]
+  fn cluster_is_transitive() {
+    let hashes: Vec<ImageHash> = [
+      0b0000_0000u8,
+      0b1111_0000,
+      0b0000_0011,
+      0b1111_0001,
+      0b0000_0111,
+    ]
+    .iter()
+    .map(|&b| ImageHash::from_bytes(&[b]).unwrap())
+    .collect();
+
+    // 0 - 2 - 4 are chained by distance 2 and 1, 1 - 3 by distance 1
+    assert_eq!(cluster(&hashes, 2), [vec![0, 2, 4], vec![1, 3]]);
+    assert_eq!(cluster(&hashes, 0), [[0], [1], [2], [3], [4]]);
+    assert_eq!(cluster(&hashes, 8), [vec![0, 1, 2, 3, 4]]);
+  }
+}
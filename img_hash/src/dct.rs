@@ -3,25 +3,49 @@ use std::sync::Arc;
 use rustdct::{DctPlanner, TransformType2And3};
 use transpose::transpose_inplace;
 
+use crate::scalar;
+
 pub const SIZE_MULTIPLIER: u32 = 2;
 pub const SIZE_MULTIPLIER_U: usize = SIZE_MULTIPLIER as usize;
 
 pub struct DctCtxt {
-  row_dct: Arc<dyn TransformType2And3<f32>>,
-  col_dct: Arc<dyn TransformType2And3<f32>>,
+  transform: Transform,
   width: usize,
   height: usize,
 }
 
+enum Transform {
+  Planned {
+    row_dct: Arc<dyn TransformType2And3<f32>>,
+    col_dct: Arc<dyn TransformType2And3<f32>>,
+  },
+  /// Used by deterministic hashing, see the `scalar` module
+  Scalar {
+    row_dct: scalar::Dct,
+    col_dct: scalar::Dct,
+  },
+}
+
 impl DctCtxt {
-  pub fn new(width: u32, height: u32) -> Self {
-    let mut planner = DctPlanner::new();
+  pub fn new(width: u32, height: u32, deterministic: bool) -> Self {
     let width = width as usize * SIZE_MULTIPLIER_U;
     let height = height as usize * SIZE_MULTIPLIER_U;
 
+    let transform = if deterministic {
+      Transform::Scalar {
+        row_dct: scalar::Dct::new(width),
+        col_dct: scalar::Dct::new(height),
+      }
+    } else {
+      let mut planner = DctPlanner::new();
+      Transform::Planned {
+        row_dct: planner.plan_dct2(width),
+        col_dct: planner.plan_dct2(height),
+      }
+    };
+
     DctCtxt {
-      row_dct: planner.plan_dct2(width),
-      col_dct: planner.plan_dct2(height),
+      transform,
       width,
       height,
     }
@@ -44,8 +68,7 @@ impl DctCtxt {
   /// If `self.width * self.height * 2 != packed_2d.len()`
   pub fn dct_2d(&self, mut packed_2d: Vec<f32>) -> Vec<f32> {
     let Self {
-      ref row_dct,
-      ref col_dct,
+      ref transform,
       width,
       height,
     } = *self;
@@ -53,6 +76,14 @@ impl DctCtxt {
     let trunc_len = width * height;
     assert_eq!(trunc_len + self.required_scratch(), packed_2d.len());
 
+    let (row_dct, col_dct) = match transform {
+      Transform::Planned { row_dct, col_dct } => (row_dct, col_dct),
+      Transform::Scalar { row_dct, col_dct } => {
+        scalar::dct_2d(row_dct, col_dct, &mut packed_2d);
+        return packed_2d;
+      }
+    };
+
     {
       let (packed_2d, scratch) = packed_2d.split_at_mut(trunc_len);
 
@@ -71,6 +102,9 @@ impl DctCtxt {
         col_dct.process_dct2_with_scratch(row_in, scratch);
       }
 
+      // NOTE: the matrix is `width` rows of `height` at this point, so for non-square sizes this
+      // doesn't restore the original layout and the cropped coefficients aren't the lowest
+      // frequencies. Correcting it would change every hash computed with DCT preprocessing.
       transpose_inplace(
         packed_2d,
         &mut scratch[..std::cmp::max(width, height)],
@@ -88,12 +122,17 @@ impl DctCtxt {
   }
 
   pub fn required_scratch(&self) -> usize {
-    let transpose_scratch = std::cmp::max(self.width, self.height);
-    let dct_scratch = std::cmp::max(
-      self.row_dct.get_scratch_len(),
-      self.col_dct.get_scratch_len(),
-    );
-    std::cmp::max(transpose_scratch, dct_scratch)
+    match self.transform {
+      Transform::Planned {
+        ref row_dct,
+        ref col_dct,
+      } => {
+        let transpose_scratch = std::cmp::max(self.width, self.height);
+        let dct_scratch = std::cmp::max(row_dct.get_scratch_len(), col_dct.get_scratch_len());
+        std::cmp::max(transpose_scratch, dct_scratch)
+      }
+      Transform::Scalar { .. } => 0,
+    }
   }
 }
 
@@ -0,0 +1,233 @@
+//! Hamming distances from one hash to many.
+
+use crate::{HashBytes, ImageHash};
+
+/// Count the differing bits in the common prefix of `lhs` and `rhs`.
+type Kernel = fn(&[u8], &[u8]) -> u32;
+
+/// Get the distance from `query` to each hash in `hashes`, in the same order.
+///
+/// Equivalent to calling [`ImageHash::dist`] on each pair, but uses the widest popcount the CPU
+/// supports (detected at runtime on x86_64).
+///
+/// ### Note
+/// All hashes must have been calculated with the same configuration; the distances between
+/// hashes of different sizes are meaningless.
+pub fn dist_many<B: HashBytes>(query: &ImageHash<B>, hashes: &[ImageHash<B>]) -> Vec<u32> {
+  let kernel = kernel();
+  let bit_order = query.bit_order();
+  let query = query.as_bytes();
+  let dist = |hash: &[u8]| {
+    let len = query.len().min(hash.len());
+    kernel(&query[..len], &hash[..len])
+  };
+  hashes
+    .iter()
+    .map(|hash| {
+      if hash.bit_order() == bit_order {
+        dist(hash.as_bytes())
+      } else {
+        dist(hash.to_bit_order(bit_order).as_bytes())
+      }
+    })
+    .collect()
+}
+
+/// Get the `k` hashes closest to `query` as `(index, distance)`, nearest first.
+///
+/// Ties are broken by the lower index. Returns fewer than `k` results if there are fewer hashes.
+pub fn top_k<B: HashBytes>(
+  query: &ImageHash<B>,
+  hashes: &[ImageHash<B>],
+  k: usize,
+) -> Vec<(usize, u32)> {
+  let mut dists: Vec<(usize, u32)> = dist_many(query, hashes).into_iter().enumerate().collect();
+  let k = k.min(dists.len());
+  if k == 0 {
+    return vec![];
+  }
+
+  let key = |&(i, dist): &(usize, u32)| (dist, i);
+  if k < dists.len() {
+    dists.select_nth_unstable_by_key(k - 1, key);
+    dists.truncate(k);
+  }
+  dists.sort_unstable_by_key(key);
+  dists
+}
+
+fn kernel() -> Kernel {
+  #[cfg(target_arch = "x86_64")]
+  {
+    if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("popcnt") {
+      return x86::avx2;
+    }
+    if is_x86_feature_detected!("popcnt") {
+      return x86::popcnt;
+    }
+  }
+
+  portable
+}
+
+/// Popcount over `u64` words, then the remaining bytes.
+#[inline(always)]
+pub(crate) fn portable(lhs: &[u8], rhs: &[u8]) -> u32 {
+  // the byte order doesn't matter for counting
+  let (lhs_words, rhs_words) = (lhs.chunks_exact(8), rhs.chunks_exact(8));
+  let tail = lhs_words
+    .remainder()
+    .iter()
+    .zip(rhs_words.remainder())
+    .map(|(l, r)| (l ^ r).count_ones())
+    .sum::<u32>();
+
+  lhs_words
+    .zip(rhs_words)
+    .map(|(l, r)| {
+      let l = u64::from_ne_bytes(l.try_into().unwrap());
+      let r = u64::from_ne_bytes(r.try_into().unwrap());
+      (l ^ r).count_ones()
+    })
+    .sum::<u32>()
+    + tail
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+  use std::arch::x86_64::*;
+
+  // the safe wrappers are only handed out by `kernel()` after checking for the features
+
+  pub(super) fn popcnt(lhs: &[u8], rhs: &[u8]) -> u32 {
+    unsafe { popcnt_impl(lhs, rhs) }
+  }
+
+  pub(super) fn avx2(lhs: &[u8], rhs: &[u8]) -> u32 {
+    unsafe { avx2_impl(lhs, rhs) }
+  }
+
+  #[target_feature(enable = "popcnt")]
+  unsafe fn popcnt_impl(lhs: &[u8], rhs: &[u8]) -> u32 {
+    // compiles to `popcnt` instead of the bit-twiddling fallback
+    super::portable(lhs, rhs)
+  }
+
+  /// Nibble lookup popcount (Muła et al.) over 32-byte blocks.
+  #[target_feature(enable = "avx2,popcnt")]
+  unsafe fn avx2_impl(lhs: &[u8], rhs: &[u8]) -> u32 {
+    debug_assert_eq!(lhs.len(), rhs.len());
+    let blocks = lhs.len() / 32;
+
+    #[rustfmt::skip]
+    let lookup = _mm256_setr_epi8(
+      0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+      0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+    );
+    let low_mask = _mm256_set1_epi8(0x0f);
+    let zero = _mm256_setzero_si256();
+    let mut acc = zero;
+
+    for i in 0..blocks {
+      let l = _mm256_loadu_si256(lhs.as_ptr().add(i * 32) as *const __m256i);
+      let r = _mm256_loadu_si256(rhs.as_ptr().add(i * 32) as *const __m256i);
+      let x = _mm256_xor_si256(l, r);
+      let lo = _mm256_and_si256(x, low_mask);
+      let hi = _mm256_and_si256(_mm256_srli_epi16(x, 4), low_mask);
+      let counts = _mm256_add_epi8(
+        _mm256_shuffle_epi8(lookup, lo),
+        _mm256_shuffle_epi8(lookup, hi),
+      );
+      // at most 8 per byte, summed horizontally into four u64 lanes
+      acc = _mm256_add_epi64(acc, _mm256_sad_epu8(counts, zero));
+    }
+
+    let mut lanes = [0u64; 4];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc);
+    let head = lanes.iter().sum::<u64>() as u32;
+
+    head + super::portable(&lhs[blocks * 32..], &rhs[blocks * 32..])
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::traits::BitSet;
+
+  /// xorshift32, for reproducible test data
+  fn bytes(seed: &mut u32, len: usize) -> Vec<u8> {
+    (0..len)
+      .map(|_| {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        *seed as u8
+      })
+      .collect()
+  }
+
+  fn kernels() -> Vec<(&'static str, Kernel)> {
+    #[allow(unused_mut)]
+    let mut kernels: Vec<(&'static str, Kernel)> = vec![("portable", portable)];
+    #[cfg(target_arch = "x86_64")]
+    {
+      if is_x86_feature_detected!("popcnt") {
+        kernels.push(("popcnt", x86::popcnt));
+      }
+      if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("popcnt") {
+        kernels.push(("avx2", x86::avx2));
+      }
+    }
+    kernels
+  }
+
+  #[test]
+  fn kernels_match_scalar() {
+    let mut seed = 0x9e37_79b9;
+    for len in (0..=100).chain([255, 256, 257, 1000]) {
+      let (lhs, rhs) = (bytes(&mut seed, len), bytes(&mut seed, len));
+      let expected: u32 = lhs
+        .iter()
+        .zip(&rhs)
+        .map(|(l, r)| (l ^ r).count_ones())
+        .sum();
+      for (name, kernel) in kernels() {
+        assert_eq!(kernel(&lhs, &rhs), expected, "{name}, {len} bytes");
+      }
+      assert_eq!(lhs.hamming(&rhs), expected);
+    }
+  }
+
+  #[test]
+  fn many() {
+    let mut seed = 1;
+    let hashes: Vec<ImageHash> = (0..500)
+      .map(|_| ImageHash::from_bytes(&bytes(&mut seed, 40)).unwrap())
+      .collect();
+    let query = &hashes[7];
+
+    let dists = dist_many(query, &hashes);
+    let expected: Vec<u32> = hashes.iter().map(|hash| query.dist(hash)).collect();
+    assert_eq!(dists, expected);
+
+    let mut sorted: Vec<(usize, u32)> = expected.into_iter().enumerate().collect();
+    sorted.sort_by_key(|&(i, dist)| (dist, i));
+    for k in [0, 1, 10, 499, 500, 600] {
+      assert_eq!(top_k(query, &hashes, k), &sorted[..k.min(500)], "k = {k}");
+    }
+    assert_eq!(top_k(query, &hashes, 1), [(7, 0)]);
+  }
+
+  #[test]
+  fn ties() {
+    let hashes: Vec<ImageHash<[u8; 1]>> = [0b11, 0b01, 0b10, 0b00, 0b01]
+      .iter()
+      .map(|&b| ImageHash::from_bytes(&[b]).unwrap())
+      .collect();
+    let query = ImageHash::from_bytes(&[0]).unwrap();
+
+    assert_eq!(top_k(&query, &hashes, 3), [(3, 0), (1, 1), (2, 1)]);
+    assert_eq!(dist_many(&query, &[]), Vec::<u32>::new());
+  }
+}
@@ -0,0 +1,222 @@
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+
+/// A synthetic edit applied to corpus images to produce near-duplicates.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Distortion {
+  /// Re-encode as JPEG with the given quality, from 1 to 100.
+  Jpeg {
+    /// The JPEG quality.
+    quality: u8,
+  },
+  /// Scale both dimensions by the given factor.
+  Resize {
+    /// The scale factor.
+    scale: f32,
+  },
+  /// Remove the given fraction of the width and height from every edge.
+  Crop {
+    /// The fraction removed from each edge.
+    fraction: f32,
+  },
+  /// Add the given value to every channel, clamping at the ends of the range.
+  Brightness {
+    /// The value added to every channel.
+    delta: i32,
+  },
+  /// Draw a line of caption text in the bottom left corner.
+  Text,
+  /// Mirror the image horizontally.
+  FlipHorizontal,
+  /// Mirror the image vertically.
+  FlipVertical,
+  /// Tile translucent text over the whole image.
+  Watermark {
+    /// The opacity of the text, from 0 to 1.
+    opacity: f32,
+  },
+}
+
+impl Distortion {
+  /// The distortions applied when none are specified.
+  pub fn defaults() -> Vec<Distortion> {
+    use Distortion::*;
+
+    vec![
+      Jpeg { quality: 75 },
+      Jpeg { quality: 30 },
+      Resize { scale: 0.5 },
+      Resize { scale: 0.25 },
+      Crop { fraction: 0.05 },
+      Crop { fraction: 0.1 },
+      Brightness { delta: 30 },
+      Brightness { delta: -30 },
+      Text,
+      FlipHorizontal,
+      FlipVertical,
+      Watermark { opacity: 0.3 },
+    ]
+  }
+
+  /// A short, unique name for this distortion, e.g. `jpeg(75)`.
+  pub fn name(&self) -> String {
+    match *self {
+      Distortion::Jpeg { quality } => format!("jpeg({quality})"),
+      Distortion::Resize { scale } => format!("resize({scale})"),
+      Distortion::Crop { fraction } => format!("crop({fraction})"),
+      Distortion::Brightness { delta } => format!("brightness({delta})"),
+      Distortion::Text => "text".into(),
+      Distortion::FlipHorizontal => "fliph".into(),
+      Distortion::FlipVertical => "flipv".into(),
+      Distortion::Watermark { opacity } => format!("watermark({opacity})"),
+    }
+  }
+
+  /// Produce a distorted copy of `img`.
+  pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+    match *self {
+      Distortion::Jpeg { quality } => {
+        let mut buf = vec![];
+        JpegEncoder::new_with_quality(&mut buf, quality)
+          .encode_image(&DynamicImage::ImageRgb8(img.to_rgb8()))
+          .expect("encoding to memory can't fail");
+        image::load(Cursor::new(buf), ImageFormat::Jpeg).expect("decoding our own JPEG failed")
+      }
+      Distortion::Resize { scale } => {
+        let width = ((img.width() as f32 * scale).round() as u32).max(1);
+        let height = ((img.height() as f32 * scale).round() as u32).max(1);
+        img.resize_exact(width, height, FilterType::Triangle)
+      }
+      Distortion::Crop { fraction } => {
+        let x = (img.width() as f32 * fraction) as u32;
+        let y = (img.height() as f32 * fraction) as u32;
+        let width = img.width().saturating_sub(x * 2).max(1);
+        let height = img.height().saturating_sub(y * 2).max(1);
+        img.crop_imm(x, y, width, height)
+      }
+      Distortion::Brightness { delta } => img.brighten(delta),
+      Distortion::Text => {
+        let mut img = img.to_rgba8();
+        let scale = (img.height() / 60).max(1);
+        let (x, y) = (scale * 4, img.height().saturating_sub(scale * 12));
+        draw_text(&mut img, x + scale, y + scale, scale, CAPTION, BLACK, 1.);
+        draw_text(&mut img, x, y, scale, CAPTION, WHITE, 1.);
+        DynamicImage::ImageRgba8(img)
+      }
+      Distortion::FlipHorizontal => DynamicImage::ImageRgba8(imageops::flip_horizontal(img)),
+      Distortion::FlipVertical => DynamicImage::ImageRgba8(imageops::flip_vertical(img)),
+      Distortion::Watermark { opacity } => {
+        let mut img = img.to_rgba8();
+        let scale = (img.width().min(img.height()) / 80).max(1);
+        let (step_x, step_y) = (text_width(WATERMARK, scale) * 3 / 2, scale * 24);
+        for (row, y) in (0..img.height()).step_by(step_y as usize).enumerate() {
+          // stagger the rows so the text isn't aligned in columns
+          let offset = if row % 2 == 0 { 0 } else { step_x / 2 };
+          for x in (0..img.width() + offset).step_by(step_x as usize) {
+            let x = x.wrapping_sub(offset);
+            draw_text(&mut img, x, y, scale, WATERMARK, WHITE, opacity);
+          }
+        }
+        DynamicImage::ImageRgba8(img)
+      }
+    }
+  }
+}
+
+const CAPTION: &str = "TRANSLATED 2023";
+const WATERMARK: &str = "SAMPLE";
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+fn text_width(text: &str, scale: u32) -> u32 {
+  text.len() as u32 * (GLYPH_WIDTH + 1) * scale
+}
+
+/// Draw `text` with its top left corner at `x, y`, skipping pixels that are out of bounds.
+fn draw_text(
+  img: &mut RgbaImage,
+  x: u32,
+  y: u32,
+  scale: u32,
+  text: &str,
+  color: Rgba<u8>,
+  opacity: f32,
+) {
+  for (i, c) in text.chars().enumerate() {
+    let glyph_x = x.wrapping_add(i as u32 * (GLYPH_WIDTH + 1) * scale);
+    for (row, bits) in glyph(c).iter().enumerate() {
+      for col in 0..GLYPH_WIDTH {
+        if bits >> (GLYPH_WIDTH - 1 - col) & 1 == 0 {
+          continue;
+        }
+
+        for dy in 0..scale {
+          for dx in 0..scale {
+            let px = glyph_x.wrapping_add(col * scale + dx);
+            let py = y + row as u32 * scale + dy;
+            if let Some(pixel) = img.get_pixel_mut_checked(px, py) {
+              blend(pixel, color, opacity);
+            }
+          }
+        }
+      }
+    }
+  }
+}
+
+fn blend(pixel: &mut Rgba<u8>, color: Rgba<u8>, opacity: f32) {
+  for (dst, src) in pixel.0.iter_mut().zip(color.0).take(3) {
+    *dst = (*dst as f32 * (1. - opacity) + src as f32 * opacity).round() as u8;
+  }
+}
+
+/// A 5x7 bitmap font, one byte per row with the leftmost pixel in bit 4.
+#[rustfmt::skip]
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+  match c.to_ascii_uppercase() {
+    'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+    'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+    'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+    'D' => [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+    'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+    'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+    'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+    'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+    'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+    'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+    'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+    'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+    'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+    'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+    'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+    'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+    'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+    'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+    'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+    'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+    'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+    'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+    'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+    'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+    'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+    '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+    '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+    '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+    '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+    '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+    '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+    '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+    '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+    '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+    _ => [0; GLYPH_HEIGHT as usize],
+  }
+}
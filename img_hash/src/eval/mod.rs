@@ -0,0 +1,441 @@
+//! Measure how well hash configurations separate near-duplicates from unrelated images.
+//!
+//! Every image added to an [`Evaluation`] is hashed as-is and after each configured
+//! [`Distortion`]. The distances between an image and its own distortions ("intra") are the
+//! ones a lookup should accept, the distances between different images ("inter") are the ones it
+//! should reject. From these the [`Report`] derives an ROC and precision/recall curve and a
+//! recommended threshold for every configuration.
+//!
+//! Requires the `eval` feature. The `hash_eval` binary runs this over a corpus directory.
+use std::io::{self, Write};
+
+use image::DynamicImage;
+
+use crate::{HashAlg, Hasher, HasherConfig, ImageHash};
+
+pub use self::distort::Distortion;
+
+mod distort;
+
+/// Collects hashes of a corpus and its distortions for a set of hasher configurations.
+pub struct Evaluation {
+  configs: Vec<(String, Hasher)>,
+  distortions: Vec<Distortion>,
+  /// `[config][image]`
+  originals: Vec<Vec<ImageHash>>,
+  /// `[config][image][distortion]`
+  distorted: Vec<Vec<Vec<ImageHash>>>,
+}
+
+impl Evaluation {
+  /// Create an evaluation of the given named hashers.
+  pub fn new(configs: Vec<(String, Hasher)>, distortions: Vec<Distortion>) -> Self {
+    let len = configs.len();
+    Evaluation {
+      configs,
+      distortions,
+      originals: vec![vec![]; len],
+      distorted: vec![vec![]; len],
+    }
+  }
+
+  /// The configurations evaluated by default: every algorithm with `width x height` bits,
+  /// with and without DCT preprocessing for the algorithms that support it, and Blockhash with
+  /// and without Difference of Gaussians preprocessing.
+  pub fn default_configs(width: u32, height: u32) -> Vec<(String, Hasher)> {
+    use HashAlg::*;
+
+    let mut configs = vec![];
+    for alg in [Mean, Gradient, VertGradient, DoubleGradient, Blockhash] {
+      for preproc in [false, true] {
+        let config = HasherConfig::new().hash_alg(alg).hash_size(width, height);
+        let (name, config) = match (alg, preproc) {
+          (Blockhash, false) => (format!("blockhash:{width}x{height}"), config),
+          (Blockhash, true) => (
+            format!("blockhash:{width}x{height}:dog(5,10)"),
+            config.preproc_diff_gauss(),
+          ),
+          (alg, false) => (format!("{alg:?}:{width}x{height}:lanczos3"), config),
+          (alg, true) => (
+            format!("dct-{alg:?}:{width}x{height}:lanczos3"),
+            config.preproc_dct(),
+          ),
+        };
+        configs.push((name.to_lowercase(), config.to_hasher()));
+      }
+    }
+    configs
+  }
+
+  /// Hash `img` and all its distortions with every configuration.
+  pub fn add_image(&mut self, img: &DynamicImage) {
+    let distorted: Vec<_> = self.distortions.iter().map(|d| d.apply(img)).collect();
+
+    for (i, (_, hasher)) in self.configs.iter().enumerate() {
+      self.originals[i].push(hasher.hash_image(img));
+      self.distorted[i].push(distorted.iter().map(|d| hasher.hash_image(d)).collect());
+    }
+  }
+
+  /// Get the number of images added so far.
+  pub fn len(&self) -> usize {
+    self.originals.first().map_or(0, Vec::len)
+  }
+
+  /// Returns `true` if no images have been added.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Compute the distance statistics for every configuration.
+  pub fn report(&self) -> Report {
+    let configs = self
+      .configs
+      .iter()
+      .enumerate()
+      .map(|(i, (name, _))| self.config_report(name, &self.originals[i], &self.distorted[i]))
+      .collect();
+
+    Report {
+      images: self.len(),
+      distortions: self.distortions.iter().map(Distortion::name).collect(),
+      configs,
+    }
+  }
+
+  fn config_report(
+    &self,
+    name: &str,
+    originals: &[ImageHash],
+    distorted: &[Vec<ImageHash>],
+  ) -> ConfigReport {
+    let bits = originals.first().map_or(0, |h| h.as_bytes().len() * 8);
+
+    let mut intra = vec![0; bits + 1];
+    let mut per_distortion = vec![vec![0; bits + 1]; self.distortions.len()];
+    for (original, distorted) in originals.iter().zip(distorted) {
+      for (d, hash) in distorted.iter().enumerate() {
+        let dist = original.dist(hash) as usize;
+        intra[dist] += 1;
+        per_distortion[d][dist] += 1;
+      }
+    }
+
+    let mut inter = vec![0; bits + 1];
+    for (i, a) in originals.iter().enumerate() {
+      for b in &originals[i + 1..] {
+        inter[a.dist(b) as usize] += 1;
+      }
+    }
+
+    let curve = curve(&intra, &inter);
+    let recommended = recommend(&curve);
+
+    let distortions = self
+      .distortions
+      .iter()
+      .zip(per_distortion)
+      .map(|(d, histogram)| DistortionReport {
+        name: d.name(),
+        mean: mean(&histogram),
+        accepted: accepted_fraction(&histogram, recommended),
+        histogram,
+      })
+      .collect();
+
+    ConfigReport {
+      config: name.to_owned(),
+      bits,
+      recommended_threshold: recommended,
+      auc: auc(&curve),
+      intra_mean: mean(&intra),
+      inter_mean: mean(&inter),
+      intra,
+      inter,
+      distortions,
+      curve,
+    }
+  }
+}
+
+/// The results of an [`Evaluation`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+  /// The number of corpus images.
+  pub images: usize,
+  /// The names of the applied distortions.
+  pub distortions: Vec<String>,
+  /// The results for each configuration.
+  pub configs: Vec<ConfigReport>,
+}
+
+/// The results of an [`Evaluation`] for one configuration.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigReport {
+  /// The name of the configuration.
+  pub config: String,
+  /// The number of bits in the hash, including padding.
+  pub bits: usize,
+  /// The threshold maximizing Youden's J statistic, `tpr - fpr`.
+  pub recommended_threshold: u32,
+  /// The area under the ROC curve.
+  pub auc: f64,
+  /// The mean distance between an image and its distortions.
+  pub intra_mean: f64,
+  /// The mean distance between different images.
+  pub inter_mean: f64,
+  /// Histogram of the distances between an image and its distortions, indexed by distance.
+  pub intra: Vec<u64>,
+  /// Histogram of the distances between different images, indexed by distance.
+  pub inter: Vec<u64>,
+  /// The intra distances broken down by distortion.
+  pub distortions: Vec<DistortionReport>,
+  /// The ROC and precision/recall curve, one point per threshold.
+  pub curve: Vec<CurvePoint>,
+}
+
+/// The distances between images and one of their distortions.
+#[derive(Debug, Clone, Serialize)]
+pub struct DistortionReport {
+  /// The name of the distortion.
+  pub name: String,
+  /// The mean distance.
+  pub mean: f64,
+  /// The fraction of distances within the recommended threshold.
+  pub accepted: f64,
+  /// Histogram of the distances, indexed by distance.
+  pub histogram: Vec<u64>,
+}
+
+/// The classification performance when accepting distances up to `threshold`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CurvePoint {
+  /// The maximum accepted distance.
+  pub threshold: u32,
+  /// The fraction of intra distances accepted, also known as recall.
+  pub tpr: f64,
+  /// The fraction of inter distances accepted.
+  pub fpr: f64,
+  /// The fraction of accepted distances that are intra distances.
+  pub precision: f64,
+  /// The harmonic mean of precision and recall.
+  pub f1: f64,
+}
+
+impl Report {
+  /// Write a summary as CSV, one row per configuration.
+  pub fn write_summary_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+    writeln!(
+      w,
+      "config,bits,recommended_threshold,auc,intra_mean,inter_mean"
+    )?;
+    for c in &self.configs {
+      writeln!(
+        w,
+        "{},{},{},{},{},{}",
+        c.config, c.bits, c.recommended_threshold, c.auc, c.intra_mean, c.inter_mean
+      )?;
+    }
+    Ok(())
+  }
+
+  /// Write the distance histograms as CSV, one row per configuration and distance.
+  pub fn write_histograms_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+    write!(w, "config,distance,intra,inter")?;
+    for name in &self.distortions {
+      write!(w, ",\"{name}\"")?;
+    }
+    writeln!(w)?;
+
+    for c in &self.configs {
+      for dist in 0..=c.bits {
+        write!(
+          w,
+          "{},{},{},{}",
+          c.config, dist, c.intra[dist], c.inter[dist]
+        )?;
+        for d in &c.distortions {
+          write!(w, ",{}", d.histogram[dist])?;
+        }
+        writeln!(w)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Write the ROC and precision/recall curves as CSV, one row per configuration and threshold.
+  pub fn write_curves_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+    writeln!(w, "config,threshold,tpr,fpr,precision,f1")?;
+    for c in &self.configs {
+      for p in &c.curve {
+        writeln!(
+          w,
+          "{},{},{},{},{},{}",
+          c.config, p.threshold, p.tpr, p.fpr, p.precision, p.f1
+        )?;
+      }
+    }
+    Ok(())
+  }
+}
+
+fn curve(intra: &[u64], inter: &[u64]) -> Vec<CurvePoint> {
+  let intra_total = intra.iter().sum::<u64>().max(1) as f64;
+  let inter_total = inter.iter().sum::<u64>().max(1) as f64;
+
+  let (mut tp, mut fp) = (0, 0);
+  intra
+    .iter()
+    .zip(inter)
+    .enumerate()
+    .map(|(threshold, (&intra, &inter))| {
+      tp += intra;
+      fp += inter;
+
+      let tpr = tp as f64 / intra_total;
+      let fpr = fp as f64 / inter_total;
+      // nothing accepted means nothing wrongly accepted
+      let precision = if tp + fp == 0 {
+        1.
+      } else {
+        tp as f64 / (tp + fp) as f64
+      };
+      let f1 = if precision + tpr == 0. {
+        0.
+      } else {
+        2. * precision * tpr / (precision + tpr)
+      };
+
+      CurvePoint {
+        threshold: threshold as u32,
+        tpr,
+        fpr,
+        precision,
+        f1,
+      }
+    })
+    .collect()
+}
+
+/// Pick the threshold maximizing `tpr - fpr`, preferring the lowest on ties.
+fn recommend(curve: &[CurvePoint]) -> u32 {
+  curve
+    .iter()
+    .fold(None::<&CurvePoint>, |best, p| match best {
+      Some(best) if best.tpr - best.fpr >= p.tpr - p.fpr => Some(best),
+      _ => Some(p),
+    })
+    .map_or(0, |p| p.threshold)
+}
+
+fn auc(curve: &[CurvePoint]) -> f64 {
+  let mut prev = (0., 0.);
+  let mut area = 0.;
+  for p in curve {
+    area += (p.fpr - prev.0) * (p.tpr + prev.1) / 2.;
+    prev = (p.fpr, p.tpr);
+  }
+  // close the curve at (1, 1)
+  area + (1. - prev.0) * (1. + prev.1) / 2.
+}
+
+fn mean(histogram: &[u64]) -> f64 {
+  let total = histogram.iter().sum::<u64>();
+  if total == 0 {
+    return 0.;
+  }
+
+  let sum = histogram
+    .iter()
+    .enumerate()
+    .map(|(dist, &count)| dist as u64 * count)
+    .sum::<u64>();
+  sum as f64 / total as f64
+}
+
+fn accepted_fraction(histogram: &[u64], threshold: u32) -> f64 {
+  let total = histogram.iter().sum::<u64>();
+  if total == 0 {
+    return 0.;
+  }
+
+  let accepted = histogram.iter().take(threshold as usize + 1).sum::<u64>();
+  accepted as f64 / total as f64
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn curve_and_threshold() {
+    // intra distances are all <= 2, inter distances are all >= 2
+    let intra = [3, 2, 1, 0, 0];
+    let inter = [0, 0, 1, 3, 4];
+    let curve = curve(&intra, &inter);
+
+    assert_eq!(curve.len(), 5);
+    assert_eq!(curve[1].tpr, 5. / 6.);
+    assert_eq!(curve[1].fpr, 0.);
+    assert_eq!(curve[1].precision, 1.);
+    assert_eq!(curve[2].tpr, 1.);
+    assert_eq!(curve[2].fpr, 1. / 8.);
+    assert_eq!(curve[4].fpr, 1.);
+
+    assert_eq!(recommend(&curve), 2);
+    assert!(auc(&curve) > 0.95);
+    assert_eq!(mean(&intra), 4. / 6.);
+    assert_eq!(accepted_fraction(&intra, 0), 0.5);
+  }
+
+  #[test]
+  fn distortions_keep_dimensions() {
+    let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(120, 90, |x, y| {
+      image::Rgb([x as u8, y as u8, (x ^ y) as u8])
+    }));
+
+    for d in Distortion::defaults() {
+      let out = d.apply(&img);
+      match d {
+        Distortion::Resize { scale } => {
+          assert_eq!(out.width(), (120. * scale) as u32);
+          assert_eq!(out.height(), (90. * scale).round() as u32);
+        }
+        Distortion::Crop { .. } => assert!(out.width() < 120 && out.height() < 90),
+        _ => assert_eq!((out.width(), out.height()), (120, 90), "{}", d.name()),
+      }
+      assert_ne!(out.to_rgb8(), img.to_rgb8(), "{} had no effect", d.name());
+    }
+  }
+
+  #[test]
+  fn evaluation() {
+    let imgs = (0..4u32).map(|seed| {
+      DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+        let v = (x * (seed + 1) + y * (3 - seed)) as u8;
+        image::Rgb([v, v.wrapping_mul(3), v ^ 0x55])
+      }))
+    });
+
+    let mut eval = Evaluation::new(
+      Evaluation::default_configs(8, 8),
+      vec![Distortion::Jpeg { quality: 90 }],
+    );
+    imgs.for_each(|img| eval.add_image(&img));
+    assert_eq!(eval.len(), 4);
+
+    let report = eval.report();
+    assert_eq!(report.configs.len(), 10);
+    for c in &report.configs {
+      // `DoubleGradient` produces fewer bits
+      assert!(c.bits <= 64, "{}", c.config);
+      assert_eq!(c.intra.iter().sum::<u64>(), 4);
+      assert_eq!(c.inter.iter().sum::<u64>(), 6);
+      assert_eq!(c.curve.len(), c.bits + 1);
+    }
+
+    let mut csv = vec![];
+    report.write_summary_csv(&mut csv).unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 11);
+  }
+}
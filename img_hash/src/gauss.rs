@@ -0,0 +1,195 @@
+//! Fast approximation of Difference of Gaussians preprocessing.
+//!
+//! Each Gaussian is approximated by three successive box blurs (which converge to a Gaussian
+//! by the central limit theorem), applied separably on the luma plane. A box blur costs the
+//! same for any radius thanks to running sums, so this is `O(width * height)` regardless of
+//! the sigmas, unlike `imageops::blur`.
+//!
+//! Only integer arithmetic is used after the box sizes are chosen, so the output is identical
+//! on every platform.
+
+use image::GrayImage;
+
+/// Passes of box blur per Gaussian.
+const PASSES: usize = 3;
+
+/// Standard deviation of a Gaussian truncated at ±2 sigma, relative to the untruncated one.
+const TRUNCATED_SIGMA: f32 = 0.8796;
+
+/// Fractional bits kept between passes.
+const FRAC_BITS: u32 = 8;
+
+/// Blur `img` with both sigmas and subtract the second result from the first, wrapping like
+/// [`DiffImage::diff_inplace`](crate::DiffImage::diff_inplace).
+pub(crate) fn diff_gauss(img: &GrayImage, sigma_a: f32, sigma_b: f32) -> GrayImage {
+  let (width, height) = img.dimensions();
+  let plane: Vec<u32> = img
+    .as_raw()
+    .iter()
+    .map(|&x| u32::from(x) << FRAC_BITS)
+    .collect();
+
+  // `imageops::blur` cuts its kernel off at 2 sigma, which narrows the Gaussian
+  let (sigma_a, sigma_b) = (sigma_a * TRUNCATED_SIGMA, sigma_b * TRUNCATED_SIGMA);
+  let blur_a = blur(&plane, width as usize, height as usize, sigma_a);
+  let blur_b = blur(&plane, width as usize, height as usize, sigma_b);
+
+  let diff = blur_a
+    .iter()
+    .zip(&blur_b)
+    .map(|(&a, &b)| to_u8(a).wrapping_sub(to_u8(b)))
+    .collect();
+
+  GrayImage::from_raw(width, height, diff).unwrap()
+}
+
+fn to_u8(x: u32) -> u8 {
+  ((x + (1 << (FRAC_BITS - 1))) >> FRAC_BITS).min(255) as u8
+}
+
+fn blur(plane: &[u32], width: usize, height: usize, sigma: f32) -> Vec<u32> {
+  let mut src = plane.to_owned();
+  let mut dst = vec![0; plane.len()];
+
+  for radius in box_radii(sigma) {
+    box_blur_h(&src, &mut dst, width, radius);
+    box_blur_v(&dst, &mut src, width, height, radius);
+  }
+
+  src
+}
+
+/// The radii of the box blurs approximating a Gaussian with `sigma`.
+///
+/// From W. Jarosz, "Fast Image Convolutions" (2001): use boxes of the two odd widths around
+/// the ideal width, with the count of each chosen so the variances add up to `sigma²`.
+fn box_radii(sigma: f32) -> [usize; PASSES] {
+  let n = PASSES as f64;
+  let var = 12. * f64::from(sigma) * f64::from(sigma);
+
+  let ideal = (var / n + 1.).sqrt();
+  let mut lower = ideal.floor() as i64;
+  if lower % 2 == 0 {
+    lower -= 1;
+  }
+  let lower = lower.max(1);
+  let l = lower as f64;
+  let num_lower = ((var - n * l * l - 4. * n * l - 3. * n) / (-4. * l - 4.)).round() as usize;
+
+  let mut radii = [0; PASSES];
+  for (i, radius) in radii.iter_mut().enumerate() {
+    let width = if i < num_lower { lower } else { lower + 2 };
+    *radius = (width / 2) as usize;
+  }
+  radii
+}
+
+/// Box blur each row. Near the edges only the pixels inside the image are averaged.
+fn box_blur_h(src: &[u32], dst: &mut [u32], width: usize, radius: usize) {
+  let last = width - 1;
+
+  for (src, dst) in src.chunks_exact(width).zip(dst.chunks_exact_mut(width)) {
+    let mut sum: u32 = src[..=radius.min(last)].iter().sum();
+
+    for x in 0..width {
+      let count = ((x + radius).min(last) + 1 - x.saturating_sub(radius)) as u32;
+      dst[x] = (sum + count / 2) / count;
+
+      if x + radius < last {
+        sum += src[x + radius + 1];
+      }
+      if x >= radius {
+        sum -= src[x - radius];
+      }
+    }
+  }
+}
+
+/// Box blur each column like [`box_blur_h`], a row at a time to stay cache friendly.
+fn box_blur_v(src: &[u32], dst: &mut [u32], width: usize, height: usize, radius: usize) {
+  let last = height - 1;
+  let row = |y: usize| &src[y * width..][..width];
+
+  let mut sums = vec![0; width];
+  for y in 0..=radius.min(last) {
+    sums.iter_mut().zip(row(y)).for_each(|(s, &x)| *s += x);
+  }
+
+  for (y, dst) in dst.chunks_exact_mut(width).enumerate() {
+    let count = ((y + radius).min(last) + 1 - y.saturating_sub(radius)) as u32;
+    dst
+      .iter_mut()
+      .zip(&sums)
+      .for_each(|(d, &s)| *d = (s + count / 2) / count);
+
+    if y + radius < last {
+      sums
+        .iter_mut()
+        .zip(row(y + radius + 1))
+        .for_each(|(s, &x)| *s += x);
+    }
+    if y >= radius {
+      sums
+        .iter_mut()
+        .zip(row(y - radius))
+        .for_each(|(s, &x)| *s -= x);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn box_radii_variance() {
+    for sigma in [0.5f32, 1., 2.5, 5., 10., 33.3] {
+      let radii = box_radii(sigma);
+      // the variance of a box of width `w` is `(w² - 1) / 12`
+      let var: f32 = radii
+        .iter()
+        .map(|&r| ((2 * r + 1).pow(2) - 1) as f32 / 12.)
+        .sum();
+      assert!((var.sqrt() - sigma).abs() <= 0.5, "{sigma}: {radii:?}");
+    }
+    assert_eq!(box_radii(0.), [0; 3]);
+  }
+
+  #[test]
+  fn blur_preserves_flat_and_mass() {
+    let flat = vec![200 << FRAC_BITS; 30 * 20];
+    assert_eq!(blur(&flat, 30, 20, 4.), flat);
+
+    // a single bright pixel in the middle spreads out but keeps (almost) all its mass
+    let mut dot = vec![0; 61 * 61];
+    dot[30 * 61 + 30] = 255 << FRAC_BITS;
+    let blurred = blur(&dot, 61, 61, 3.);
+    let mass: u32 = blurred.iter().sum();
+    assert!(mass.abs_diff(255 << FRAC_BITS) < 61 * 61, "{mass}");
+    let max = blurred.iter().copied().max().unwrap();
+    assert_eq!(blurred[30 * 61 + 30], max);
+    assert_eq!(blurred[30 * 61 + 29], blurred[30 * 61 + 31]);
+    assert_eq!(blurred[29 * 61 + 30], blurred[30 * 61 + 29]);
+  }
+
+  #[test]
+  fn close_to_gaussian() {
+    let img = GrayImage::from_fn(97, 64, |x, y| {
+      [if (x / 16 + y / 16) % 2 == 0 { 220 } else { 30 }].into()
+    });
+
+    for sigma in [1.5f32, 3., 6.] {
+      let plane: Vec<u32> = img.iter().map(|&x| u32::from(x) << FRAC_BITS).collect();
+      let fast = blur(&plane, 97, 64, sigma);
+      let exact = image::imageops::blur(&img, sigma / TRUNCATED_SIGMA);
+
+      let err = fast
+        .iter()
+        .zip(exact.iter())
+        .map(|(&f, &e)| i32::from(to_u8(f)).abs_diff(i32::from(e)))
+        .max()
+        .unwrap();
+      assert!(err <= 12, "sigma {sigma}: max error {err}");
+    }
+  }
+}
@@ -28,6 +28,7 @@ extern crate serde;
 use std::borrow::Cow;
 use std::fmt;
 use std::marker::PhantomData;
+use std::ops;
 
 use base64::Engine;
 use fr::resize_gray;
@@ -36,15 +37,35 @@ use image::GrayImage;
 use serde::{Deserialize, Serialize};
 
 pub use alg::HashAlg;
+#[cfg(feature = "cache")]
+pub use cache::{FileError, FileHash, FileHasher, HashCache};
+pub use cluster::{cluster, BkTree};
 use dct::DctCtxt;
+pub use distance::{dist_many, top_k};
+pub use sequence::HashSequence;
+pub use spec::ParseConfigError;
 pub(crate) use traits::BitSet;
-pub use traits::{DiffImage, HashBytes, Image};
+pub use traits::{DiffImage, HashBits, HashBytes, Image};
+pub use trim::Rect;
 
 mod dct;
 
 mod alg;
+#[cfg(feature = "cache")]
+pub mod cache;
+mod cluster;
+mod distance;
+#[cfg(feature = "eval")]
+pub mod eval;
 mod fr;
+mod gauss;
+#[cfg(feature = "jpeg")]
+mod jpeg;
+mod scalar;
+mod sequence;
+mod spec;
 mod traits;
+mod trim;
 
 /// **Start here**. Configuration builder for [`Hasher`](::Hasher).
 ///
@@ -106,6 +127,10 @@ mod traits;
 /// let config = HasherConfig::with_bytes_type::<[u8; 8]>();
 /// ```
 ///
+/// ### String Form
+/// Configs can be parsed from and printed as compact strings like `dct-gradient:10x10`, e.g. to
+/// store them next to hashes. See the [`FromStr`](std::str::FromStr) impl for the grammar.
+///
 #[derive(Serialize, Deserialize)]
 pub struct HasherConfig<B = Box<[u8]>> {
   width: u32,
@@ -115,6 +140,14 @@ pub struct HasherConfig<B = Box<[u8]>> {
   resize_filter: FilterType,
   dct: bool,
   hash_alg: HashAlg,
+  #[serde(default)]
+  deterministic: bool,
+  #[serde(default)]
+  bit_order: BitOrder,
+  #[serde(default)]
+  gauss_mode: GaussMode,
+  #[serde(default)]
+  trim_tolerance: Option<u8>,
   _bytes_type: PhantomData<B>,
 }
 
@@ -142,6 +175,10 @@ impl HasherConfig<Box<[u8]>> {
       resize_filter: FilterType::Lanczos3,
       dct: false,
       hash_alg: HashAlg::Gradient,
+      deterministic: false,
+      bit_order: BitOrder::LsbFirst,
+      gauss_mode: GaussMode::Exact,
+      trim_tolerance: None,
       _bytes_type: PhantomData,
     }
   }
@@ -263,6 +300,9 @@ impl<B: HashBytes> HasherConfig<B> {
   /// * https://en.wikipedia.org/wiki/Difference_of_Gaussians
   /// * http://homepages.inf.ed.ac.uk/rbf/HIPR2/log.htm
   /// (Difference of Gaussians is an approximation of a Laplacian of Gaussian filter)
+  ///
+  /// Blurring large images is slow; see [`.gauss_mode()`](#method.gauss_mode) for a faster
+  /// approximation.
   #[must_use]
   pub fn preproc_diff_gauss_sigmas(self, sigma_a: f32, sigma_b: f32) -> Self {
     Self {
@@ -271,11 +311,75 @@ impl<B: HashBytes> HasherConfig<B> {
     }
   }
 
+  /// Compute hashes that are bit-identical on every target, e.g. native and `wasm32`.
+  ///
+  /// By default resizing and the DCT use SIMD where available and `f32` math from the
+  /// platform, whose rounding differs between targets and can flip bits that are close to the
+  /// threshold. In deterministic mode both are replaced by scalar implementations with a fixed
+  /// order of operations: resizing uses fixed-point arithmetic and the DCT is computed directly
+  /// in `f64` with coefficients that don't depend on the platform's libm.
+  ///
+  /// The resulting hashes almost always equal the default ones, but this is not guaranteed;
+  /// don't mix hashes from both modes when looking up exact matches. Deterministic mode is
+  /// slower, especially with DCT preprocessing on large hash sizes.
+  ///
+  /// ### Note
+  /// [Difference of Gaussians](#method.preproc_diff_gauss_sigmas) preprocessing is only
+  /// covered by this guarantee with [`GaussMode::Fast`].
+  #[must_use]
+  pub fn deterministic(self) -> Self {
+    Self {
+      deterministic: true,
+      ..self
+    }
+  }
+
+  /// Set the order in which the bits of a hash are packed into its bytes.
+  ///
+  /// Defaults to [`BitOrder::LsbFirst`] for compatibility with existing hashes. Choose
+  /// [`BitOrder::MsbFirst`] for hashes that match most other perceptual hash implementations
+  /// when printed as hex.
+  #[must_use]
+  pub fn bit_order(self, bit_order: BitOrder) -> Self {
+    Self { bit_order, ..self }
+  }
+
+  /// Set how [Difference of Gaussians](#method.preproc_diff_gauss_sigmas) preprocessing blurs
+  /// the image. Has no effect unless it is enabled.
+  ///
+  /// Defaults to [`GaussMode::Exact`] for compatibility with existing hashes.
+  /// [`GaussMode::Fast`] is much faster on large images; for grayscale images the resulting
+  /// hashes are usually within a few bits of the exact ones, but as it blurs the luma instead of
+  /// each color channel, hashes of colored images can differ in up to a fifth of their bits.
+  #[must_use]
+  pub fn gauss_mode(self, gauss_mode: GaussMode) -> Self {
+    Self { gauss_mode, ..self }
+  }
+
+  /// Crop near-uniform borders off images before hashing, e.g. the white or black letterboxing
+  /// added by scanners, screenshots and reader apps.
+  ///
+  /// Each side is trimmed while its outermost rows or columns are uniform: all of their luma
+  /// values are within `tolerance` of the mean of the outermost line. Images that are uniform
+  /// throughout are hashed as they are. Use
+  /// [`Hasher::hash_image_with_crop()`](struct.Hasher.html#method.hash_image_with_crop) to find
+  /// out what was hashed.
+  ///
+  /// Content with a uniform edge, such as a clear sky, is trimmed as well; keep `tolerance` low
+  /// to only remove borders that were added to the image.
+  #[must_use]
+  pub fn preproc_trim_borders(self, tolerance: u8) -> Self {
+    Self {
+      trim_tolerance: Some(tolerance),
+      ..self
+    }
+  }
+
   /// Create a [`Hasher`](struct.Hasher.html) from this config which can be used to hash images.
   ///
   /// ### Panics
   /// If the chosen hash size (`width x height`, rounded for the algorithm if necessary)
-  /// is too large for the chosen container type (`B::max_bits()`).
+  /// is zero or too large for the chosen container type (`B::max_bits()`).
   pub fn to_hasher(&self) -> Hasher<B> {
     let Self {
       hash_alg,
@@ -284,21 +388,27 @@ impl<B: HashBytes> HasherConfig<B> {
       gauss_sigmas,
       resize_filter,
       dct,
+      deterministic,
+      bit_order,
+      gauss_mode,
+      trim_tolerance,
       ..
     } = *self;
 
-    let (width, height) = hash_alg.round_hash_size(width, height);
-
     assert!(
-      (width * height) as usize <= B::max_bits(),
-      "hash size too large for container: {width} x {height}",
+      width > 0 && height > 0,
+      "hash size must not be zero: {width} x {height}"
     );
+    let (width, height) = hash_alg
+      .round_hash_size(width, height)
+      .filter(|&(width, height)| fits::<B>(width, height))
+      .unwrap_or_else(|| panic!("hash size too large for container: {width} x {height}"));
 
     // Blockhash doesn't resize the image so don't waste time calculating coefficients
-    let dct_coeffs = if dct && hash_alg != HashAlg::Blockhash {
+    let dct_coeffs = if dct && !matches!(hash_alg, HashAlg::Blockhash | HashAlg::LegacyBlockhash) {
       // calculate the coefficients based on the resize dimensions
       let (dct_width, dct_height) = hash_alg.resize_dimensions(width, height);
-      Some(DctCtxt::new(dct_width, dct_height))
+      Some(DctCtxt::new(dct_width, dct_height, deterministic))
     } else {
       None
     };
@@ -306,12 +416,16 @@ impl<B: HashBytes> HasherConfig<B> {
     Hasher {
       ctxt: HashCtxt {
         gauss_sigmas,
+        gauss_mode,
         dct_ctxt: dct_coeffs,
         width,
         height,
         resize_filter,
+        deterministic,
       },
       hash_alg,
+      bit_order,
+      trim_tolerance,
       bytes_type: PhantomData,
     }
   }
@@ -327,6 +441,10 @@ impl<B> fmt::Debug for HasherConfig<B> {
       .field("resize_filter", &debug_filter_type(&self.resize_filter))
       .field("gauss_sigmas", &self.gauss_sigmas)
       .field("use_dct", &self.dct)
+      .field("deterministic", &self.deterministic)
+      .field("bit_order", &self.bit_order)
+      .field("gauss_mode", &self.gauss_mode)
+      .field("trim_tolerance", &self.trim_tolerance)
       .finish()
   }
 }
@@ -337,6 +455,8 @@ impl<B> fmt::Debug for HasherConfig<B> {
 pub struct Hasher<B = Box<[u8]>> {
   ctxt: HashCtxt,
   hash_alg: HashAlg,
+  bit_order: BitOrder,
+  trim_tolerance: Option<u8>,
   bytes_type: PhantomData<B>,
 }
 
@@ -346,10 +466,37 @@ where
 {
   /// Calculate a hash for the given image with the configured options.
   pub fn hash_image<I: Image>(&self, img: &I) -> ImageHash<B> {
-    let hash = self.hash_alg.hash_image(&self.ctxt, img);
+    self.hash_image_with_crop(img).0
+  }
+
+  /// Calculate a hash like [`hash_image()`](#method.hash_image), also returning the part of the
+  /// image that was hashed if [border trimming](struct.HasherConfig.html#method.preproc_trim_borders)
+  /// is enabled.
+  pub fn hash_image_with_crop<I: Image>(&self, img: &I) -> (ImageHash<B>, Option<Rect>) {
+    let Some(tolerance) = self.trim_tolerance else {
+      return (self.hash_whole(img), None);
+    };
+
+    let rect = trim::content_rect(&img.to_grayscale(), tolerance);
+    let hash = if rect.width == img.width() && rect.height == img.height() {
+      self.hash_whole(img)
+    } else {
+      self.hash_whole(&img.crop(rect))
+    };
+    (hash, Some(rect))
+  }
+
+  fn hash_whole<I: Image>(&self, img: &I) -> ImageHash<B> {
+    // the algorithms always pack LSB-first
+    let hash: B = self.hash_alg.hash_image(&self.ctxt, img);
+    let hash = match self.bit_order {
+      BitOrder::LsbFirst => hash,
+      BitOrder::MsbFirst => B::from_iter(hash.as_slice().iter().map(|b| b.reverse_bits())),
+    };
+
     ImageHash {
       hash,
-      __backcompat: (),
+      bit_order: self.bit_order,
     }
   }
 }
@@ -357,6 +504,7 @@ where
 enum CowImage<'a, I: Image> {
   Borrowed(&'a I),
   Owned(I::Buf),
+  Gray(GrayImage),
 }
 
 impl<'a, I: Image> CowImage<'a, I> {
@@ -364,6 +512,7 @@ impl<'a, I: Image> CowImage<'a, I> {
     match *self {
       CowImage::Borrowed(img) => img.to_grayscale(),
       CowImage::Owned(ref img) => img.to_grayscale(),
+      CowImage::Gray(ref img) => Cow::Borrowed(img),
     }
   }
 }
@@ -376,30 +525,58 @@ enum HashVals {
 // TODO: implement `Debug`, needs adaptor for `FilterType`
 struct HashCtxt {
   gauss_sigmas: Option<[f32; 2]>,
+  gauss_mode: GaussMode,
   dct_ctxt: Option<DctCtxt>,
   resize_filter: FilterType,
   width: u32,
   height: u32,
+  deterministic: bool,
 }
 
 impl HashCtxt {
   /// If Difference of Gaussians preprocessing is configured, produce a new image with it applied.
   fn gauss_preproc<'a, I: Image>(&self, image: &'a I) -> CowImage<'a, I> {
-    if let Some([sigma_a, sigma_b]) = self.gauss_sigmas {
-      let mut blur_a = image.blur(sigma_a);
-      let blur_b = image.blur(sigma_b);
-      blur_a.diff_inplace(&blur_b);
+    let Some([sigma_a, sigma_b]) = self.gauss_sigmas else {
+      return CowImage::Borrowed(image);
+    };
 
-      CowImage::Owned(blur_a)
-    } else {
-      CowImage::Borrowed(image)
+    match self.gauss_mode {
+      GaussMode::Exact => {
+        let mut blur_a = image.blur(sigma_a);
+        let blur_b = image.blur(sigma_b);
+        blur_a.diff_inplace(&blur_b);
+
+        CowImage::Owned(blur_a)
+      }
+      GaussMode::Fast { max_size } => {
+        let gray = image.to_grayscale();
+        let (width, height) = gray.dimensions();
+        let larger = width.max(height);
+
+        match max_size {
+          Some(max_size) if larger > max_size => {
+            let scale = max_size as f32 / larger as f32;
+            let resized_width = ((width as f32 * scale).round() as u32).max(1);
+            let resized_height = ((height as f32 * scale).round() as u32).max(1);
+            let resized = self.resize(&gray, resized_width, resized_height);
+
+            // keep the blur radius relative to the image content
+            CowImage::Gray(gauss::diff_gauss(
+              &resized,
+              sigma_a * scale,
+              sigma_b * scale,
+            ))
+          }
+          _ => CowImage::Gray(gauss::diff_gauss(&gray, sigma_a, sigma_b)),
+        }
+      }
     }
   }
 
   /// If DCT preprocessing is configured, produce a vector of floats, otherwise a vector of bytes.
   fn calc_hash_vals(&self, img: &GrayImage, width: u32, height: u32) -> HashVals {
     if let Some(ref dct_ctxt) = self.dct_ctxt {
-      let img = resize_gray(img, dct_ctxt.width(), dct_ctxt.height(), self.resize_filter);
+      let img = self.resize(img, dct_ctxt.width(), dct_ctxt.height());
 
       let img_vals = img.into_vec();
       let input_len = img_vals.len() + dct_ctxt.required_scratch();
@@ -414,10 +591,18 @@ impl HashCtxt {
       let hash_vals = dct_ctxt.dct_2d(vals_with_scratch);
       HashVals::Floats(dct_ctxt.crop_2d(hash_vals))
     } else {
-      let img = resize_gray(img, width, height, self.resize_filter);
+      let img = self.resize(img, width, height);
       HashVals::Bytes(img.into_vec())
     }
   }
+
+  fn resize(&self, img: &GrayImage, width: u32, height: u32) -> GrayImage {
+    if self.deterministic {
+      scalar::resize_gray(img, width, height, self.resize_filter)
+    } else {
+      resize_gray(img, width, height, self.resize_filter)
+    }
+  }
 }
 
 /// A struct representing an image processed by a perceptual hash.
@@ -427,7 +612,45 @@ impl HashCtxt {
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct ImageHash<B = Box<[u8]>> {
   hash: B,
-  __backcompat: (),
+  bit_order: BitOrder,
+}
+
+/// How [Difference of Gaussians](struct.HasherConfig.html#method.preproc_diff_gauss_sigmas)
+/// preprocessing blurs the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GaussMode {
+  /// Gaussian blur of every channel at full resolution, with `image::imageops::blur`.
+  ///
+  /// This is the mode used by previous versions of this crate.
+  #[default]
+  Exact,
+  /// Three box blurs approximating the Gaussian on the luma plane only.
+  ///
+  /// If `max_size` is set, larger images are first downscaled with the configured
+  /// [resize filter](struct.HasherConfig.html#method.resize_filter) so neither side exceeds it,
+  /// and the sigmas are scaled to match.
+  ///
+  /// Hashes are bit-identical on every platform, see
+  /// [`HasherConfig::deterministic()`](struct.HasherConfig.html#method.deterministic).
+  Fast {
+    /// The largest width or height to blur at
+    max_size: Option<u32>,
+  },
+}
+
+/// The order in which the bits of a hash are packed into its bytes.
+///
+/// Bit `i` of a hash is always the `i`-th bit computed by the hash algorithm; this only
+/// decides where it ends up in [`ImageHash::as_bytes()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BitOrder {
+  /// Bit `i` is bit `i % 8` of byte `i / 8`, counting from the least significant bit.
+  ///
+  /// This is the order used by previous versions of this crate.
+  #[default]
+  LsbFirst,
+  /// Bit `i` is bit `7 - i % 8` of byte `i / 8`, counting from the least significant bit.
+  MsbFirst,
 }
 
 /// Error that can happen constructing a `ImageHash` from bytes.
@@ -455,30 +678,150 @@ impl<B: HashBytes> ImageHash<B> {
   /// ## Errors:
   /// Returns a `InvalidBytesError::BytesWrongLength` error if the slice passed can't fit in `B`.
   pub fn from_bytes(bytes: &[u8]) -> Result<ImageHash<B>, InvalidBytesError> {
-    if bytes.len() * 8 > B::max_bits() {
+    // round up, a hash of e.g. 100 bits is stored in 13 bytes
+    let max_bytes = B::max_bits() / 8 + (B::max_bits() % 8 != 0) as usize;
+    if bytes.len() > max_bytes {
       return Err(InvalidBytesError::BytesWrongLength {
-        expected: B::max_bits() / 8,
+        expected: max_bytes,
         found: bytes.len(),
       });
     }
 
     Ok(ImageHash {
       hash: B::from_iter(bytes.iter().copied()),
-      __backcompat: (),
+      bit_order: BitOrder::LsbFirst,
     })
   }
 
+  /// Create an `ImageHash` instance from the given bytes, packed in the given order.
+  ///
+  /// ## Errors:
+  /// The same as `from_bytes`.
+  pub fn from_bytes_with_order(
+    bytes: &[u8],
+    bit_order: BitOrder,
+  ) -> Result<ImageHash<B>, InvalidBytesError> {
+    let hash = Self::from_bytes(bytes)?;
+    Ok(ImageHash { bit_order, ..hash })
+  }
+
+  /// Get the order in which the bits of this hash are packed into its bytes.
+  pub fn bit_order(&self) -> BitOrder {
+    self.bit_order
+  }
+
+  /// Repack the bits of this hash in the given order.
+  ///
+  /// The result has the same bits, but different bytes if the order changes.
+  pub fn to_bit_order(&self, bit_order: BitOrder) -> Self {
+    let repack = bit_order != self.bit_order;
+    ImageHash {
+      hash: B::from_iter(
+        self
+          .as_bytes()
+          .iter()
+          .map(|&b| if repack { b.reverse_bits() } else { b }),
+      ),
+      bit_order,
+    }
+  }
+
+  /// Get the number of bits in this hash, including any padding in the last byte.
+  pub fn len_bits(&self) -> usize {
+    self.as_bytes().len() * 8
+  }
+
+  /// Get bit `i` of this hash.
+  ///
+  /// ### Panics
+  /// If `i >= self.len_bits()`.
+  pub fn bit(&self, i: usize) -> bool {
+    let byte = self.as_bytes()[i / 8];
+    let shift = match self.bit_order {
+      BitOrder::LsbFirst => i % 8,
+      BitOrder::MsbFirst => 7 - i % 8,
+    };
+    byte >> shift & 1 == 1
+  }
+
+  /// Iterate over the bits of this hash in order, including any padding in the last byte.
+  pub fn iter_bits(&self) -> impl Iterator<Item = bool> + '_ {
+    (0..self.len_bits()).map(|i| self.bit(i))
+  }
+
+  /// Convert this hash to a `u64` if it has at most 64 bits.
+  ///
+  /// For [`BitOrder::LsbFirst`] bit `i` of the hash is bit `i` of the integer; the bytes are read
+  /// as little-endian. For [`BitOrder::MsbFirst`] the bytes are read as big-endian, so the
+  /// first bit of the hash is the most significant bit of the integer.
+  ///
+  /// Cast the result to `i64` to store it as an SQLite `INTEGER`.
+  pub fn to_u64(&self) -> Option<u64> {
+    if self.as_bytes().len() > 8 {
+      return None;
+    }
+
+    self.to_u128().map(|x| x as u64)
+  }
+
+  /// Convert this hash to a `u128` if it has at most 128 bits.
+  ///
+  /// See [`to_u64()`](#method.to_u64) for the order of the bits.
+  pub fn to_u128(&self) -> Option<u128> {
+    let bytes = self.as_bytes();
+    if bytes.len() > 16 {
+      return None;
+    }
+
+    let fold = |x: u128, &b: &u8| x << 8 | b as u128;
+    Some(match self.bit_order {
+      BitOrder::LsbFirst => bytes.iter().rev().fold(0, fold),
+      BitOrder::MsbFirst => bytes.iter().fold(0, fold),
+    })
+  }
+
+  fn zip_with(&self, other: &Self, op: impl Fn(u8, u8) -> u8) -> Self {
+    assert_eq!(
+      self.as_bytes().len(),
+      other.as_bytes().len(),
+      "hashes have different sizes"
+    );
+    assert_eq!(
+      self.bit_order, other.bit_order,
+      "hashes have different bit orders"
+    );
+
+    ImageHash {
+      hash: B::from_iter(
+        self
+          .as_bytes()
+          .iter()
+          .zip(other.as_bytes())
+          .map(|(&a, &b)| op(a, b)),
+      ),
+      bit_order: self.bit_order,
+    }
+  }
+
   /// Calculate the Hamming distance between this and `other`.
   ///
-  /// Equivalent to counting the 1-bits of the XOR of the two hashes.
+  /// Equivalent to counting the 1-bits of the XOR of the two hashes. See [`dist_many`] for
+  /// comparing against many hashes at once.
   ///
   /// Essential to determining the perceived difference between `self` and `other`.
   ///
+  /// Hashes packed in different [`BitOrder`]s are compared bit by bit, as if `other` was
+  /// repacked in the order of `self`.
+  ///
   /// ### Note
   /// This return value is meaningless if these two hashes are from different hash sizes or
   /// algorithms.
   pub fn dist(&self, other: &Self) -> u32 {
-    BitSet::hamming(&self.hash, &other.hash)
+    if self.bit_order == other.bit_order {
+      BitSet::hamming(&self.hash, &other.hash)
+    } else {
+      BitSet::hamming(&self.hash, &other.to_bit_order(self.bit_order).hash)
+    }
   }
 
   /// Create an `ImageHash` instance from the given Base64-encoded string.
@@ -502,6 +845,33 @@ impl<B: HashBytes> ImageHash<B> {
   }
 }
 
+macro_rules! hash_bit_op {
+  ($trait_:ident, $method:ident, $op:tt) => {
+    /// ### Panics
+    /// If the hashes have different sizes or bit orders.
+    impl<B: HashBytes> ops::$trait_ for &ImageHash<B> {
+      type Output = ImageHash<B>;
+
+      fn $method(self, other: Self) -> ImageHash<B> {
+        self.zip_with(other, |a, b| a $op b)
+      }
+    }
+
+    /// ### Panics
+    /// If the hashes have different sizes or bit orders.
+    impl<B: HashBytes> ops::$trait_ for ImageHash<B> {
+      type Output = ImageHash<B>;
+
+      fn $method(self, other: Self) -> ImageHash<B> {
+        self.zip_with(&other, |a, b| a $op b)
+      }
+    }
+  };
+}
+
+hash_bit_op!(BitXor, bitxor, ^);
+hash_bit_op!(BitAnd, bitand, &);
+
 /// Provide Serde a typedef for `image::FilterType`: https://serde.rs/remote-derive.html
 /// This is automatically checked, if Serde complains then double-check with the original definition
 #[derive(Deserialize)]
@@ -532,6 +902,11 @@ impl SerdeFilterType {
   }
 }
 
+/// Whether a hash of `width x height` bits fits in `B`.
+pub(crate) fn fits<B: HashBytes>(width: u32, height: u32) -> bool {
+  u64::from(width) * u64::from(height) <= B::max_bits() as u64
+}
+
 fn debug_filter_type(ft: &FilterType) -> &'static str {
   use FilterType::*;
 
@@ -552,7 +927,7 @@ mod test {
 
   use rand::{rngs::SmallRng, RngCore, SeedableRng};
 
-  use super::{HashAlg, HasherConfig, ImageHash};
+  use super::{BitOrder, FilterType, GaussMode, HashAlg, HasherConfig, ImageHash};
 
   type RgbaBuf = ImageBuffer<Rgba<u8>, Vec<u8>>;
 
@@ -588,7 +963,10 @@ mod test {
         .hash_alg(HashAlg::$type)
         .hash_size($size, $size);
       if $preproc_dct {
-        if HashAlg::$type != HashAlg::Blockhash {
+        if !matches!(
+          HashAlg::$type,
+          HashAlg::Blockhash | HashAlg::LegacyBlockhash
+        ) {
           cfg = cfg.preproc_dct();
         } else {
           cfg = cfg.preproc_diff_gauss();
@@ -619,10 +997,281 @@ mod test {
 
   test_hash_type!(Mean, mean);
   test_hash_type!(Blockhash, blockhash);
+  test_hash_type!(LegacyBlockhash, legacy_blockhash);
   test_hash_type!(Gradient, gradient);
   test_hash_type!(DoubleGradient, dbl_gradient);
   test_hash_type!(VertGradient, vert_gradient);
 
+  /// Generate an image from a xorshift sequence, which unlike `SmallRng` is the same on every
+  /// target.
+  fn gen_golden_img(width: u32, height: u32, seed: u32) -> RgbaBuf {
+    let mut state = seed;
+    ImageBuffer::from_fn(width, height, |x, y| {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      let noise = (state & 0x3f) as u8;
+      let (fx, fy) = (x * 255 / width, y * 255 / height);
+      let base = match seed {
+        1 => (fx + fy) / 2,
+        2 => (fx * fx + fy * fy) / 64 % 256,
+        _ => (fx / 32 + fy / 48) % 2 * 160 + 40,
+      } as u8;
+      let [a, b, c, _] = state.to_le_bytes();
+      Rgba([base.wrapping_add(noise), a / 2 + base / 2, b ^ c, 255])
+    })
+  }
+
+  /// Deterministic hashes must never change between targets or releases, so they are checked
+  /// against values recorded on x86_64. Run with `UPDATE_GOLDEN=1` to rewrite the file after an
+  /// intentional change.
+  #[test]
+  fn deterministic_golden_hashes() {
+    use std::fmt::Write;
+
+    let images = [
+      ("noise", gen_golden_img(301, 203, 1)),
+      ("wide", gen_golden_img(640, 120, 2)),
+      ("tall", gen_golden_img(123, 457, 3)),
+    ];
+
+    let mut configs = vec![];
+    for (name, alg) in [
+      ("mean", HashAlg::Mean),
+      ("gradient", HashAlg::Gradient),
+      ("vert-gradient", HashAlg::VertGradient),
+      ("double-gradient", HashAlg::DoubleGradient),
+    ] {
+      let config = || HasherConfig::new().hash_alg(alg).deterministic();
+      configs.push((format!("{name}:8x8"), config()));
+      configs.push((
+        format!("dct-{name}:10x10"),
+        config().hash_size(10, 10).preproc_dct(),
+      ));
+    }
+    for (name, filter) in [
+      ("box", FilterType::Box),
+      ("bilinear", FilterType::Bilinear),
+      ("hamming", FilterType::Hamming),
+      ("catmull-rom", FilterType::CatmullRom),
+      ("mitchell", FilterType::Mitchell),
+    ] {
+      let config = HasherConfig::new().resize_filter(filter).deterministic();
+      configs.push((
+        format!("dct-gradient:16x16:{name}"),
+        config.hash_size(16, 16).preproc_dct(),
+      ));
+    }
+    configs.push((
+      "dog-blockhash:16x16".into(),
+      HasherConfig::new()
+        .hash_alg(HashAlg::Blockhash)
+        .hash_size(16, 16)
+        .preproc_diff_gauss()
+        .gauss_mode(GaussMode::Fast {
+          max_size: Some(256),
+        })
+        .deterministic(),
+    ));
+    configs.push((
+      "blockhash:16x16".into(),
+      HasherConfig::new()
+        .hash_alg(HashAlg::Blockhash)
+        .hash_size(16, 16)
+        .deterministic(),
+    ));
+    configs.push((
+      "legacy-blockhash:16x16".into(),
+      HasherConfig::new()
+        .hash_alg(HashAlg::LegacyBlockhash)
+        .hash_size(16, 16)
+        .deterministic(),
+    ));
+
+    let mut actual = String::new();
+    for (config_name, config) in &configs {
+      let hasher = config.to_hasher();
+      for (image_name, image) in &images {
+        let hash: String = hasher
+          .hash_image(image)
+          .as_bytes()
+          .iter()
+          .map(|b| format!("{b:02x}"))
+          .collect();
+        writeln!(actual, "{config_name} {image_name} {hash}").unwrap();
+      }
+    }
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/deterministic.txt");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+      std::fs::write(path, &actual).unwrap();
+    }
+    let expected = std::fs::read_to_string(path).unwrap();
+    for (actual, expected) in actual.lines().zip(expected.lines()) {
+      assert_eq!(actual, expected);
+    }
+    assert_eq!(actual.lines().count(), expected.lines().count());
+  }
+
+  /// Generate a photo-like image: flat colored shapes on a gradient, with a little noise.
+  pub(crate) fn gen_scene_img(width: u32, height: u32, seed: u32) -> image::RgbImage {
+    let mut state = seed;
+    let mut next = move || {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      state
+    };
+    let shapes: Vec<_> = (0..12)
+      .map(|_| {
+        let (cx, cy) = (next() % width, next() % height);
+        let r = next() % (width.min(height) / 3) + width.min(height) / 16;
+        let color = next().to_le_bytes();
+        (
+          cx as i64,
+          cy as i64,
+          (r * r) as i64,
+          [color[0], color[1], color[2]],
+        )
+      })
+      .collect();
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+      let base = ((x + y) * 200 / (width + height)) as u8;
+      let mut px = [base, base / 2 + 60, 255 - base];
+      for &(cx, cy, r2, color) in &shapes {
+        let (dx, dy) = (x as i64 - cx, y as i64 - cy);
+        if dx * dx + dy * dy < r2 {
+          px = color;
+        }
+      }
+      let noise = (next() % 9) as u8;
+      image::Rgb(px.map(|c| c.saturating_add(noise).saturating_sub(4)))
+    })
+  }
+
+  /// The fast Difference of Gaussians should stay close to the exact one in practice.
+  #[test]
+  fn fast_diff_gauss() {
+    let config = || {
+      HasherConfig::new()
+        .hash_alg(HashAlg::Blockhash)
+        .hash_size(16, 16)
+        .preproc_diff_gauss()
+    };
+    let exact = config().to_hasher();
+    let fast = config()
+      .gauss_mode(GaussMode::Fast { max_size: None })
+      .to_hasher();
+    let downscaled = config()
+      .gauss_mode(GaussMode::Fast {
+        max_size: Some(512),
+      })
+      .to_hasher();
+
+    for (seed, (width, height)) in [(301, 203), (640, 480), (1200, 900), (800, 1400)]
+      .into_iter()
+      .enumerate()
+    {
+      let image = gen_scene_img(width, height, seed as u32 + 1);
+      // `Exact` blurs and subtracts each color channel separately, which is not comparable
+      let image = image::DynamicImage::ImageRgb8(image).into_luma8();
+      let expected = exact.hash_image(&image);
+
+      let dist = fast.hash_image(&image).dist(&expected);
+      assert!(dist <= 12, "{width}x{height}: {dist}");
+      let dist = downscaled.hash_image(&image).dist(&expected);
+      assert!(dist <= 20, "{width}x{height} downscaled: {dist}");
+    }
+  }
+
+  /// `Fast` blurs the luma plane while `Exact` blurs each color channel, so on colored images
+  /// they differ more than on grayscale ones.
+  #[test]
+  fn fast_diff_gauss_rgb() {
+    let config = || {
+      HasherConfig::new()
+        .hash_alg(HashAlg::Blockhash)
+        .hash_size(16, 16)
+        .preproc_diff_gauss()
+    };
+    let exact = config().to_hasher();
+    let fast = config()
+      .gauss_mode(GaussMode::Fast { max_size: None })
+      .to_hasher();
+
+    for (seed, (width, height)) in [(301, 203), (640, 480), (1200, 900), (800, 1400)]
+      .into_iter()
+      .enumerate()
+    {
+      let image = gen_scene_img(width, height, seed as u32 + 1);
+      let dist = fast.hash_image(&image).dist(&exact.hash_image(&image));
+      // 8 to 50 of the 256 bits when this was written
+      assert!(dist > 0 && dist <= 64, "{width}x{height}: {dist}");
+    }
+  }
+
+  #[test]
+  fn bit_order() {
+    let test_img = gen_test_img(256, 256);
+    let config = || {
+      HasherConfig::new()
+        .hash_alg(HashAlg::Gradient)
+        .hash_size(10, 10)
+    };
+    let lsb = config().to_hasher().hash_image(&test_img);
+    let msb = config()
+      .bit_order(BitOrder::MsbFirst)
+      .to_hasher()
+      .hash_image(&test_img);
+
+    assert_eq!(lsb.bit_order(), BitOrder::LsbFirst);
+    assert_eq!(msb.bit_order(), BitOrder::MsbFirst);
+    assert_ne!(lsb.as_bytes(), msb.as_bytes());
+    assert!(lsb.iter_bits().eq(msb.iter_bits()));
+    assert_eq!(lsb.to_bit_order(BitOrder::MsbFirst), msb);
+    assert_eq!(msb.to_bit_order(BitOrder::LsbFirst), lsb);
+    assert_eq!(
+      lsb.to_u128().unwrap().count_ones(),
+      msb.to_u128().unwrap().count_ones()
+    );
+    assert_eq!(lsb.to_u64(), None);
+  }
+
+  #[test]
+  fn bit_accessors() {
+    let lsb = ImageHash::<Box<[u8]>>::from_bytes(&[0b0000_0101, 0b1000_0000]).unwrap();
+    let msb = ImageHash::<Box<[u8]>>::from_bytes_with_order(
+      &[0b1010_0000, 0b0000_0001],
+      BitOrder::MsbFirst,
+    )
+    .unwrap();
+
+    for hash in [&lsb, &msb] {
+      let set: Vec<_> = (0..hash.len_bits()).filter(|&i| hash.bit(i)).collect();
+      assert_eq!(set, [0, 2, 15]);
+    }
+    assert_eq!(lsb.to_u64(), Some(0x8005));
+    assert_eq!(msb.to_u64(), Some(0xa001));
+
+    let other = ImageHash::from_bytes(&[0b0000_0110, 0b1111_1111]).unwrap();
+    assert_eq!((&lsb ^ &other).as_bytes(), [0b0000_0011, 0b0111_1111]);
+    assert_eq!((&lsb & &other).as_bytes(), [0b0000_0100, 0b1000_0000]);
+    assert_eq!(
+      (&lsb ^ &other).iter_bits().filter(|&b| b).count() as u32,
+      lsb.dist(&other)
+    );
+
+    // hashes in different orders are compared by their bits, not their bytes
+    assert_eq!((lsb.dist(&msb), msb.dist(&lsb)), (0, 0));
+    assert_eq!(msb.dist(&other), lsb.dist(&other));
+    assert_eq!(other.dist(&msb), lsb.dist(&other));
+    assert_eq!(
+      crate::dist_many(&msb, &[lsb.clone(), other.clone()]),
+      [0, lsb.dist(&other)]
+    );
+  }
+
   #[test]
   fn size() {
     let test_img = gen_test_img(1024, 1024);
@@ -0,0 +1,374 @@
+//! Scalar, order-defined implementations used by
+//! [deterministic hashing](crate::HasherConfig::deterministic).
+//!
+//! Everything here only uses IEEE 754 basic operations (`+ - * /`), rounding functions and
+//! integer arithmetic, which produce identical results on every target. In particular the
+//! trigonometric functions of `std` are avoided since they call into the platform's libm, which
+//! differs between e.g. glibc and the `wasm32-unknown-unknown` target.
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use image::GrayImage;
+use transpose::transpose;
+
+use crate::fr::FilterType;
+
+/// `FRAC_PI_2` split into a head with trailing zero bits and the remainder, so that
+/// `k * PI_2_HI` is exact for the small `k` used here (Cody-Waite reduction).
+const PI_2_HI: f64 = 1.570_796_326_734_125_6;
+const PI_2_LO: f64 = FRAC_PI_2 - PI_2_HI;
+
+/// Reduce `x` to `r` in `[-pi/4, pi/4]` and the quadrant `k` such that `x = k * pi/2 + r`.
+fn reduce(x: f64) -> (f64, i64) {
+  let k = (x / FRAC_PI_2).round();
+  (x - k * PI_2_HI - k * PI_2_LO, k as i64)
+}
+
+/// Taylor series of `sin` for `|r| <= pi/4`.
+fn sin_kernel(r: f64) -> f64 {
+  let r2 = r * r;
+  let p = -1. / 1307674368000.;
+  let p = p * r2 + 1. / 6227020800.;
+  let p = p * r2 - 1. / 39916800.;
+  let p = p * r2 + 1. / 362880.;
+  let p = p * r2 - 1. / 5040.;
+  let p = p * r2 + 1. / 120.;
+  let p = p * r2 - 1. / 6.;
+  r + r * r2 * p
+}
+
+/// Taylor series of `cos` for `|r| <= pi/4`.
+fn cos_kernel(r: f64) -> f64 {
+  let r2 = r * r;
+  let p = -1. / 20922789888000.;
+  let p = p * r2 + 1. / 87178291200.;
+  let p = p * r2 - 1. / 479001600.;
+  let p = p * r2 + 1. / 3628800.;
+  let p = p * r2 - 1. / 40320.;
+  let p = p * r2 + 1. / 720.;
+  let p = p * r2 - 1. / 24.;
+  let p = p * r2 + 1. / 2.;
+  1. - r2 * p
+}
+
+/// Deterministic `sin`, accurate to a few ulps for the small arguments used in this crate.
+pub fn sin(x: f64) -> f64 {
+  let (r, k) = reduce(x);
+  match k.rem_euclid(4) {
+    0 => sin_kernel(r),
+    1 => cos_kernel(r),
+    2 => -sin_kernel(r),
+    _ => -cos_kernel(r),
+  }
+}
+
+/// Deterministic `cos`, accurate to a few ulps for the small arguments used in this crate.
+pub fn cos(x: f64) -> f64 {
+  let (r, k) = reduce(x);
+  match k.rem_euclid(4) {
+    0 => cos_kernel(r),
+    1 => -sin_kernel(r),
+    2 => -cos_kernel(r),
+    _ => sin_kernel(r),
+  }
+}
+
+// The filters and the fixed-point convolution below mirror the scalar `U8` path of
+// `fast_image_resize`, so deterministic hashes rarely differ from the default ones.
+
+fn filter(filter_type: FilterType) -> (fn(f64) -> f64, f64) {
+  match filter_type {
+    FilterType::Box => (box_filter, 0.5),
+    FilterType::Bilinear => (bilinear_filter, 1.0),
+    FilterType::Hamming => (hamming_filter, 1.0),
+    FilterType::CatmullRom => (catmull_rom_filter, 2.0),
+    FilterType::Mitchell => (mitchell_filter, 2.0),
+    _ => (lanczos3_filter, 3.0),
+  }
+}
+
+fn box_filter(x: f64) -> f64 {
+  if x > -0.5 && x <= 0.5 {
+    1.0
+  } else {
+    0.0
+  }
+}
+
+fn bilinear_filter(x: f64) -> f64 {
+  let x = x.abs();
+  if x < 1.0 {
+    1.0 - x
+  } else {
+    0.0
+  }
+}
+
+fn hamming_filter(x: f64) -> f64 {
+  let x = x.abs();
+  if x == 0.0 {
+    1.0
+  } else if x >= 1.0 {
+    0.0
+  } else {
+    let x = x * PI;
+    (0.54 + 0.46 * cos(x)) * sin(x) / x
+  }
+}
+
+fn catmull_rom_filter(x: f64) -> f64 {
+  const A: f64 = -0.5;
+  let x = x.abs();
+  if x < 1.0 {
+    ((A + 2.) * x - (A + 3.)) * x * x + 1.
+  } else if x < 2.0 {
+    (((x - 5.) * x + 8.) * x - 4.) * A
+  } else {
+    0.0
+  }
+}
+
+fn mitchell_filter(x: f64) -> f64 {
+  let x = x.abs();
+  if x < 1.0 {
+    (7. * x / 6. - 2.) * x * x + 16. / 18.
+  } else if x < 2.0 {
+    ((2. - 7. * x / 18.) * x - 10. / 3.) * x + 16. / 9.
+  } else {
+    0.0
+  }
+}
+
+fn sinc(x: f64) -> f64 {
+  if x == 0.0 {
+    1.0
+  } else {
+    let x = x * PI;
+    sin(x) / x
+  }
+}
+
+fn lanczos3_filter(x: f64) -> f64 {
+  if (-3.0..3.0).contains(&x) {
+    sinc(x) * sinc(x / 3.)
+  } else {
+    0.0
+  }
+}
+
+/// Fixed-point convolution weights for resizing one dimension.
+struct Weights {
+  /// `(first input index, weights)` for each output index
+  windows: Vec<(usize, Vec<i32>)>,
+  precision: u32,
+}
+
+impl Weights {
+  fn new(in_size: u32, out_size: u32, filter_type: FilterType) -> Self {
+    let (filter, support) = filter(filter_type);
+
+    let scale = in_size as f64 / out_size as f64;
+    let filter_scale = scale.max(1.0);
+    let radius = support * filter_scale;
+    let recip_filter_scale = 1.0 / filter_scale;
+
+    let windows: Vec<(usize, Vec<f64>)> = (0..out_size)
+      .map(|out_x| {
+        let in_center = (out_x as f64 + 0.5) * scale;
+        let x_min = (in_center - radius).floor().max(0.) as u32;
+        let x_max = (in_center + radius).ceil().min(in_size as f64) as u32;
+        let center = in_center - 0.5;
+
+        let mut weights: Vec<f64> = (x_min..x_max)
+          .map(|x| filter((x as f64 - center) * recip_filter_scale))
+          .collect();
+        let sum: f64 = weights.iter().sum();
+        if sum != 0.0 {
+          weights.iter_mut().for_each(|w| *w /= sum);
+        }
+
+        (x_min as usize, weights)
+      })
+      .collect();
+
+    // 8 bits for the result and 2 for overflow in an `i32`, and the weights must fit an `i16`
+    let max_weight = windows
+      .iter()
+      .flat_map(|(_, w)| w)
+      .copied()
+      .fold(0.0, f64::max);
+    let mut precision = 0;
+    for p in 0..22 {
+      precision = p;
+      if (max_weight * (1 << (p + 1)) as f64).round() as i32 >= 1 << 15 {
+        break;
+      }
+    }
+
+    let scale = (1 << precision) as f64;
+    Weights {
+      windows: windows
+        .into_iter()
+        .map(|(start, w)| {
+          (
+            start,
+            w.iter().map(|w| (w * scale).round() as i32).collect(),
+          )
+        })
+        .collect(),
+      precision,
+    }
+  }
+
+  fn apply(&self, start: usize, weights: &[i32], pixel: impl Fn(usize) -> u8) -> u8 {
+    let mut sum = 1 << (self.precision - 1);
+    for (i, &w) in weights.iter().enumerate() {
+      sum += pixel(start + i) as i32 * w;
+    }
+    (sum >> self.precision).clamp(0, 255) as u8
+  }
+}
+
+/// Resize a grayscale image with a separable fixed-point convolution, horizontally first.
+pub fn resize_gray(image: &GrayImage, width: u32, height: u32, filter: FilterType) -> GrayImage {
+  let (in_width, in_height) = image.dimensions();
+  let src = image.as_raw();
+
+  let horiz = if width != in_width {
+    let weights = Weights::new(in_width, width, filter);
+    let mut dst = Vec::with_capacity(width as usize * in_height as usize);
+    for row in src.chunks_exact(in_width as usize) {
+      for (start, w) in &weights.windows {
+        dst.push(weights.apply(*start, w, |x| row[x]));
+      }
+    }
+    dst
+  } else {
+    src.clone()
+  };
+
+  let vert = if height != in_height {
+    let weights = Weights::new(in_height, height, filter);
+    let mut dst = Vec::with_capacity(width as usize * height as usize);
+    for (start, w) in &weights.windows {
+      for x in 0..width as usize {
+        dst.push(weights.apply(*start, w, |y| horiz[y * width as usize + x]));
+      }
+    }
+    dst
+  } else {
+    horiz
+  };
+
+  GrayImage::from_vec(width, height, vert).unwrap()
+}
+
+/// The unnormalized DCT-II as computed by `rustdct`, as a `len x len` matrix of coefficients.
+pub struct Dct {
+  len: usize,
+  coeffs: Vec<f64>,
+}
+
+impl Dct {
+  pub fn new(len: usize) -> Self {
+    let coeffs = (0..len)
+      .flat_map(|k| (0..len).map(move |n| cos(PI / len as f64 * (n as f64 + 0.5) * k as f64)))
+      .collect();
+    Dct { len, coeffs }
+  }
+
+  fn process(&self, data: &mut [f64], scratch: &mut Vec<f64>) {
+    scratch.clear();
+    scratch.extend(
+      self
+        .coeffs
+        .chunks_exact(self.len)
+        .map(|row| row.iter().zip(&*data).fold(0., |sum, (c, x)| sum + c * x)),
+    );
+    data.copy_from_slice(scratch);
+  }
+}
+
+/// Perform a 2D DCT on a 1D-packed `width x height` matrix.
+///
+/// Moves the values around exactly like [`DctCtxt::dct_2d()`](crate::dct::DctCtxt::dct_2d)
+/// so both produce the same layout of coefficients.
+pub fn dct_2d(row_dct: &Dct, col_dct: &Dct, packed_2d: &mut [f32]) {
+  let (width, height) = (row_dct.len, col_dct.len);
+  let mut data: Vec<f64> = packed_2d.iter().map(|&x| x as f64).collect();
+  let mut transposed = vec![0.; data.len()];
+  let mut scratch = Vec::with_capacity(width.max(height));
+
+  for row in data.chunks_exact_mut(width) {
+    row_dct.process(row, &mut scratch);
+  }
+  transpose(&data, &mut transposed, width, height);
+
+  for col in transposed.chunks_exact_mut(height) {
+    col_dct.process(col, &mut scratch);
+  }
+  transpose(&transposed, &mut data, width, height);
+
+  for (dst, src) in packed_2d.iter_mut().zip(data) {
+    *dst = src as f32;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use image::GrayImage;
+
+  use super::{cos, resize_gray, sin};
+  use crate::dct::DctCtxt;
+  use crate::fr::{self, FilterType};
+
+  #[test]
+  fn trigonometry() {
+    for i in -2000..2000 {
+      let x = i as f64 / 100.;
+      assert!((sin(x) - x.sin()).abs() < 1e-14, "sin({x})");
+      assert!((cos(x) - x.cos()).abs() < 1e-14, "cos({x})");
+    }
+  }
+
+  #[test]
+  fn resize_matches_fast_image_resize() {
+    let image = GrayImage::from_fn(97, 61, |x, y| [(x * 7 + y * 13 + x * y) as u8].into());
+    for filter in [
+      FilterType::Box,
+      FilterType::Bilinear,
+      FilterType::Hamming,
+      FilterType::CatmullRom,
+      FilterType::Mitchell,
+      FilterType::Lanczos3,
+    ] {
+      for (width, height) in [(9, 8), (20, 20), (97, 12), (150, 61)] {
+        assert_eq!(
+          resize_gray(&image, width, height, filter),
+          fr::resize_gray(&image, width, height, filter),
+          "{filter:?} {width}x{height}"
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn dct_matches_planned() {
+    // non-square like the matrices hashed by `Gradient`
+    let (width, height) = (9, 6);
+    let len = (width * height * 4) as usize;
+    let input: Vec<f32> = (0..len).map(|i| (i * 37 % 255) as f32).collect();
+
+    let transform = |deterministic| {
+      let ctxt = DctCtxt::new(width, height, deterministic);
+      let mut vals = input.clone();
+      vals.resize(len + ctxt.required_scratch(), 0.);
+      ctxt.dct_2d(vals)
+    };
+
+    let (actual, expected) = (transform(true), transform(false));
+    assert_eq!(actual.len(), expected.len());
+    for (a, e) in actual.iter().zip(&expected) {
+      assert!((a - e).abs() <= e.abs() * 1e-4 + 1e-2, "{a} != {e}");
+    }
+  }
+}
@@ -0,0 +1,225 @@
+use image::{Frames, ImageResult};
+
+use crate::{HashBytes, Hasher, ImageHash};
+
+/// The hashes of a sequence of images, such as the frames of an animated GIF, APNG or WebP.
+///
+/// Get an instance with [`Hasher::hash_frames()`](struct.Hasher.html#method.hash_frames),
+/// [`Hasher::hash_animation()`](struct.Hasher.html#method.hash_animation) or
+/// [`HashSequence::from_hashes()`](#method.from_hashes).
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct HashSequence<B = Box<[u8]>> {
+  hashes: Vec<ImageHash<B>>,
+}
+
+impl<B: HashBytes> Hasher<B> {
+  /// Calculate a hash for every image yielded by `frames`, in order.
+  pub fn hash_frames<'a, I, F>(&self, frames: F) -> HashSequence<B>
+  where
+    I: crate::Image,
+    F: IntoIterator<Item = &'a I>,
+  {
+    HashSequence {
+      hashes: frames.into_iter().map(|img| self.hash_image(img)).collect(),
+    }
+  }
+
+  /// Calculate a hash for every frame of a decoded animation.
+  ///
+  /// Frames are hashed as they are decoded and are not retained.
+  ///
+  /// ## Errors:
+  /// Returns the first error produced while decoding the frames.
+  pub fn hash_animation(&self, frames: Frames) -> ImageResult<HashSequence<B>> {
+    let hashes = frames
+      .map(|frame| frame.map(|frame| self.hash_image(frame.buffer())))
+      .collect::<ImageResult<_>>()?;
+
+    Ok(HashSequence { hashes })
+  }
+}
+
+impl<B: HashBytes> HashSequence<B> {
+  /// Create a sequence from hashes calculated with the same configuration.
+  pub fn from_hashes(hashes: Vec<ImageHash<B>>) -> Self {
+    HashSequence { hashes }
+  }
+
+  /// Get the per-frame hashes of this sequence.
+  pub fn frames(&self) -> &[ImageHash<B>] {
+    &self.hashes
+  }
+
+  /// Get the number of frames in this sequence.
+  pub fn len(&self) -> usize {
+    self.hashes.len()
+  }
+
+  /// Returns `true` if this sequence contains no frames.
+  pub fn is_empty(&self) -> bool {
+    self.hashes.is_empty()
+  }
+
+  /// Get the indices of the keyframes of this sequence.
+  ///
+  /// The first frame is always a keyframe. Every following frame is a keyframe if its distance
+  /// to the _last keyframe_ (not the previous frame) is greater than `threshold`, so a slow fade
+  /// still produces new keyframes once it has drifted far enough.
+  pub fn keyframe_indices(&self, threshold: u32) -> Vec<usize> {
+    let mut indices: Vec<usize> = Vec::new();
+
+    for (i, hash) in self.hashes.iter().enumerate() {
+      match indices.last() {
+        Some(&last) if self.hashes[last].dist(hash) <= threshold => {}
+        _ => indices.push(i),
+      }
+    }
+
+    indices
+  }
+
+  /// Get a copy of this sequence containing only its keyframes.
+  ///
+  /// See [`keyframe_indices()`](#method.keyframe_indices) for how keyframes are chosen.
+  pub fn keyframes(&self, threshold: u32) -> Self
+  where
+    B: Clone,
+  {
+    HashSequence {
+      hashes: self
+        .keyframe_indices(threshold)
+        .into_iter()
+        .map(|i| self.hashes[i].clone())
+        .collect(),
+    }
+  }
+
+  /// Calculate a single hash representing the whole sequence.
+  ///
+  /// Each bit is set if it is set in more than half of the frames; ties are broken by the first
+  /// frame. Deduplicate the sequence with [`keyframes()`](#method.keyframes) first if frames
+  /// that are held for a long time should not outweigh the rest of the animation.
+  ///
+  /// Returns `None` if the sequence is empty.
+  pub fn aggregate(&self) -> Option<ImageHash<B>> {
+    let first = self.hashes.first()?.as_bytes();
+    let total = self.hashes.len();
+
+    let mut counts = vec![0usize; first.len() * 8];
+    for hash in &self.hashes {
+      for (i, &byte) in hash.as_bytes().iter().enumerate() {
+        for bit in 0..8 {
+          counts[i * 8 + bit] += (byte >> bit & 1) as usize;
+        }
+      }
+    }
+
+    let bytes = counts.chunks(8).zip(first).map(|(counts, &first)| {
+      counts.iter().enumerate().fold(0u8, |byte, (bit, &count)| {
+        let set = match (count * 2).cmp(&total) {
+          std::cmp::Ordering::Greater => true,
+          std::cmp::Ordering::Equal => first >> bit & 1 == 1,
+          std::cmp::Ordering::Less => false,
+        };
+        byte | (set as u8) << bit
+      })
+    });
+
+    Some(ImageHash {
+      hash: B::from_iter(bytes),
+      bit_order: self.hashes[0].bit_order,
+    })
+  }
+
+  /// Calculate the distance between this and `other`, tolerating dropped frames.
+  ///
+  /// Every frame of the shorter sequence is matched, in order, to a distinct frame of the
+  /// longer one so that the sum of the Hamming distances is minimal; frames of the longer
+  /// sequence that are not matched are treated as dropped and cost nothing. The result is
+  /// the mean distance of the matched pairs, so it is directly comparable to
+  /// [`ImageHash::dist()`](struct.ImageHash.html#method.dist).
+  ///
+  /// Returns `None` if either sequence is empty.
+  ///
+  /// ### Note
+  /// This takes `O(self.len() * other.len())` time; compare the
+  /// [`keyframes()`](#method.keyframes) of long sequences instead.
+  pub fn dist(&self, other: &Self) -> Option<f32> {
+    let (short, long) = if self.len() <= other.len() {
+      (&self.hashes, &other.hashes)
+    } else {
+      (&other.hashes, &self.hashes)
+    };
+
+    if short.is_empty() {
+      return None;
+    }
+
+    // `row[j]` is the minimum cost of matching the frames of `short` seen so far
+    // into the first `j` frames of `long`
+    let mut row = vec![0u64; long.len() + 1];
+    for (i, s) in short.iter().enumerate() {
+      let mut next = vec![u64::MAX; long.len() + 1];
+      // frame `i` of `short` can't match before frame `i` of `long`
+      for j in i + 1..=long.len() {
+        let matched = row[j - 1].saturating_add(s.dist(&long[j - 1]) as u64);
+        next[j] = next[j - 1].min(matched);
+      }
+      row = next;
+    }
+
+    Some(row[long.len()] as f32 / short.len() as f32)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::HashSequence;
+  use crate::ImageHash;
+
+  fn seq(hashes: &[u8]) -> HashSequence {
+    HashSequence::from_hashes(
+      hashes
+        .iter()
+        .map(|&b| ImageHash::from_bytes(&[b]).unwrap())
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn keyframes() {
+    let s = seq(&[0b0000, 0b0001, 0b0011, 0b0111, 0b0111, 0b1111_0000]);
+    assert_eq!(s.keyframe_indices(0), [0, 1, 2, 3, 5]);
+    assert_eq!(s.keyframe_indices(1), [0, 2, 5]);
+    assert_eq!(s.keyframes(2), seq(&[0b0000, 0b0111, 0b1111_0000]));
+  }
+
+  #[test]
+  fn aggregate() {
+    assert_eq!(seq(&[]).aggregate(), None);
+    assert_eq!(
+      seq(&[0b0011, 0b0110, 0b0100]).aggregate(),
+      seq(&[0b0110]).aggregate()
+    );
+    // ties are broken by the first frame
+    assert_eq!(
+      seq(&[0b1010, 0b0101]).aggregate().unwrap().as_bytes(),
+      [0b1010]
+    );
+  }
+
+  #[test]
+  fn dist_tolerates_dropped_frames() {
+    let full = seq(&[0x00, 0x0f, 0xf0, 0xff, 0x3c]);
+    let dropped = seq(&[0x00, 0xf0, 0x3c]);
+    assert_eq!(full.dist(&dropped), Some(0.));
+    assert_eq!(dropped.dist(&full), Some(0.));
+
+    // order matters
+    let reversed = seq(&[0x3c, 0xf0, 0x00]);
+    assert!(full.dist(&reversed).unwrap() > 0.);
+
+    assert_eq!(seq(&[0x00]).dist(&seq(&[0x01, 0x03])), Some(1.));
+    assert_eq!(seq(&[]).dist(&full), None);
+  }
+}
@@ -0,0 +1,357 @@
+//! A compact string form of [`HasherConfig`], e.g. `dct-gradient:10x10:lanczos3`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{BitOrder, FilterType, GaussMode, HashAlg, HashBytes, HasherConfig};
+
+/// Error returned when parsing a [`HasherConfig`] from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseConfigError {
+  token: String,
+  reason: &'static str,
+}
+
+impl ParseConfigError {
+  fn new(token: &str, reason: &'static str) -> Self {
+    Self {
+      token: token.into(),
+      reason,
+    }
+  }
+
+  /// The `:`-separated token that could not be parsed.
+  pub fn token(&self) -> &str {
+    &self.token
+  }
+}
+
+impl fmt::Display for ParseConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "invalid token `{}` in hasher config: {}",
+      self.token, self.reason
+    )
+  }
+}
+
+impl std::error::Error for ParseConfigError {}
+
+const ALGS: [(&str, HashAlg); 6] = [
+  ("mean", HashAlg::Mean),
+  ("gradient", HashAlg::Gradient),
+  ("vert-gradient", HashAlg::VertGradient),
+  ("double-gradient", HashAlg::DoubleGradient),
+  ("blockhash", HashAlg::Blockhash),
+  ("legacy-blockhash", HashAlg::LegacyBlockhash),
+];
+
+const FILTERS: [(&str, FilterType); 6] = [
+  ("box", FilterType::Box),
+  ("bilinear", FilterType::Bilinear),
+  ("hamming", FilterType::Hamming),
+  ("catmull-rom", FilterType::CatmullRom),
+  ("mitchell", FilterType::Mitchell),
+  ("lanczos3", FilterType::Lanczos3),
+];
+
+/// Parse a config from `[dct-]ALG:WIDTHxHEIGHT[:OPTION]...`.
+///
+/// `ALG` is one of `mean`, `gradient`, `vert-gradient`, `double-gradient`, `blockhash` or
+/// `legacy-blockhash`; the `dct-` prefix enables [DCT preprocessing](Self::preproc_dct). The
+/// options may come in any order:
+///
+/// * a resize filter: `box`, `bilinear`, `hamming`, `catmull-rom`, `mitchell` or `lanczos3`;
+/// * `trim(TOLERANCE)`: [border trimming](Self::preproc_trim_borders);
+/// * `dog` or `dog(SIGMA_A,SIGMA_B)`: [Difference of Gaussians](Self::preproc_diff_gauss_sigmas)
+///   preprocessing;
+/// * `fast-dog` or `fast-dog(MAX_SIZE)`: [`GaussMode::Fast`];
+/// * `deterministic`: [deterministic mode](Self::deterministic);
+/// * `lsb` or `msb`: the [bit order](Self::bit_order).
+///
+/// Unset options keep their defaults. Hash sizes that are zero or don't fit in `B` are rejected,
+/// so unlike the builder the parsed config never panics in [`to_hasher()`](Self::to_hasher).
+/// [`Display`](fmt::Display) produces the canonical form, which parses back into the same
+/// config.
+///
+/// ```rust
+/// # use image_hasher::HasherConfig;
+/// let config: HasherConfig = "dct-gradient:10x10:lanczos3".parse().unwrap();
+/// assert_eq!(config.to_string(), "dct-gradient:10x10");
+///
+/// let err = "blockhash:16x16:dog(5,x)".parse::<HasherConfig>().unwrap_err();
+/// assert_eq!(err.token(), "dog(5,x)");
+/// ```
+impl<B: HashBytes> FromStr for HasherConfig<B> {
+  type Err = ParseConfigError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut tokens = s.split(':');
+    let mut config = HasherConfig::with_bytes_type::<B>();
+
+    let alg = tokens.next().unwrap_or_default();
+    let (dct, name) = match alg.strip_prefix("dct-") {
+      Some(name) => (true, name),
+      None => (false, alg),
+    };
+    config.hash_alg =
+      lookup(&ALGS, name).ok_or_else(|| ParseConfigError::new(alg, "unknown hash algorithm"))?;
+    config.dct = dct;
+
+    let size = tokens
+      .next()
+      .ok_or_else(|| ParseConfigError::new("", "missing hash size"))?;
+    (config.width, config.height) = size
+      .split_once('x')
+      .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+      .ok_or_else(|| ParseConfigError::new(size, "expected a hash size like `8x8`"))?;
+    if config.width == 0 || config.height == 0 {
+      return Err(ParseConfigError::new(size, "hash size must not be zero"));
+    }
+    let rounded = config.hash_alg.round_hash_size(config.width, config.height);
+    if !rounded.map_or(false, |(width, height)| crate::fits::<B>(width, height)) {
+      return Err(ParseConfigError::new(
+        size,
+        "hash size too large for the hash type",
+      ));
+    }
+
+    let (mut filter, mut trim, mut dog, mut fast_dog, mut deterministic, mut bit_order) =
+      (false, false, false, false, false, false);
+
+    for token in tokens {
+      let (name, args) = match token.strip_suffix(')').and_then(|t| t.split_once('(')) {
+        Some((name, args)) => (name, Some(args)),
+        None => (token, None),
+      };
+      let invalid = |reason| ParseConfigError::new(token, reason);
+      let once = |seen: &mut bool| {
+        if std::mem::replace(seen, true) {
+          Err(invalid("duplicate option"))
+        } else {
+          Ok(())
+        }
+      };
+
+      match (name, args) {
+        ("trim", Some(args)) => {
+          once(&mut trim)?;
+          let tolerance = args
+            .parse()
+            .map_err(|_| invalid("expected `trim(TOLERANCE)` with a tolerance up to 255"))?;
+          config.trim_tolerance = Some(tolerance);
+        }
+        ("dog", None) => {
+          once(&mut dog)?;
+          config.gauss_sigmas = Some([5., 10.]);
+        }
+        ("dog", Some(args)) => {
+          once(&mut dog)?;
+          let sigmas = args
+            .split_once(',')
+            .and_then(|(a, b)| Some([a.parse().ok()?, b.parse().ok()?]))
+            .filter(|sigmas: &[f32; 2]| sigmas.iter().all(|s| s.is_finite()))
+            .ok_or_else(|| invalid("expected `dog(SIGMA_A,SIGMA_B)`"))?;
+          config.gauss_sigmas = Some(sigmas);
+        }
+        ("fast-dog", args) => {
+          once(&mut fast_dog)?;
+          let max_size = args
+            .map(|args| args.parse())
+            .transpose()
+            .map_err(|_| invalid("expected `fast-dog(MAX_SIZE)`"))?;
+          config.gauss_mode = GaussMode::Fast { max_size };
+        }
+        ("deterministic", None) => {
+          once(&mut deterministic)?;
+          config.deterministic = true;
+        }
+        ("lsb", None) => {
+          once(&mut bit_order)?;
+          config.bit_order = BitOrder::LsbFirst;
+        }
+        ("msb", None) => {
+          once(&mut bit_order)?;
+          config.bit_order = BitOrder::MsbFirst;
+        }
+        (name, None) => {
+          config.resize_filter = lookup(&FILTERS, name).ok_or_else(|| invalid("unknown option"))?;
+          once(&mut filter)?;
+        }
+        _ => return Err(invalid("unknown option")),
+      }
+    }
+
+    Ok(config)
+  }
+}
+
+impl<B> fmt::Display for HasherConfig<B> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.dct {
+      f.write_str("dct-")?;
+    }
+    let alg = ALGS
+      .iter()
+      .find(|(_, alg)| *alg == self.hash_alg)
+      .unwrap()
+      .0;
+    write!(f, "{alg}:{}x{}", self.width, self.height)?;
+
+    if self.resize_filter != FilterType::Lanczos3 {
+      let filter = FILTERS
+        .iter()
+        .find(|(_, filter)| *filter == self.resize_filter)
+        .unwrap()
+        .0;
+      write!(f, ":{filter}")?;
+    }
+    if let Some(tolerance) = self.trim_tolerance {
+      write!(f, ":trim({tolerance})")?;
+    }
+    if let Some([sigma_a, sigma_b]) = self.gauss_sigmas {
+      write!(f, ":dog({sigma_a},{sigma_b})")?;
+    }
+    match self.gauss_mode {
+      GaussMode::Exact => {}
+      GaussMode::Fast { max_size: None } => f.write_str(":fast-dog")?,
+      GaussMode::Fast {
+        max_size: Some(max_size),
+      } => write!(f, ":fast-dog({max_size})")?,
+    }
+    if self.deterministic {
+      f.write_str(":deterministic")?;
+    }
+    if self.bit_order == BitOrder::MsbFirst {
+      f.write_str(":msb")?;
+    }
+
+    Ok(())
+  }
+}
+
+fn lookup<T: Copy>(table: &[(&str, T)], name: &str) -> Option<T> {
+  table
+    .iter()
+    .find(|(n, _)| *n == name)
+    .map(|&(_, value)| value)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn parse(s: &str) -> Result<HasherConfig, ParseConfigError> {
+    s.parse()
+  }
+
+  #[test]
+  fn round_trip() {
+    for (spec, canonical) in [
+      ("gradient:8x8", "gradient:8x8"),
+      ("dct-gradient:10x10:lanczos3", "dct-gradient:10x10"),
+      ("dct-mean:10x10:lanczos3", "dct-mean:10x10"),
+      ("blockhash:16x16:dog(5,10)", "blockhash:16x16:dog(5,10)"),
+      ("blockhash:16x16:dog", "blockhash:16x16:dog(5,10)"),
+      (
+        "legacy-blockhash:12x8:msb:fast-dog(512):dog(2.5,7.25)",
+        "legacy-blockhash:12x8:dog(2.5,7.25):fast-dog(512):msb",
+      ),
+      (
+        "double-gradient:16x16:deterministic:catmull-rom:fast-dog",
+        "double-gradient:16x16:catmull-rom:fast-dog:deterministic",
+      ),
+      ("vert-gradient:9x7:lsb:box", "vert-gradient:9x7:box"),
+      (
+        "blockhash:16x16:dog:trim(12):mitchell",
+        "blockhash:16x16:mitchell:trim(12):dog(5,10)",
+      ),
+    ] {
+      let config = parse(spec).unwrap();
+      assert_eq!(config.to_string(), canonical, "{spec}");
+      let reparsed = parse(canonical).unwrap();
+      assert_eq!(format!("{reparsed:?}"), format!("{config:?}"), "{spec}");
+    }
+  }
+
+  #[test]
+  fn matches_builder() {
+    let parsed = parse("dct-gradient:10x10:lanczos3:deterministic").unwrap();
+    let built = HasherConfig::new()
+      .hash_size(10, 10)
+      .resize_filter(FilterType::Lanczos3)
+      .preproc_dct()
+      .deterministic();
+    assert_eq!(format!("{parsed:?}"), format!("{built:?}"));
+    assert_eq!(built.to_string(), "dct-gradient:10x10:deterministic");
+
+    let parsed: HasherConfig<[u8; 13]> = "dct-gradient:10x10".parse().unwrap();
+    assert_eq!(
+      parsed
+        .to_hasher()
+        .hash_image(&image::GrayImage::new(20, 20))
+        .as_bytes()
+        .len(),
+      13
+    );
+  }
+
+  #[test]
+  fn errors_name_the_token() {
+    for (spec, token) in [
+      ("", ""),
+      ("dct-foo:8x8", "dct-foo"),
+      ("gradient", ""),
+      ("gradient:8", "8"),
+      ("gradient:8xy", "8xy"),
+      ("gradient:8x8:lanczos4", "lanczos4"),
+      ("gradient:8x8:box:box", "box"),
+      ("gradient:8x8:box:mitchell", "mitchell"),
+      ("gradient:8x8:dog(5)", "dog(5)"),
+      ("gradient:8x8:dog(5,inf)", "dog(5,inf)"),
+      ("gradient:8x8:fast-dog(-1)", "fast-dog(-1)"),
+      ("gradient:8x8:msb:lsb", "lsb"),
+      ("gradient:8x8:deterministic(1)", "deterministic(1)"),
+      ("gradient:8x8:trim", "trim"),
+      ("gradient:8x8:trim(256)", "trim(256)"),
+      ("gradient:8x8:trim(0):trim(1)", "trim(1)"),
+      ("gradient:8x8:", ""),
+    ] {
+      let err = parse(spec).unwrap_err();
+      assert_eq!(err.token(), token, "{spec}: {err}");
+    }
+
+    // sizes are checked after rounding for the algorithm
+    let parse_13 = |s: &str| s.parse::<HasherConfig<[u8; 13]>>().map(|_| ());
+    assert_eq!(parse_13("dct-gradient:10x10"), Ok(()));
+    assert_eq!(parse_13("dct-gradient:11x10").unwrap_err().token(), "11x10");
+    assert_eq!(parse_13("blockhash:10x10").unwrap_err().token(), "10x10");
+
+    // sizes that would make hashing divide by zero or overflow when rounded
+    for size in ["0x0", "0x8", "8x0"] {
+      let err = parse(&format!("mean:{size}")).unwrap_err();
+      assert_eq!(
+        (err.token(), err.reason),
+        (size, "hash size must not be zero")
+      );
+    }
+    for spec in ["blockhash:4294967295x1", "double-gradient:1x4294967295"] {
+      let err = parse(spec).unwrap_err();
+      assert_eq!(
+        err.reason, "hash size too large for the hash type",
+        "{spec}"
+      );
+    }
+    // without overflowing `usize` on 32-bit targets
+    assert_eq!(
+      parse_13("gradient:65536x65536").unwrap_err().token(),
+      "65536x65536"
+    );
+
+    assert_eq!(
+      parse("gradient:8x8:lanczos4").unwrap_err().to_string(),
+      "invalid token `lanczos4` in hasher config: unknown option"
+    );
+  }
+}
@@ -3,13 +3,11 @@ use std::ops;
 
 use image::{imageops, DynamicImage, GenericImageView, GrayImage, ImageBuffer, Pixel};
 
+use crate::Rect;
+
 /// Interface for types used for storing hash data.
 ///
-/// This is implemented for `Vec<u8>`, `Box<[u8]>` and arrays that are multiples/combinations of
-/// useful x86 bytewise SIMD register widths (64, 128, 256, 512 bits).
-///
-/// Please feel free to open a pull request [on Github](https://github.com/qarmin/img_hash)
-/// if you need this implemented for a different array size.
+/// This is implemented for `Vec<u8>`, `Box<[u8]>`, byte arrays of any length and [`HashBits`].
 pub trait HashBytes {
   /// Construct this type from an iterator of bytes.
   ///
@@ -59,30 +57,78 @@ impl HashBytes for Vec<u8> {
   }
 }
 
-macro_rules! hash_bytes_array {
-    ($($n:expr),*) => {$(
-        impl HashBytes for [u8; $n] {
-            fn from_iter<I: Iterator<Item=u8>>(mut iter: I) -> Self {
-                // optimizer should eliminate this zeroing
-                let mut out = [0; $n];
+impl<const N: usize> HashBytes for [u8; N] {
+  fn from_iter<I: Iterator<Item = u8>>(iter: I) -> Self {
+    // optimizer should eliminate this zeroing
+    let mut out = [0; N];
 
-                for (src, dest) in iter.by_ref().zip(out.as_mut()) {
-                    *dest = src;
-                }
+    for (src, dest) in iter.zip(out.as_mut()) {
+      *dest = src;
+    }
 
-                out
-            }
+    out
+  }
 
-            fn max_bits() -> usize {
-                $n * 8
-            }
+  fn max_bits() -> usize {
+    N * 8
+  }
 
-            fn as_slice(&self) -> &[u8] { self }
-        }
-    )*}
+  fn as_slice(&self) -> &[u8] {
+    self
+  }
 }
 
-hash_bytes_array!(8, 16, 24, 32, 40, 48, 56, 64);
+/// Inline storage for hashes of exactly `BITS` bits, in the `BYTES = ceil(BITS / 8)` bytes
+/// needed to hold them.
+///
+/// Unlike `[u8; N]`, a [`HasherConfig`](crate::HasherConfig) using this type rejects hash sizes
+/// that don't fit in `BITS` rather than `BYTES * 8` bits. Name it with the
+/// [`hash_bits!`](crate::hash_bits) macro, which calculates `BYTES`:
+///
+/// ```rust
+/// use image_hasher::{hash_bits, HasherConfig};
+///
+/// type Hash100 = hash_bits!(100);
+///
+/// let config = HasherConfig::with_bytes_type::<Hash100>().hash_size(10, 10);
+/// ```
+///
+/// Using a `BYTES` other than `ceil(BITS / 8)` is a compile-time error.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HashBits<const BITS: usize, const BYTES: usize>([u8; BYTES]);
+
+impl<const BITS: usize, const BYTES: usize> HashBits<BITS, BYTES> {
+  const VALID: () = assert!(
+    BYTES == (BITS + 7) / 8,
+    "`BYTES` must be `BITS / 8` rounded up, use the `hash_bits!` macro"
+  );
+}
+
+impl<const BITS: usize, const BYTES: usize> HashBytes for HashBits<BITS, BYTES> {
+  fn from_iter<I: Iterator<Item = u8>>(iter: I) -> Self {
+    #[allow(clippy::let_unit_value)]
+    let () = Self::VALID;
+    HashBits(HashBytes::from_iter(iter))
+  }
+
+  fn max_bits() -> usize {
+    #[allow(clippy::let_unit_value)]
+    let () = Self::VALID;
+    BITS
+  }
+
+  fn as_slice(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+/// Name the [`HashBits`] type storing hashes of the given number of bits.
+#[macro_export]
+macro_rules! hash_bits {
+  ($bits:expr) => {
+    $crate::HashBits<{ $bits }, { ($bits + 7) / 8 }>
+  };
+}
 
 struct BoolsToBytes<I> {
   iter: I,
@@ -131,12 +177,9 @@ pub(crate) trait BitSet: HashBytes {
   }
 
   fn hamming(&self, other: &Self) -> u32 {
-    self
-      .as_slice()
-      .iter()
-      .zip(other.as_slice())
-      .map(|(l, r)| (l ^ r).count_ones())
-      .sum()
+    let (lhs, rhs) = (self.as_slice(), other.as_slice());
+    let len = lhs.len().min(rhs.len());
+    crate::distance::portable(&lhs[..len], &rhs[..len])
   }
 }
 
@@ -155,6 +198,11 @@ pub trait Image: GenericImageView + 'static {
   /// Blur the image with the given `Gaussian` sigma.
   fn blur(&self, sigma: f32) -> Self::Buf;
 
+  /// Copy the given rectangle of the image, used for border trimming.
+  ///
+  /// Added in 2.0, implementations outside this crate have to provide it.
+  fn crop(&self, rect: Rect) -> Self::Buf;
+
   /// Iterate over the image, passing each pixel's coordinates and values in `u8` to the closure.
   ///
   /// The iteration order is unspecified but each pixel **must** be visited exactly _once_.
@@ -192,6 +240,10 @@ where
     imageops::blur(self, sigma)
   }
 
+  fn crop(&self, rect: Rect) -> Self::Buf {
+    imageops::crop_imm(self, rect.x, rect.y, rect.width, rect.height).to_image()
+  }
+
   fn foreach_pixel8<F>(&self, mut foreach: F)
   where
     F: FnMut(u32, u32, &[u8]),
@@ -218,6 +270,10 @@ where
     imageops::blur(self, sigma)
   }
 
+  default fn crop(&self, rect: Rect) -> Self::Buf {
+    imageops::crop_imm(self, rect.x, rect.y, rect.width, rect.height).to_image()
+  }
+
   default fn foreach_pixel8<F>(&self, mut foreach: F)
   where
     F: FnMut(u32, u32, &[u8]),
@@ -252,6 +308,10 @@ impl Image for DynamicImage {
     imageops::blur(self, sigma)
   }
 
+  fn crop(&self, rect: Rect) -> Self::Buf {
+    imageops::crop_imm(self, rect.x, rect.y, rect.width, rect.height).to_image()
+  }
+
   fn foreach_pixel8<F>(&self, mut foreach: F)
   where
     F: FnMut(u32, u32, &[u8]),
@@ -281,3 +341,40 @@ fn test_bools_to_bytes() {
   let bools_to_bytes = BoolsToBytes { iter: bools };
   assert_eq!(bools_to_bytes.size_hint(), (2, Some(2)));
 }
+
+#[test]
+fn test_hamming_words() {
+  let lhs: Vec<u8> = (0..37u8).map(|x| x.wrapping_mul(73)).collect();
+  let rhs: Vec<u8> = (0..37u8).map(|x| x.wrapping_mul(151) ^ 0x5a).collect();
+
+  for len in [0, 1, 7, 8, 9, 16, 23, 37] {
+    let expected: u32 = lhs[..len]
+      .iter()
+      .zip(&rhs[..len])
+      .map(|(l, r)| (l ^ r).count_ones())
+      .sum();
+    assert_eq!(lhs[..len].to_vec().hamming(&rhs[..len].to_vec()), expected);
+  }
+}
+
+#[test]
+fn test_hash_bits() {
+  use crate::{HasherConfig, ImageHash};
+
+  type Hash100 = hash_bits!(100);
+  assert_eq!(std::mem::size_of::<Hash100>(), 13);
+  assert_eq!(Hash100::max_bits(), 100);
+
+  let image = GrayImage::from_fn(64, 64, |x, y| [(x * y % 251) as u8].into());
+  let config = || HasherConfig::new().hash_size(10, 10);
+  let hash = config().to_hasher().hash_image(&image);
+  let inline = HasherConfig::with_bytes_type::<Hash100>()
+    .hash_size(10, 10)
+    .to_hasher()
+    .hash_image(&image);
+  assert_eq!(hash.as_bytes(), inline.as_bytes());
+
+  assert!(ImageHash::<Hash100>::from_bytes(hash.as_bytes()).is_ok());
+  assert!(ImageHash::<Hash100>::from_bytes(&[0; 14]).is_err());
+  assert!(ImageHash::<[u8; 13]>::from_bytes(hash.as_bytes()).is_ok());
+}
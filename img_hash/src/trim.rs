@@ -0,0 +1,195 @@
+//! Detection of near-uniform borders, e.g. letterboxing added by scanners and screenshots.
+
+use image::GrayImage;
+
+/// A rectangle of an image, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Rect {
+  /// Column of the left edge
+  pub x: u32,
+  /// Row of the top edge
+  pub y: u32,
+  /// Width of the rectangle
+  pub width: u32,
+  /// Height of the rectangle
+  pub height: u32,
+}
+
+/// Find the part of `img` inside its near-uniform borders.
+///
+/// Each side is trimmed separately, so the borders may have different colors. A row or column
+/// belongs to a border while all of its pixels are within `tolerance` of the mean of the
+/// outermost one. Top and bottom are trimmed first, then left and right within the remaining
+/// rows. If the borders leave no content, e.g. the image is uniform or split in two colors,
+/// nothing is trimmed.
+pub(crate) fn content_rect(img: &GrayImage, tolerance: u8) -> Rect {
+  let (width, height) = img.dimensions();
+  let full = Rect {
+    x: 0,
+    y: 0,
+    width,
+    height,
+  };
+  if width == 0 || height == 0 {
+    return full;
+  }
+
+  let row = |y: u32| {
+    img.as_raw()[(y * width) as usize..][..width as usize]
+      .iter()
+      .copied()
+  };
+  let top = border_len(0..height, row, tolerance);
+  if top == height {
+    return full;
+  }
+  let bottom = border_len((top..height).rev(), row, tolerance);
+  if top + bottom == height {
+    return full;
+  }
+
+  let rows = top..height - bottom;
+  let column = |x: u32| rows.clone().map(move |y| img.get_pixel(x, y)[0]);
+  let left = border_len(0..width, column, tolerance);
+  if left == width {
+    return full;
+  }
+  let right = border_len((left..width).rev(), column, tolerance);
+  if left + right == width {
+    return full;
+  }
+
+  Rect {
+    x: left,
+    y: top,
+    width: width - left - right,
+    height: height - top - bottom,
+  }
+}
+
+/// Count the lines in `order` that are within `tolerance` of the mean of the first one.
+fn border_len<L, I>(mut order: impl Iterator<Item = u32>, line: L, tolerance: u8) -> u32
+where
+  L: Fn(u32) -> I,
+  I: Iterator<Item = u8>,
+{
+  let Some(first) = order.next() else {
+    return 0;
+  };
+  let (sum, count) = line(first).fold((0u64, 0u64), |(sum, count), px| {
+    (sum + u64::from(px), count + 1)
+  });
+  if count == 0 {
+    return 0;
+  }
+  let reference = ((sum + count / 2) / count) as u8;
+  let uniform = |i| line(i).all(|px| px.abs_diff(reference) <= tolerance);
+
+  if !uniform(first) {
+    return 0;
+  }
+  1 + order.take_while(|&i| uniform(i)).count() as u32
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  /// A noisy checkerboard of `width x height` inside borders of the given sizes and value.
+  fn bordered(
+    (width, height): (u32, u32),
+    [top, right, bottom, left]: [u32; 4],
+    border: u8,
+    noise: u8,
+  ) -> GrayImage {
+    let mut state = 0x1234_5678u32;
+    GrayImage::from_fn(left + width + right, top + height + bottom, |x, y| {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      let jitter = (state % (u32::from(noise) + 1)) as u8;
+
+      let inside = (left..left + width).contains(&x) && (top..top + height).contains(&y);
+      let value = if inside {
+        if ((x - left) / 8 + (y - top) / 8) % 2 == 0 {
+          60
+        } else {
+          180
+        }
+      } else {
+        border.saturating_sub(jitter)
+      };
+      [value].into()
+    })
+  }
+
+  fn trim(img: &GrayImage, tolerance: u8) -> (u32, u32, u32, u32) {
+    let Rect {
+      x,
+      y,
+      width,
+      height,
+    } = content_rect(img, tolerance);
+    (x, y, width, height)
+  }
+
+  #[test]
+  fn trims_each_side() {
+    let img = bordered((64, 48), [10, 3, 0, 25], 255, 0);
+    assert_eq!(trim(&img, 0), (25, 10, 64, 48));
+
+    let img = bordered((40, 40), [0, 7, 12, 0], 0, 0);
+    assert_eq!(trim(&img, 0), (0, 0, 40, 40));
+  }
+
+  #[test]
+  fn tolerance() {
+    let img = bordered((64, 48), [9, 9, 9, 9], 250, 6);
+    assert_eq!(trim(&img, 8), (9, 9, 64, 48));
+    assert_eq!(trim(&img, 0), (0, 0, 82, 66));
+  }
+
+  #[test]
+  fn uniform_is_untouched() {
+    let img = GrayImage::from_pixel(31, 17, [200].into());
+    assert_eq!(trim(&img, 0), (0, 0, 31, 17));
+    assert_eq!(trim(&GrayImage::new(0, 0), 0), (0, 0, 0, 0));
+  }
+
+  #[test]
+  fn split_is_untouched() {
+    let top_bottom = GrayImage::from_fn(40, 30, |_, y| [if y < 15 { 255 } else { 0 }].into());
+    let left_right = GrayImage::from_fn(40, 30, |x, _| [if x < 20 { 255 } else { 0 }].into());
+    let hasher = crate::HasherConfig::new()
+      .preproc_trim_borders(8)
+      .to_hasher();
+    for img in [top_bottom, left_right] {
+      assert_eq!(trim(&img, 8), (0, 0, 40, 30));
+      let (_, rect) = hasher.hash_image_with_crop(&img);
+      assert_eq!(rect.map(|rect| (rect.width, rect.height)), Some((40, 30)));
+    }
+  }
+
+  #[test]
+  fn hash_ignores_borders() {
+    let hasher = crate::HasherConfig::new()
+      .hash_size(10, 10)
+      .preproc_dct()
+      .preproc_trim_borders(8)
+      .to_hasher();
+    let content = bordered((120, 90), [0; 4], 0, 0);
+    let letterboxed = bordered((120, 90), [30, 0, 30, 0], 10, 6);
+    let pillarboxed = bordered((120, 90), [0, 45, 0, 45], 245, 6);
+
+    let (expected, rect) = hasher.hash_image_with_crop(&content);
+    assert_eq!(rect.map(|rect| (rect.width, rect.height)), Some((120, 90)));
+    for img in [letterboxed, pillarboxed] {
+      let (hash, rect) = hasher.hash_image_with_crop(&img);
+      assert_eq!(hash, expected);
+      assert_eq!(rect.map(|rect| (rect.width, rect.height)), Some((120, 90)));
+    }
+
+    let untrimmed = crate::HasherConfig::new().to_hasher();
+    assert_eq!(untrimmed.hash_image_with_crop(&content).1, None);
+  }
+}
@@ -0,0 +1,214 @@
+#!/usr/bin/env python3
+"""Generate the blockhash test images and their expected hashes.
+
+The hashing functions are a line-for-line port of the reference implementation,
+https://github.com/commonsmachinery/blockhash-python/blob/e8b009d/blockhash.py,
+with PIL replaced by plain pixel lists so this runs without dependencies.
+
+The hashes are only as good as the port: they catch regressions, conformance with
+upstream is checked by the ignored `upstream_hashes` test against the published
+test data of https://github.com/commonsmachinery/blockhash.
+
+Usage: python3 generate.py > hashes.txt
+"""
+
+import math
+import struct
+import zlib
+
+
+def median(data):
+    data = sorted(data)
+    length = len(data)
+    if length % 2 == 0:
+        return (data[length // 2 - 1] + data[length // 2]) / 2.0
+    return data[length // 2]
+
+
+def total_value(px):
+    # images are converted to RGB(A) by the reference implementation
+    if len(px) == 4 and px[3] == 0:
+        return 765
+    if len(px) <= 2:
+        return 765 if len(px) == 2 and px[1] == 0 else px[0] * 3
+    return px[0] + px[1] + px[2]
+
+
+def bits_to_hexhash(bits):
+    return '{0:0={width}x}'.format(int(''.join([str(x) for x in bits]), 2), width=len(bits) // 4)
+
+
+def translate_blocks_to_bits(blocks, pixels_per_block):
+    half_block_value = pixels_per_block * 256 * 3 / 2
+
+    # Compare medians across four horizontal bands
+    bandsize = len(blocks) // 4
+    for i in range(4):
+        m = median(blocks[i * bandsize:(i + 1) * bandsize])
+        for j in range(i * bandsize, (i + 1) * bandsize):
+            v = blocks[j]
+            blocks[j] = int(v > m or (abs(v - m) < 1 and m > half_block_value))
+
+
+def blockhash_even(width, height, pixels, bits):
+    blocksize_x = width // bits
+    blocksize_y = height // bits
+
+    result = []
+
+    for y in range(bits):
+        for x in range(bits):
+            value = 0
+
+            for iy in range(blocksize_y):
+                for ix in range(blocksize_x):
+                    cx = x * blocksize_x + ix
+                    cy = y * blocksize_y + iy
+                    value += total_value(pixels[cy * width + cx])
+
+            result.append(value)
+
+    translate_blocks_to_bits(result, blocksize_x * blocksize_y)
+    return bits_to_hexhash(result)
+
+
+def blockhash(width, height, pixels, bits):
+    if width % bits == 0 and height % bits == 0:
+        return blockhash_even(width, height, pixels, bits)
+
+    even_x = width % bits == 0
+    even_y = height % bits == 0
+
+    blocks = [[0 for col in range(bits)] for row in range(bits)]
+
+    block_width = float(width) / bits
+    block_height = float(height) / bits
+
+    for y in range(height):
+        if even_y:
+            # don't bother dividing y, if the size evenly divides by bits
+            block_top = block_bottom = int(y // block_height)
+            weight_top, weight_bottom = 1, 0
+        else:
+            y_frac, y_int = math.modf((y + 1) % block_height)
+
+            weight_top = (1 - y_frac)
+            weight_bottom = (y_frac)
+
+            # y_int will be 0 on bottom/right borders and on block boundaries
+            if y_int > 0 or (y + 1) == height:
+                block_top = block_bottom = int(y // block_height)
+            else:
+                block_top = int(y // block_height)
+                block_bottom = int(-(-y // block_height))  # int(math.ceil(float(y) / block_height))
+
+        for x in range(width):
+            value = total_value(pixels[y * width + x])
+
+            if even_x:
+                # don't bother dividing x, if the size evenly divides by bits
+                block_left = block_right = int(x // block_width)
+                weight_left, weight_right = 1, 0
+            else:
+                x_frac, x_int = math.modf((x + 1) % block_width)
+
+                weight_left = (1 - x_frac)
+                weight_right = (x_frac)
+
+                # x_int will be 0 on bottom/right borders and on block boundaries
+                if x_int > 0 or (x + 1) == width:
+                    block_left = block_right = int(x // block_width)
+                else:
+                    block_left = int(x // block_width)
+                    block_right = int(-(-x // block_width))  # int(math.ceil(float(x) / block_width))
+
+            # add weighted pixel value to relevant blocks
+            blocks[block_top][block_left] += value * weight_top * weight_left
+            blocks[block_top][block_right] += value * weight_top * weight_right
+            blocks[block_bottom][block_left] += value * weight_bottom * weight_left
+            blocks[block_bottom][block_right] += value * weight_bottom * weight_right
+
+    result = [blocks[row][col] for row in range(bits) for col in range(bits)]
+
+    translate_blocks_to_bits(result, block_width * block_height)
+    return bits_to_hexhash(result)
+
+
+def write_png(path, width, height, channels, pixels):
+    color_type = {1: 0, 2: 4, 3: 2, 4: 6}[channels]
+    raw = b''.join(
+        b'\0' + bytes(c for px in pixels[y * width:(y + 1) * width] for c in px)
+        for y in range(height)
+    )
+
+    def chunk(kind, data):
+        body = kind + data
+        return struct.pack('>I', len(data)) + body + struct.pack('>I', zlib.crc32(body))
+
+    with open(path, 'wb') as f:
+        f.write(b'\x89PNG\r\n\x1a\n')
+        f.write(chunk(b'IHDR', struct.pack('>IIBBBBB', width, height, 8, color_type, 0, 0, 0)))
+        f.write(chunk(b'IDAT', zlib.compress(raw, 9)))
+        f.write(chunk(b'IEND', b''))
+
+
+def scene(width, height, seed):
+    """Colored discs on a gradient with a little noise, like `gen_scene_img` in the crate."""
+    state = seed
+
+    def rand():
+        nonlocal state
+        state ^= (state << 13) & 0xffffffff
+        state ^= state >> 17
+        state ^= (state << 5) & 0xffffffff
+        return state
+
+    shapes = []
+    for _ in range(8):
+        cx, cy = rand() % width, rand() % height
+        r = rand() % (min(width, height) // 3) + min(width, height) // 16
+        shapes.append((cx, cy, r * r, rand().to_bytes(4, 'little')))
+
+    pixels = []
+    for y in range(height):
+        for x in range(width):
+            base = (x + y) * 200 // (width + height)
+            px = [base, base // 2 + 60, 255 - base, 255]
+            for cx, cy, r2, color in shapes:
+                if (x - cx) ** 2 + (y - cy) ** 2 < r2:
+                    px = list(color)
+            noise = rand() % 9
+            px = [min(255, max(0, c + noise - 4)) for c in px[:3]] + [px[3]]
+            pixels.append(tuple(px))
+    return pixels
+
+
+IMAGES = [
+    # name, width, height, channels, seed
+    ('even', 256, 256, 3, 1),
+    ('odd', 301, 203, 3, 2),
+    ('wide', 640, 121, 3, 3),
+    ('tall', 123, 480, 3, 4),
+    ('alpha', 200, 150, 4, 5),
+    ('gray', 97, 64, 1, 6),
+    ('small', 13, 11, 3, 7),
+]
+
+if __name__ == '__main__':
+    for name, width, height, channels, seed in IMAGES:
+        pixels = scene(width, height, seed)
+        if channels == 4:
+            # punch a transparent hole into the image
+            pixels = [
+                px[:3] + (0,) if (x - width // 3) ** 2 + (y - height // 2) ** 2 < (height // 3) ** 2 else px
+                for i, px in enumerate(pixels)
+                for x, y in [(i % width, i // width)]
+            ]
+        elif channels == 1:
+            pixels = [((px[0] * 2126 + px[1] * 7152 + px[2] * 722) // 10000,) for px in pixels]
+        else:
+            pixels = [px[:3] for px in pixels]
+
+        write_png(f'{name}.png', width, height, channels, pixels)
+        for bits in (8, 16):
+            print(f'{name}.png {bits} {blockhash(width, height, pixels, bits)}')
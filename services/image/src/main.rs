@@ -0,0 +1,124 @@
+//! A self-hostable `wk-image`: the same upload endpoint and responses, storing images in a
+//! directory or an S3-compatible bucket instead of R2.
+//!
+//! Processing is done by `cotrans-image-core`, so stored images and hashes are identical to
+//! the ones of `wk-image`.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread;
+
+use clap::{ArgGroup, Parser};
+use image_core::{BlobStore, Codec, ImagePipeline, Limits, DEFAULT_HASHER, DEFAULT_LIMITS};
+use url::Url;
+
+use crate::server::Service;
+use crate::store::{FsStore, S3Store};
+
+mod multipart;
+mod server;
+mod store;
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+#[command(group(ArgGroup::new("storage").required(true).args(["storage_dir", "s3_bucket"])))]
+struct Cli {
+  /// Address to listen on; requests aren't authenticated, only listen on localhost or a private
+  /// network
+  #[arg(long, env = "LISTEN", default_value = "127.0.0.1:8080")]
+  listen: SocketAddr,
+
+  /// Number of requests processed in parallel, defaults to the number of CPUs
+  #[arg(long, env = "THREADS")]
+  threads: Option<usize>,
+
+  /// Hasher config string, see `wk-image`'s `HASHER` variable
+  #[arg(long, env = "HASHER", default_value = DEFAULT_HASHER)]
+  hasher: String,
+
+  /// Maximum width and height of uploaded images
+  #[arg(long, env = "MAX_IMAGE_DIMENSION")]
+  max_dimension: Option<u32>,
+
+  /// Maximum number of pixels of uploaded images
+  #[arg(long, env = "MAX_IMAGE_PIXELS")]
+  max_pixels: Option<u64>,
+
+  /// Maximum size of decoded uploads in bytes
+  #[arg(long, env = "MAX_IMAGE_ALLOC")]
+  max_alloc: Option<u64>,
+
+  /// Format images are stored in, see `wk-image`'s `CODEC` variable
+  #[arg(long, env = "CODEC", default_value = "png:fast")]
+  codec: Codec,
+
+  /// Store images in this directory
+  #[arg(long, env = "STORAGE_DIR")]
+  storage_dir: Option<PathBuf>,
+
+  /// Store images in this bucket of an S3-compatible API
+  #[arg(
+    long,
+    env = "S3_BUCKET",
+    requires_all = ["s3_endpoint", "s3_access_key", "s3_secret_key"]
+  )]
+  s3_bucket: Option<String>,
+
+  /// Endpoint of the S3 API, e.g. `https://<account>.r2.cloudflarestorage.com`
+  #[arg(long, env = "S3_ENDPOINT")]
+  s3_endpoint: Option<Url>,
+
+  /// Region of the bucket
+  #[arg(long, env = "S3_REGION", default_value = "auto")]
+  s3_region: String,
+
+  #[arg(long, env = "S3_ACCESS_KEY_ID", hide_env_values = true)]
+  s3_access_key: Option<String>,
+
+  #[arg(long, env = "S3_SECRET_ACCESS_KEY", hide_env_values = true)]
+  s3_secret_key: Option<String>,
+}
+
+fn main() -> ExitCode {
+  let cli = Cli::parse();
+
+  let pipeline = match ImagePipeline::new(&cli.hasher) {
+    Ok(pipeline) => pipeline,
+    Err(e) => {
+      eprintln!("image-service: invalid hasher config: {e}");
+      return ExitCode::FAILURE;
+    }
+  };
+  let default = DEFAULT_LIMITS;
+  let pipeline = pipeline
+    .with_limits(Limits {
+      max_dimension: cli.max_dimension.unwrap_or(default.max_dimension),
+      max_pixels: cli.max_pixels.unwrap_or(default.max_pixels),
+      max_alloc: cli.max_alloc.unwrap_or(default.max_alloc),
+    })
+    .with_codec(cli.codec);
+
+  let store: Box<dyn BlobStore + Send + Sync> = match (cli.storage_dir, cli.s3_bucket) {
+    (Some(dir), _) => Box::new(FsStore::new(dir)),
+    (None, Some(bucket)) => Box::new(S3Store::new(
+      cli.s3_endpoint.unwrap(),
+      bucket,
+      cli.s3_region,
+      cli.s3_access_key.unwrap(),
+      cli.s3_secret_key.unwrap(),
+    )),
+    (None, None) => unreachable!("storage is required"),
+  };
+
+  let threads = cli
+    .threads
+    .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+  match server::serve(Service { pipeline, store }, cli.listen, threads) {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("image-service: {e}");
+      ExitCode::FAILURE
+    }
+  }
+}
@@ -0,0 +1,206 @@
+//! Just enough of `multipart/form-data` (RFC 7578) to read uploads from a buffered body.
+
+/// A field of a form.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Part<'a> {
+  pub name: String,
+  /// Set for files
+  pub filename: Option<String>,
+  pub data: &'a [u8],
+}
+
+/// The boundary of a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+  let (mime, params) = content_type.split_once(';')?;
+  if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+    return None;
+  }
+  params_of(params)
+    .into_iter()
+    .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+    .map(|(_, boundary)| boundary)
+    .filter(|boundary| !boundary.is_empty())
+}
+
+/// Split a body into its parts, or `None` if it isn't valid.
+pub fn parse<'a>(body: &'a [u8], boundary: &str) -> Option<Vec<Part<'a>>> {
+  let delimiter = format!("\r\n--{boundary}");
+  let delimiter = delimiter.as_bytes();
+
+  // the first delimiter doesn't have to follow a line break
+  let mut pos = if body.starts_with(&delimiter[2..]) {
+    delimiter.len() - 2
+  } else {
+    find(body, delimiter)? + delimiter.len()
+  };
+
+  let mut parts = vec![];
+  loop {
+    let rest = &body[pos..];
+    if rest.starts_with(b"--") {
+      return Some(parts);
+    }
+    let line_end = find(rest, b"\r\n")?;
+    if rest[..line_end].iter().any(|&b| b != b' ' && b != b'\t') {
+      return None;
+    }
+    pos += line_end + 2;
+
+    let len = find(&body[pos..], delimiter)?;
+    parts.push(part(&body[pos..pos + len])?);
+    pos += len + delimiter.len();
+  }
+}
+
+fn part(part: &[u8]) -> Option<Part> {
+  let (headers, data) = if part.starts_with(b"\r\n") {
+    (&b""[..], &part[2..])
+  } else {
+    let end = find(part, b"\r\n\r\n")?;
+    (&part[..end], &part[end + 4..])
+  };
+
+  let headers = std::str::from_utf8(headers).ok()?;
+  let disposition = headers.split("\r\n").find_map(|line| {
+    let (name, value) = line.split_once(':')?;
+    name
+      .trim()
+      .eq_ignore_ascii_case("content-disposition")
+      .then_some(value)
+  })?;
+
+  let (kind, params) = disposition.split_once(';')?;
+  if !kind.trim().eq_ignore_ascii_case("form-data") {
+    return None;
+  }
+  let mut name = None;
+  let mut filename = None;
+  for (param, value) in params_of(params) {
+    if param.eq_ignore_ascii_case("name") {
+      name = Some(value);
+    } else if param.eq_ignore_ascii_case("filename") {
+      filename = Some(value);
+    }
+  }
+
+  Some(Part {
+    name: name?,
+    filename,
+    data,
+  })
+}
+
+/// Parse `; name=value` parameters, where values may be quoted.
+fn params_of(mut params: &str) -> Vec<(String, String)> {
+  let mut parsed = vec![];
+  loop {
+    params = params.trim_start_matches(|c: char| c == ';' || c.is_ascii_whitespace());
+    let Some((name, rest)) = params.split_once('=') else {
+      return parsed;
+    };
+
+    let mut value = String::new();
+    if let Some(quoted) = rest.strip_prefix('"') {
+      let mut chars = quoted.char_indices();
+      params = "";
+      while let Some((i, c)) = chars.next() {
+        match c {
+          '"' => {
+            params = &quoted[i + 1..];
+            break;
+          }
+          '\\' => value.extend(chars.next().map(|(_, c)| c)),
+          c => value.push(c),
+        }
+      }
+    } else {
+      let end = rest.find(';').unwrap_or(rest.len());
+      value.push_str(rest[..end].trim());
+      params = &rest[end..];
+    }
+
+    parsed.push((name.trim().to_owned(), value));
+  }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack
+    .windows(needle.len())
+    .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn boundaries() {
+    assert_eq!(
+      boundary("multipart/form-data; boundary=abc").as_deref(),
+      Some("abc")
+    );
+    assert_eq!(
+      boundary("Multipart/Form-Data;charset=utf-8; Boundary=\"a b;c\"").as_deref(),
+      Some("a b;c")
+    );
+    assert_eq!(boundary("multipart/mixed; boundary=abc"), None);
+    assert_eq!(boundary("multipart/form-data; boundary="), None);
+    assert_eq!(boundary("multipart/form-data"), None);
+  }
+
+  #[test]
+  fn parses_forms() {
+    let body = b"preamble\r\n--xyz\r\n\
+      Content-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".png\"\r\n\
+      Content-Type: image/png\r\n\r\n\
+      \x89PNG\r\n--xy\r\n\
+      --xyz  \r\n\
+      content-disposition: form-data; name=mime\r\n\r\n\
+      image/png\r\n\
+      --xyz--\r\nepilogue";
+    assert_eq!(
+      parse(body, "xyz").unwrap(),
+      [
+        Part {
+          name: "file".into(),
+          filename: Some("a \"b\".png".into()),
+          data: b"\x89PNG\r\n--xy",
+        },
+        Part {
+          name: "mime".into(),
+          filename: None,
+          data: b"image/png",
+        }
+      ]
+    );
+
+    assert_eq!(parse(b"--xyz--", "xyz").unwrap(), []);
+    assert_eq!(
+      parse(
+        b"--b\r\nContent-Disposition: form-data; name=a\r\n\r\n\r\n--b--",
+        "b"
+      )
+      .unwrap(),
+      [Part {
+        name: "a".into(),
+        filename: None,
+        data: b"",
+      }]
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_forms() {
+    let part = "--b\r\nContent-Disposition: form-data; name=a\r\n\r\ndata";
+    assert!(parse(part.as_bytes(), "b").is_none());
+    assert!(parse(format!("{part}\r\n--c--").as_bytes(), "b").is_none());
+    assert!(parse(b"--b\r\nContent-Type: text/plain\r\n\r\nx\r\n--b--", "b").is_none());
+    assert!(parse(
+      b"--b\r\nContent-Disposition: attachment; name=a\r\n\r\nx\r\n--b--",
+      "b"
+    )
+    .is_none());
+    assert!(parse(b"--bx\r\n\r\n--b--", "b").is_none());
+    assert!(parse(b"", "b").is_none());
+  }
+}
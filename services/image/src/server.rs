@@ -0,0 +1,145 @@
+//! The upload endpoint of `wk-image`'s `DOImage`.
+//!
+//! `POST` a `multipart/form-data` body with a `file` and optional `mime`, `codec` and `compare`
+//! fields to get the same JSON `wk-image` responds with, for uploads and for errors.
+//!
+//! Like `wk-image`, which is only reachable through its Durable Object binding, requests are not
+//! authenticated.
+
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+use futures_executor::block_on;
+use image_core::{BlobStore, Error, ImagePipeline, Options};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use crate::multipart;
+
+/// Larger request bodies are rejected, like by Cloudflare.
+const MAX_BODY: u64 = 100 << 20;
+
+/// Processes uploads and stores them.
+pub struct Service {
+  pub pipeline: ImagePipeline,
+  pub store: Box<dyn BlobStore + Send + Sync>,
+}
+
+/// Serve requests on `addr` with `threads` workers, until the process is killed.
+pub fn serve(service: Service, addr: SocketAddr, threads: usize) -> io::Result<()> {
+  let server = Server::http(addr).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+  eprintln!(
+    "image-service: listening on http://{}",
+    server.server_addr()
+  );
+  run(Arc::new(server), Arc::new(service), threads);
+  Ok(())
+}
+
+fn run(server: Arc<Server>, service: Arc<Service>, threads: usize) {
+  let workers: Vec<_> = (0..threads.max(1))
+    .map(|_| {
+      let (server, service) = (server.clone(), service.clone());
+      thread::spawn(move || {
+        for request in server.incoming_requests() {
+          if let Err(err) = handle(request, &service) {
+            eprintln!("image-service: {err}");
+          }
+        }
+      })
+    })
+    .collect();
+
+  for worker in workers {
+    let _ = worker.join();
+  }
+}
+
+fn handle(mut request: Request, service: &Service) -> io::Result<()> {
+  if *request.method() != Method::Post {
+    return request.respond(Response::from_string("Method not allowed").with_status_code(405));
+  }
+  if request
+    .body_length()
+    .map_or(false, |len| len as u64 > MAX_BODY)
+  {
+    return request.respond(error(&Error::FileTooLarge));
+  }
+
+  let boundary = request
+    .headers()
+    .iter()
+    .find(|header| header.field.equiv("Content-Type"))
+    .and_then(|header| multipart::boundary(header.value.as_str()));
+  let Some(boundary) = boundary else {
+    return request.respond(error(&Error::InvalidForm));
+  };
+
+  let mut body = vec![];
+  request
+    .as_reader()
+    .take(MAX_BODY + 1)
+    .read_to_end(&mut body)?;
+  if body.len() as u64 > MAX_BODY {
+    return request.respond(error(&Error::FileTooLarge));
+  }
+  let Some(form) = multipart::parse(&body, &boundary) else {
+    return request.respond(error(&Error::InvalidForm));
+  };
+
+  let Some(file) = form
+    .iter()
+    .find(|part| part.name == "file" && part.filename.is_some())
+  else {
+    return request.respond(error(&Error::NoFile));
+  };
+  let field = |name| {
+    form
+      .iter()
+      .find(|part| part.name == name && part.filename.is_none())
+      .map(|part| String::from_utf8_lossy(part.data).into_owned())
+  };
+
+  let options = match Options::from_form(
+    field("mime"),
+    field("codec").as_deref(),
+    field("compare").as_deref(),
+  ) {
+    Ok(options) => options,
+    Err(err) => return request.respond(error(&err)),
+  };
+  let result = block_on(
+    service
+      .pipeline
+      .upload(&*service.store, file.data.to_vec(), &options),
+  );
+  drop(body);
+
+  match result {
+    Ok(upload) => {
+      let json = serde_json::to_vec(&upload)?;
+      request.respond(json_response(json, 200))
+    }
+    Err(err) => {
+      if err.status() >= 500 {
+        match (&err, std::error::Error::source(&err)) {
+          (Error::Panic(message), _) => eprintln!("image-service: {err}: {message}"),
+          (_, Some(source)) => eprintln!("image-service: {err}: {source}"),
+          _ => eprintln!("image-service: {err}"),
+        }
+      }
+      request.respond(error(&err))
+    }
+  }
+}
+
+fn error(err: &Error) -> Response<io::Cursor<Vec<u8>>> {
+  json_response(serde_json::to_vec(err).unwrap(), err.status())
+}
+
+fn json_response(json: Vec<u8>, status: u16) -> Response<io::Cursor<Vec<u8>>> {
+  Response::from_data(json)
+    .with_status_code(StatusCode(status))
+    .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
@@ -0,0 +1,321 @@
+//! Starts `image-service` on localhost and uploads to it like the gateway does to `wk-image`.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+use image_core::{Codec, ImagePipeline, Options, Upload, DEFAULT_HASHER};
+use sha2::{Digest, Sha256};
+
+/// A running `image-service`, killed on drop.
+struct Service {
+  child: Child,
+  addr: SocketAddr,
+}
+
+impl Service {
+  fn start(args: &[&str]) -> Self {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_image-service"))
+      .args(["--listen", "127.0.0.1:0", "--threads", "2"])
+      .args(args)
+      .env_clear()
+      .stderr(Stdio::piped())
+      .spawn()
+      .unwrap();
+
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let addr = line
+      .trim()
+      .strip_prefix("image-service: listening on http://")
+      .unwrap_or_else(|| panic!("unexpected output: {line}"))
+      .parse()
+      .unwrap();
+    // keep draining stderr so the service never blocks on it
+    thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+
+    Self { child, addr }
+  }
+
+  /// Send a request and return the status code and body of the response.
+  fn request(&self, method: &str, content_type: &str, body: &[u8]) -> (u16, String) {
+    let mut stream = TcpStream::connect(self.addr).unwrap();
+    write!(
+      stream,
+      "{method} / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n",
+      body.len()
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_owned();
+    (status, body)
+  }
+
+  /// Upload a form with a file and the given text fields.
+  fn upload(&self, file: Option<&[u8]>, fields: &[(&str, &str)]) -> (u16, String) {
+    let mut body = vec![];
+    if let Some(file) = file {
+      body.extend_from_slice(
+        b"--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"blob\"\r\n\
+          Content-Type: application/octet-stream\r\n\r\n",
+      );
+      body.extend_from_slice(file);
+      body.extend_from_slice(b"\r\n");
+    }
+    for (name, value) in fields {
+      body.extend_from_slice(
+        format!("--boundary\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n")
+          .as_bytes(),
+      );
+    }
+    body.extend_from_slice(b"--boundary--\r\n");
+    self.request("POST", "multipart/form-data; boundary=boundary", &body)
+  }
+}
+
+impl Drop for Service {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+fn png() -> Vec<u8> {
+  let image = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 48, |x, y| {
+    [(x * 4) as u8, (y * 5) as u8, 128].into()
+  }));
+  let mut png = vec![];
+  image
+    .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+    .unwrap();
+  png
+}
+
+/// The status and `code` of an error response.
+fn code((status, body): (u16, String)) -> (u16, String) {
+  let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+  (status, error["code"].as_str().unwrap().to_owned())
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("cotrans-{name}-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&dir);
+  dir
+}
+
+#[test]
+fn stores_in_a_directory() {
+  let dir = temp_dir("image-service");
+  let service = Service::start(&["--storage-dir", dir.to_str().unwrap()]);
+  let png = png();
+
+  let (expected, data) = ImagePipeline::new(DEFAULT_HASHER)
+    .unwrap()
+    .encode(png.clone(), &Options::default())
+    .unwrap();
+
+  let (status, body) = service.upload(Some(&png), &[("mime", "image/png")]);
+  assert_eq!(status, 200, "{body}");
+  assert_eq!(serde_json::from_str::<Upload>(&body).unwrap(), expected);
+  assert_eq!(body, serde_json::to_string(&expected).unwrap());
+  assert_eq!(std::fs::read(dir.join(&expected.key)).unwrap(), data);
+
+  // the format is guessed without a `mime` field
+  assert_eq!(service.upload(Some(&png), &[]), (200, body));
+
+  assert_eq!(
+    code(service.upload(Some(&png), &[("mime", "image/x-unknown")])),
+    (400, "invalid-mime".into())
+  );
+  assert_eq!(
+    code(service.upload(Some(b"not an image"), &[])),
+    (400, "unknown-format".into())
+  );
+  assert_eq!(
+    service.upload(Some(&png[..100]), &[]),
+    (
+      400,
+      r#"{"code":"invalid-image","message":"Invalid image","details":{"format":"image/png","width":64,"height":48}}"#
+        .into()
+    )
+  );
+  let bomb = std::fs::read(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../image-core/testdata/bomb.png"
+  ))
+  .unwrap();
+  assert_eq!(
+    code(service.upload(Some(&bomb), &[])),
+    (413, "image-too-large".into())
+  );
+  assert_eq!(
+    code(service.upload(None, &[("file", "not a file")])),
+    (400, "no-file".into())
+  );
+  assert_eq!(
+    code(service.request("POST", "application/octet-stream", &png)),
+    (400, "invalid-form".into())
+  );
+  assert_eq!(service.request("GET", "text/plain", b"").0, 405);
+
+  drop(service);
+  std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// A request received by the fake S3 API.
+struct Put {
+  path: String,
+  authorization: String,
+  content_sha256: String,
+  body: Vec<u8>,
+}
+
+fn header(request: &tiny_http::Request, name: &'static str) -> String {
+  request
+    .headers()
+    .iter()
+    .find(|header| header.field.equiv(name))
+    .map_or_else(String::new, |header| header.value.to_string())
+}
+
+/// Start a fake S3 API responding with `status`, sending the requests it receives to the
+/// returned channel.
+fn fake_s3(status: u16) -> (SocketAddr, mpsc::Receiver<Put>) {
+  let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+  let addr = server.server_addr().to_ip().unwrap();
+  let (tx, rx) = mpsc::channel();
+
+  thread::spawn(move || {
+    for mut request in server.incoming_requests() {
+      let mut put = Put {
+        path: request.url().to_owned(),
+        authorization: header(&request, "Authorization"),
+        content_sha256: header(&request, "x-amz-content-sha256"),
+        body: vec![],
+      };
+      assert_eq!(*request.method(), tiny_http::Method::Put);
+      request.as_reader().read_to_end(&mut put.body).unwrap();
+      let _ = tx.send(put);
+      let _ = request.respond(tiny_http::Response::empty(status));
+    }
+  });
+
+  (addr, rx)
+}
+
+#[test]
+fn limits_images() {
+  let dir = temp_dir("image-service-limits");
+  let service = Service::start(&[
+    "--storage-dir",
+    dir.to_str().unwrap(),
+    "--max-dimension",
+    "50",
+  ]);
+  assert_eq!(
+    code(service.upload(Some(&png()), &[])),
+    (413, "image-too-large".into())
+  );
+  assert!(!dir.exists());
+}
+
+#[test]
+fn stores_codecs() {
+  let dir = temp_dir("image-service-codecs");
+  let service = Service::start(&["--storage-dir", dir.to_str().unwrap(), "--codec", "webp"]);
+  let png = png();
+
+  let (status, body) = service.upload(Some(&png), &[]);
+  assert_eq!(status, 200, "{body}");
+  let upload: Upload = serde_json::from_str(&body).unwrap();
+  assert_eq!(
+    (upload.codec, upload.key.ends_with(".webp")),
+    (Codec::WebP, true)
+  );
+  let data = std::fs::read(dir.join(&upload.key)).unwrap();
+  assert_eq!(
+    image::guess_format(&data).unwrap(),
+    image::ImageFormat::WebP
+  );
+
+  let (status, body) = service.upload(Some(&png), &[("codec", "png"), ("compare", "webp")]);
+  assert_eq!(status, 200, "{body}");
+  let compared: Upload = serde_json::from_str(&body).unwrap();
+  assert_eq!(compared.key, format!("upload/{}.png", upload.sha));
+  assert_eq!(compared.sizes[&Codec::WebP], upload.size);
+  assert_eq!(compared.sizes[&Codec::default()], compared.size);
+
+  assert_eq!(
+    code(service.upload(Some(&png), &[("codec", "gif")])),
+    (400, "invalid-codec".into())
+  );
+
+  drop(service);
+  std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn stores_in_an_s3_bucket() {
+  let (addr, puts) = fake_s3(200);
+  let endpoint = format!("http://{addr}");
+  let service = Service::start(&[
+    "--s3-bucket",
+    "cotrans-private",
+    "--s3-endpoint",
+    &endpoint,
+    "--s3-access-key",
+    "key-id",
+    "--s3-secret-key",
+    "secret",
+  ]);
+
+  let (status, body) = service.upload(Some(&png()), &[]);
+  assert_eq!(status, 200, "{body}");
+  let upload: Upload = serde_json::from_str(&body).unwrap();
+
+  let put = puts.recv().unwrap();
+  assert_eq!(put.path, format!("/cotrans-private/{}", upload.key));
+  assert!(
+    put
+      .authorization
+      .starts_with("AWS4-HMAC-SHA256 Credential=key-id/"),
+    "{}",
+    put.authorization
+  );
+  assert_eq!(put.content_sha256, hex::encode(Sha256::digest(&put.body)));
+  assert_eq!(put.body.len(), upload.size);
+  assert_eq!(
+    image::load_from_memory(&put.body).unwrap(),
+    image::load_from_memory(&png()).unwrap()
+  );
+}
+
+#[test]
+fn reports_storage_errors() {
+  let (addr, puts) = fake_s3(503);
+  let endpoint = format!("http://{addr}");
+  let service = Service::start(&[
+    "--s3-bucket",
+    "bucket",
+    "--s3-endpoint",
+    &endpoint,
+    "--s3-access-key",
+    "key-id",
+    "--s3-secret-key",
+    "secret",
+  ]);
+
+  assert_eq!(
+    code(service.upload(Some(&png()), &[])),
+    (500, "store-failed".into())
+  );
+  assert!(puts.recv().is_ok());
+}
@@ -1,3 +1,161 @@
-fn main() {
-    println!("Hello, world!");
+//! Hash images exactly like `wk-image` does on upload, from the command line or over HTTP
+//!
+//! `phash hash` prints one JSON object per input, with the fields of `wk-image`'s response.
+//! It exits with code `2` if any input could not be processed.
+
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::thread;
+
+use clap::{Args, Parser, Subcommand};
+
+use image_core::{ImagePipeline, Limits, Options, Upload, DEFAULT_HASHER, DEFAULT_LIMITS};
+
+mod server;
+
+const EXIT_ERROR: u8 = 2;
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+  /// Hasher config string, must match the one `wk-image` is deployed with
+  #[arg(long, env = "HASHER", default_value = DEFAULT_HASHER, global = true)]
+  hasher: String,
+
+  /// Maximum width and height of images, must match `wk-image`'s `MAX_IMAGE_DIMENSION`
+  #[arg(long, env = "MAX_IMAGE_DIMENSION", global = true)]
+  max_dimension: Option<u32>,
+
+  /// Maximum number of pixels of images, must match `wk-image`'s `MAX_IMAGE_PIXELS`
+  #[arg(long, env = "MAX_IMAGE_PIXELS", global = true)]
+  max_pixels: Option<u64>,
+
+  /// Maximum size of decoded images in bytes, must match `wk-image`'s `MAX_IMAGE_ALLOC`
+  #[arg(long, env = "MAX_IMAGE_ALLOC", global = true)]
+  max_alloc: Option<u64>,
+
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Hash files, or stdin if the path is `-`
+  Hash(HashArgs),
+  /// Serve `POST` requests with an image as the body
+  Serve(ServeArgs),
+}
+
+#[derive(Args, Debug)]
+struct HashArgs {
+  /// MIME type of the inputs, guessed from their contents if not set
+  #[arg(long)]
+  mime: Option<String>,
+
+  /// Files to hash
+  #[arg(required = true)]
+  inputs: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct ServeArgs {
+  /// Address to listen on
+  #[arg(long, default_value = "127.0.0.1:8080")]
+  listen: SocketAddr,
+
+  /// Number of requests processed in parallel, defaults to the number of CPUs
+  #[arg(long)]
+  threads: Option<usize>,
+}
+
+fn main() -> ExitCode {
+  let cli = Cli::parse();
+
+  let pipeline = match ImagePipeline::new(&cli.hasher) {
+    Ok(pipeline) => pipeline,
+    Err(e) => {
+      eprintln!("phash: invalid hasher config: {e}");
+      return ExitCode::from(EXIT_ERROR);
+    }
+  };
+  let default = DEFAULT_LIMITS;
+  let pipeline = pipeline.with_limits(Limits {
+    max_dimension: cli.max_dimension.unwrap_or(default.max_dimension),
+    max_pixels: cli.max_pixels.unwrap_or(default.max_pixels),
+    max_alloc: cli.max_alloc.unwrap_or(default.max_alloc),
+  });
+
+  let result = match cli.command {
+    Command::Hash(args) => hash(&pipeline, args),
+    Command::Serve(args) => {
+      let threads = args
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+      server::serve(pipeline, args.listen, threads).map(|()| ExitCode::SUCCESS)
+    }
+  };
+
+  match result {
+    Ok(code) => code,
+    Err(e) => {
+      eprintln!("phash: {e}");
+      ExitCode::from(EXIT_ERROR)
+    }
+  }
+}
+
+fn hash(pipeline: &ImagePipeline, args: HashArgs) -> io::Result<ExitCode> {
+  let mut out = BufWriter::new(io::stdout().lock());
+  let mut failed = false;
+
+  for input in &args.inputs {
+    let file = if input == "-" {
+      let mut file = vec![];
+      io::stdin().read_to_end(&mut file).map(|_| file)
+    } else {
+      fs::read(input)
+    };
+
+    let result = file.map_err(|e| e.to_string()).and_then(|file| {
+      process(pipeline, file, args.mime.as_deref()).map_err(|e| {
+        match std::error::Error::source(&e) {
+          Some(source) => format!("{e}: {source}"),
+          None => e.to_string(),
+        }
+      })
+    });
+
+    match result {
+      Ok(response) => {
+        serde_json::to_writer(&mut out, &response)?;
+        writeln!(out)?;
+      }
+      Err(e) => {
+        eprintln!("phash: {input}: {e}");
+        failed = true;
+      }
+    }
+  }
+
+  out.flush()?;
+  Ok(if failed {
+    ExitCode::from(EXIT_ERROR)
+  } else {
+    ExitCode::SUCCESS
+  })
+}
+
+/// Process an upload like `wk-image`, without storing it.
+fn process(
+  pipeline: &ImagePipeline,
+  file: Vec<u8>,
+  mime: Option<&str>,
+) -> Result<Upload, image_core::Error> {
+  let options = Options {
+    mime: mime.map(str::to_owned),
+    ..Options::default()
+  };
+  pipeline.encode(file, &options).map(|(upload, _)| upload)
 }
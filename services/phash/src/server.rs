@@ -0,0 +1,166 @@
+//! A local HTTP endpoint for the pipeline.
+//!
+//! `POST` an image as the request body, optionally with an `image/*` `Content-Type`, to get
+//! the same JSON `wk-image` responds with. Errors are plain text with `wk-image`'s status codes.
+
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use image_core::ImagePipeline;
+
+use crate::process;
+
+/// Larger request bodies are rejected.
+const MAX_BODY: u64 = 100 << 20;
+
+/// Serve requests on `addr` with `threads` workers, until the process is killed.
+pub fn serve(pipeline: ImagePipeline, addr: SocketAddr, threads: usize) -> io::Result<()> {
+  let server = Server::http(addr).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+  eprintln!("phash: listening on http://{}", server.server_addr());
+  run(Arc::new(server), Arc::new(pipeline), threads);
+  Ok(())
+}
+
+fn run(server: Arc<Server>, pipeline: Arc<ImagePipeline>, threads: usize) {
+  let workers: Vec<_> = (0..threads.max(1))
+    .map(|_| {
+      let (server, pipeline) = (server.clone(), pipeline.clone());
+      thread::spawn(move || {
+        for request in server.incoming_requests() {
+          if let Err(err) = handle(request, &pipeline) {
+            eprintln!("phash: {err}");
+          }
+        }
+      })
+    })
+    .collect();
+
+  for worker in workers {
+    let _ = worker.join();
+  }
+}
+
+fn handle(mut request: Request, pipeline: &ImagePipeline) -> io::Result<()> {
+  if *request.method() != Method::Post {
+    return request.respond(text("Method not allowed", 405));
+  }
+  if request
+    .body_length()
+    .map_or(false, |len| len as u64 > MAX_BODY)
+  {
+    return request.respond(text("File too large", 413));
+  }
+
+  let mut file = vec![];
+  request
+    .as_reader()
+    .take(MAX_BODY + 1)
+    .read_to_end(&mut file)?;
+  if file.len() as u64 > MAX_BODY {
+    return request.respond(text("File too large", 413));
+  }
+  if file.is_empty() {
+    return request.respond(text("No file found", 400));
+  }
+
+  let mime = request
+    .headers()
+    .iter()
+    .find(|header| header.field.equiv("Content-Type"))
+    .map(|header| {
+      let value = header.value.as_str();
+      value.split(';').next().unwrap_or(value).trim().to_owned()
+    })
+    // clients send generic types like `application/octet-stream` for raw bodies
+    .filter(|mime| mime.starts_with("image/"));
+
+  match process(pipeline, file, mime.as_deref()) {
+    Ok(response) => {
+      let json = serde_json::to_vec(&response)?;
+      request.respond(
+        Response::from_data(json)
+          .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()),
+      )
+    }
+    Err(err) => request.respond(text(&err.to_string(), err.status())),
+  }
+}
+
+fn text(message: &str, status: u16) -> Response<io::Cursor<Vec<u8>>> {
+  Response::from_string(message).with_status_code(StatusCode(status))
+}
+
+#[cfg(test)]
+mod test {
+  use std::io::Write;
+  use std::net::TcpStream;
+
+  use super::*;
+  use image_core::DEFAULT_HASHER;
+
+  /// Send a request and return the status code and body of the response.
+  fn post(addr: SocketAddr, method: &str, headers: &str, body: &[u8]) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+      stream,
+      "{method} / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n{headers}\r\n",
+      body.len()
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_owned();
+    (status, body)
+  }
+
+  #[test]
+  fn responds_like_wk_image() {
+    let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+    let addr = server.server_addr().to_ip().unwrap();
+    let pipeline = Arc::new(ImagePipeline::new(DEFAULT_HASHER).unwrap());
+    thread::spawn({
+      let pipeline = pipeline.clone();
+      move || run(server, pipeline, 2)
+    });
+
+    let image = image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(64, 48, |x, y| {
+      [(x * 4 + y) as u8].into()
+    }));
+    let mut png = vec![];
+    image
+      .write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)
+      .unwrap();
+
+    let (status, body) = post(addr, "POST", "Content-Type: image/png\r\n", &png);
+    assert_eq!(status, 200, "{body}");
+    let expected = serde_json::to_string(&process(&pipeline, png.clone(), None).unwrap()).unwrap();
+    assert_eq!(body, expected);
+
+    assert_eq!(
+      post(addr, "POST", "Content-Type: image/x-unknown\r\n", &png),
+      (400, "Invalid MIME type".into())
+    );
+    assert_eq!(
+      post(
+        addr,
+        "POST",
+        "Content-Type: application/octet-stream\r\n",
+        &png
+      ),
+      (200, expected)
+    );
+    assert_eq!(
+      post(addr, "POST", "", b"not an image"),
+      (400, "Could not guess image format".into())
+    );
+    assert_eq!(post(addr, "POST", "", b""), (400, "No file found".into()));
+    assert_eq!(post(addr, "PUT", "", &png).0, 405);
+  }
+}
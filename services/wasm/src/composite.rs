@@ -0,0 +1,215 @@
+//! Compositing translation masks over the original images, replacing the canvas drawing in the
+//! userscript.
+
+use std::io::Cursor;
+
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageEncoder, RgbaImage};
+use image_core::resize;
+use wasm_bindgen::prelude::*;
+
+/// How [`composite_mask`] returns the result.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+  /// Raw RGBA pixels, e.g. for `new ImageData()`
+  Rgba,
+  Png,
+  /// Lossless WebP, smaller than PNG but slower to encode
+  WebP,
+}
+
+/// A composited image.
+#[wasm_bindgen(getter_with_clone)]
+pub struct Composited {
+  pub width: u32,
+  pub height: u32,
+  /// Pixels or an encoded file, see [`OutputFormat`]
+  pub data: Vec<u8>,
+}
+
+/// Composite a translation mask over the original image, given as RGBA pixels like
+/// `ImageData.data`.
+///
+/// The result is `scale` times the size of the original, e.g. `devicePixelRatio` times the size
+/// it is displayed at. The original and the mask are resized to that size with Lanczos3, like
+/// uploads are in `wk-image`, so masks of images that were scaled down on upload fit as well.
+#[wasm_bindgen(js_name = compositeMask)]
+pub fn composite_mask(
+  source: Vec<u8>,
+  width: u32,
+  height: u32,
+  mask: &[u8],
+  scale: Option<f32>,
+  format: OutputFormat,
+) -> Result<Composited, JsError> {
+  crate::utils::set_panic_hook();
+  let image = composite(source, width, height, mask, scale.unwrap_or(1.)).map_err(JsError::new)?;
+  let (width, height) = image.dimensions();
+  let data = encode(image, format).map_err(JsError::new)?;
+  Ok(Composited {
+    width,
+    height,
+    data,
+  })
+}
+
+pub(crate) fn composite(
+  source: Vec<u8>,
+  width: u32,
+  height: u32,
+  mask: &[u8],
+  scale: f32,
+) -> Result<RgbaImage, &'static str> {
+  let source = RgbaImage::from_raw(width, height, source)
+    .filter(|source| source.len() == width as usize * height as usize * 4)
+    .ok_or("pixel data doesn't match the image size")?;
+  if width == 0 || height == 0 {
+    return Err("empty image");
+  }
+  if !(scale.is_finite() && scale > 0.) {
+    return Err("scale must be positive");
+  }
+  let scaled = |len: u32| ((len as f32 * scale).round() as u32).max(1);
+  let (out_width, out_height) = (scaled(width), scaled(height));
+  if out_width as u64 * out_height as u64 > 1 << 28 {
+    return Err("scaled image too large");
+  }
+
+  let mask = image::load_from_memory(mask)
+    .map_err(|_| "Invalid mask image")?
+    .into_rgba8();
+
+  let fit = |image: RgbaImage| {
+    if image.dimensions() == (out_width, out_height) {
+      image
+    } else {
+      resize(DynamicImage::ImageRgba8(image), out_width, out_height).into_rgba8()
+    }
+  };
+  let mut image = fit(source);
+  let mask = fit(mask);
+
+  for (px, mask) in image.pixels_mut().zip(mask.pixels()) {
+    px.0 = over(mask.0, px.0);
+  }
+  Ok(image)
+}
+
+/// Alpha-composite `top` over `bottom`, both with straight (not premultiplied) alpha.
+fn over(top: [u8; 4], bottom: [u8; 4]) -> [u8; 4] {
+  let top_alpha = u32::from(top[3]);
+  let bottom_weight = u32::from(bottom[3]) * (255 - top_alpha);
+
+  // the alpha of the result, times 255
+  let alpha = top_alpha * 255 + bottom_weight;
+  if alpha == 0 {
+    return [0; 4];
+  }
+
+  let mut out = [0; 4];
+  for c in 0..3 {
+    let sum = u32::from(top[c]) * top_alpha * 255 + u32::from(bottom[c]) * bottom_weight;
+    out[c] = ((sum + alpha / 2) / alpha) as u8;
+  }
+  out[3] = ((alpha + 127) / 255) as u8;
+  out
+}
+
+fn encode(image: RgbaImage, format: OutputFormat) -> Result<Vec<u8>, &'static str> {
+  let (width, height) = image.dimensions();
+  let mut buf = vec![];
+  let result = match format {
+    OutputFormat::Rgba => return Ok(image.into_raw()),
+    OutputFormat::Png => PngEncoder::new(Cursor::new(&mut buf)).write_image(
+      &image,
+      width,
+      height,
+      image::ColorType::Rgba8,
+    ),
+    OutputFormat::WebP => WebPEncoder::new_lossless(Cursor::new(&mut buf)).write_image(
+      &image,
+      width,
+      height,
+      image::ColorType::Rgba8,
+    ),
+  };
+  result.map_err(|_| "Could not encode image")?;
+  Ok(buf)
+}
+
+#[cfg(test)]
+mod test {
+  use image::{ImageFormat, Rgba};
+
+  use super::*;
+
+  fn png(image: &RgbaImage) -> Vec<u8> {
+    encode(image.clone(), OutputFormat::Png).unwrap()
+  }
+
+  #[test]
+  fn blends() {
+    assert_eq!(
+      over([10, 20, 30, 255], [200, 200, 200, 255]),
+      [10, 20, 30, 255]
+    );
+    assert_eq!(
+      over([10, 20, 30, 0], [200, 100, 0, 255]),
+      [200, 100, 0, 255]
+    );
+    assert_eq!(
+      over([0, 0, 0, 128], [255, 255, 255, 255]),
+      [127, 127, 127, 255]
+    );
+    assert_eq!(over([255, 0, 0, 128], [0, 0, 255, 128]), [170, 0, 85, 192]);
+    assert_eq!(over([1, 2, 3, 0], [4, 5, 6, 0]), [0; 4]);
+  }
+
+  #[test]
+  fn composites_and_scales() {
+    let source = RgbaImage::from_pixel(40, 30, Rgba([200, 200, 200, 255]));
+    // a mask covering the left half, at half the size as for a downscaled upload
+    let mask = RgbaImage::from_fn(20, 15, |x, _| {
+      Rgba(if x < 10 { [0, 0, 0, 255] } else { [0; 4] })
+    });
+
+    let image = composite(source.clone().into_raw(), 40, 30, &png(&mask), 1.).unwrap();
+    assert_eq!(image.dimensions(), (40, 30));
+    assert_eq!(image[(5, 15)], Rgba([0, 0, 0, 255]));
+    assert_eq!(image[(35, 15)], Rgba([200, 200, 200, 255]));
+
+    let hidpi = composite(source.into_raw(), 40, 30, &png(&mask), 2.).unwrap();
+    assert_eq!(hidpi.dimensions(), (80, 60));
+    assert_eq!(hidpi[(10, 30)], Rgba([0, 0, 0, 255]));
+    assert_eq!(hidpi[(70, 30)], Rgba([200, 200, 200, 255]));
+  }
+
+  #[test]
+  fn encodes_losslessly() {
+    let image = RgbaImage::from_fn(33, 17, |x, y| Rgba([x as u8 * 7, y as u8 * 13, 90, 255]));
+    for (format, image_format) in [
+      (OutputFormat::Png, ImageFormat::Png),
+      (OutputFormat::WebP, ImageFormat::WebP),
+    ] {
+      let encoded = encode(image.clone(), format).unwrap();
+      let decoded = image::load_from_memory_with_format(&encoded, image_format).unwrap();
+      assert_eq!(decoded.into_rgba8(), image, "{format:?}");
+    }
+    assert_eq!(
+      encode(image.clone(), OutputFormat::Rgba).unwrap(),
+      image.into_raw()
+    );
+  }
+
+  #[test]
+  fn errors() {
+    let mask = png(&RgbaImage::new(2, 2));
+    assert!(composite(vec![0; 15], 2, 2, &mask, 1.).is_err());
+    assert!(composite(vec![0; 16], 2, 2, &mask, 0.).is_err());
+    assert!(composite(vec![0; 16], 2, 2, &mask, f32::NAN).is_err());
+    assert!(composite(vec![0; 16], 2, 2, b"not a png", 1.).is_err());
+    assert!(composite(vec![], 0, 0, &mask, 1.).is_err());
+  }
+}
@@ -1,29 +1,226 @@
+mod composite;
+mod pipeline;
 mod utils;
 
-use image::{ImageBuffer, RgbaImage};
-use img_hash::{FilterType, HasherConfig};
+use image::RgbaImage;
+use image_core::{encode_hash, ImagePipeline, Limits, ParseConfigError, DEFAULT_LIMITS};
+use image_hasher::HasherConfig;
 use wasm_bindgen::prelude::*;
 
+pub use crate::composite::{composite_mask, Composited, OutputFormat};
+pub use crate::pipeline::Prepared;
+
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
-#[cfg(feature = "wee_alloc")]
+#[cfg(all(feature = "wee_alloc", target_arch = "wasm32"))]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+/// The config `wk-image` uses if its `HASHER` variable isn't set.
+pub const SERVER_HASHER: &str = image_core::DEFAULT_HASHER;
+
+/// Hash RGBA pixels with the gradient algorithm to hex, `hash_size` defaults to 16.
+///
+/// Kept for existing callers, the hashes are the ones of `gradient:{hash_size}x{hash_size}:lanczos3`
+/// and don't match the ones `wk-image` stores, use `Hasher` for those.
+#[wasm_bindgen]
+pub fn phash(
+  rgba: Vec<u8>,
+  width: u32,
+  height: u32,
+  hash_size: Option<u32>,
+) -> Result<String, JsError> {
+  utils::set_panic_hook();
+  let size = hash_size.unwrap_or(16);
+  let config: HasherConfig = format!("gradient:{size}x{size}:lanczos3")
+    .parse()
+    .map_err(|err: ParseConfigError| JsError::new(&err.to_string()))?;
+  let image = RgbaImage::from_raw(width, height, rgba)
+    .filter(|image| image.len() == width as usize * height as usize * 4)
+    .ok_or_else(|| JsError::new("pixel data doesn't match the image size"))?;
+  Ok(encode_hash(&config.to_hasher().hash_image(&image)))
+}
+
+/// Computes the same hashes as `wk-image` does for uploads.
+///
+/// Build it from the `hasher` field of an upload response to match a server with a custom
+/// config, or use `Hasher.serverDefault()`.
+#[wasm_bindgen]
+pub struct Hasher {
+  pipeline: ImagePipeline,
+}
+
+impl Hasher {
+  /// Create a hasher from a config string, see `HasherConfig`'s `FromStr` impl.
+  pub fn parse(spec: &str) -> Result<Self, ParseConfigError> {
+    Ok(Self {
+      pipeline: ImagePipeline::new(spec)?,
+    })
+  }
+
+  /// Hash RGBA pixels to hex, or `None` if there are too few or too many of them.
+  pub fn hash(&self, rgba: Vec<u8>, width: u32, height: u32) -> Option<String> {
+    let image = RgbaImage::from_raw(width, height, rgba)
+      .filter(|image| image.len() == width as usize * height as usize * 4)?;
+    Some(encode_hash(&self.pipeline.hasher().hash_image(&image)))
+  }
+}
+
 #[wasm_bindgen]
-pub fn phash(rgba: Vec<u8>, width: u32, height: u32, hash_size: Option<u32>) -> String {
-    return phash_inner(rgba, width, height, hash_size.unwrap_or(16));
+impl Hasher {
+  /// Create a hasher from a config string like `dct-gradient:10x10`.
+  #[wasm_bindgen(constructor)]
+  pub fn new(spec: &str) -> Result<Hasher, JsError> {
+    utils::set_panic_hook();
+    Self::parse(spec).map_err(|err| JsError::new(&err.to_string()))
+  }
+
+  /// The hasher `wk-image` uses by default.
+  #[wasm_bindgen(js_name = serverDefault)]
+  pub fn server_default() -> Hasher {
+    utils::set_panic_hook();
+    Self::parse(SERVER_HASHER).unwrap()
+  }
+
+  /// Decode files in `prepare` within other limits than `wk-image`'s default ones, e.g. the
+  /// `MAX_IMAGE_*` variables of a server configured differently. Unset limits keep their default.
+  #[wasm_bindgen(js_name = withLimits)]
+  pub fn with_limits(
+    self,
+    max_dimension: Option<u32>,
+    max_pixels: Option<u32>,
+    max_alloc: Option<u32>,
+  ) -> Hasher {
+    let default = DEFAULT_LIMITS;
+    Hasher {
+      pipeline: self.pipeline.with_limits(Limits {
+        max_dimension: max_dimension.unwrap_or(default.max_dimension),
+        max_pixels: max_pixels.map_or(default.max_pixels, u64::from),
+        max_alloc: max_alloc.map_or(default.max_alloc, u64::from),
+      }),
+    }
+  }
+
+  /// The canonical config string, as in upload responses.
+  #[wasm_bindgen(getter)]
+  pub fn spec(&self) -> String {
+    self.pipeline.spec().to_owned()
+  }
+
+  /// Hash RGBA pixels, e.g. `ImageData.data`, to hex.
+  #[wasm_bindgen(js_name = hashRgba)]
+  pub fn hash_rgba(&self, rgba: Vec<u8>, width: u32, height: u32) -> Result<String, JsError> {
+    self
+      .hash(rgba, width, height)
+      .ok_or_else(|| JsError::new("pixel data doesn't match the image size"))
+  }
+
+  /// Decode an image file and compute what `wk-image` would store for it, so an existing upload
+  /// can be looked up by its sha before sending the file. `mime` and `codec` are optional, as
+  /// for uploads; without `codec` the key is the one of a server storing PNG.
+  ///
+  /// Only BMP, GIF, JPEG, PNG and WebP files are supported, upload other files as they are.
+  pub fn prepare(
+    &self,
+    file: Vec<u8>,
+    mime: Option<String>,
+    codec: Option<String>,
+  ) -> Result<Prepared, JsError> {
+    pipeline::prepare(&self.pipeline, file, mime.as_deref(), codec.as_deref())
+      .map_err(|err| JsError::new(&err))
+  }
 }
 
-fn phash_inner(rgba: Vec<u8>, width: u32, height: u32, hash_size: u32) -> String {
-    let image: RgbaImage = ImageBuffer::from_raw(width, height, rgba).unwrap();
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const VECTORS: &str = include_str!("../../../image-core/testdata/vectors.txt");
+  const VECTOR_PNG: &[u8] = include_bytes!("../../../image-core/testdata/vector.png");
+
+  /// The vectors in `image-core/testdata` are computed by `image-core` itself, natively.
+  fn check_vectors() {
+    let mut count = 0;
+    for line in VECTORS.lines().filter(|line| !line.starts_with('#')) {
+      let [file, spec, expected] = line.split(' ').collect::<Vec<_>>()[..] else {
+        panic!("invalid line: {line}");
+      };
+      let file = match file {
+        "vector.png" => VECTOR_PNG,
+        _ => panic!("{file} isn't included"),
+      };
+      // pixels as the browser gives them to us
+      let image = image::load_from_memory(file).unwrap().into_rgba8();
+      let (width, height) = image.dimensions();
+
+      let hasher = Hasher::parse(spec).unwrap();
+      assert_eq!(
+        hasher.hash(image.into_raw(), width, height).unwrap(),
+        expected,
+        "{line}"
+      );
+      count += 1;
+    }
+    assert!(count >= 2);
+  }
+
+  #[test]
+  fn matches_wk_image() {
+    check_vectors();
+    assert_eq!(
+      Hasher::parse(SERVER_HASHER).unwrap().pipeline.spec(),
+      "dct-gradient:10x10"
+    );
+  }
+
+  /// Browsers hash on wasm32 like `wk-image`, run with `wasm-pack test --node`.
+  #[cfg(target_arch = "wasm32")]
+  #[wasm_bindgen_test::wasm_bindgen_test]
+  fn matches_wk_image_on_wasm() {
+    check_vectors();
+  }
+
+  #[test]
+  fn phash_is_gradient() {
+    let image = image::open(concat!(
+      env!("CARGO_MANIFEST_DIR"),
+      "/../../image-core/testdata/vector.png"
+    ))
+    .unwrap()
+    .into_rgba8();
+    let (width, height) = image.dimensions();
+
+    let hash = phash(image.to_vec(), width, height, Some(10)).ok().unwrap();
+    let hasher = Hasher::parse("gradient:10x10:lanczos3").unwrap();
+    assert_eq!(Some(hash), hasher.hash(image.to_vec(), width, height));
+
+    // 16x16 bits by default, more than `Hasher` holds
+    assert_eq!(
+      phash(image.into_raw(), width, height, None)
+        .ok()
+        .unwrap()
+        .len(),
+      64
+    );
+  }
 
-    let hasher = HasherConfig::new()
-        .hash_size(hash_size, hash_size)
-        .resize_filter(FilterType::Lanczos3)
-        .to_hasher();
+  #[test]
+  fn checks_the_size() {
+    assert_eq!(
+      Hasher::parse("gradient:16x16").err().unwrap().token(),
+      "16x16"
+    );
 
-    let hash = hasher.hash_image(&image);
+    let hasher = Hasher::parse(SERVER_HASHER).unwrap();
+    assert_eq!(*hasher.pipeline.limits(), DEFAULT_LIMITS);
+    assert!(hasher.hash(vec![0; 4 * 6], 2, 3).is_some());
+    assert!(hasher.hash(vec![0; 4 * 6 - 1], 2, 3).is_none());
+    assert!(hasher.hash(vec![0; 4 * 6 + 4], 2, 3).is_none());
 
-    return hex::encode(hash.as_bytes());
+    let limits = *hasher.with_limits(None, Some(1000), None).pipeline.limits();
+    assert_eq!(
+      (limits.max_pixels, limits.max_alloc),
+      (1000, DEFAULT_LIMITS.max_alloc)
+    );
+  }
 }
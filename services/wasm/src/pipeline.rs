@@ -0,0 +1,110 @@
+//! The part of `wk-image`'s upload processing that decides what is stored: decode, orient,
+//! normalize, hash. Lets clients look up an upload by its sha before sending it.
+
+use image_core::{encode_hash, Codec, Frames, ImagePipeline, Options};
+use wasm_bindgen::prelude::*;
+
+/// What `wk-image` would store and report for an upload.
+#[wasm_bindgen(getter_with_clone)]
+pub struct Prepared {
+  /// Where the image would be stored with the requested codec
+  pub key: String,
+  pub width: u32,
+  pub height: u32,
+  /// sha256 of the stored pixels
+  pub sha: String,
+  pub hash: String,
+  /// EXIF orientation that was applied, if any
+  pub orientation: Option<u16>,
+}
+
+/// Process an uploaded file like `wk-image`. The format is guessed from the contents if `mime`
+/// is empty, and `codec` is the upload's `codec` field. Errors are `wk-image`'s messages.
+pub fn prepare(
+  pipeline: &ImagePipeline,
+  file: Vec<u8>,
+  mime: Option<&str>,
+  codec: Option<&str>,
+) -> Result<Prepared, String> {
+  let codec = match codec.filter(|codec| !codec.trim().is_empty()) {
+    // only the key depends on the codec, so ones this build can't encode are fine
+    Some(codec) => Some(codec.parse::<Codec>().map_err(|_| "Invalid codec")?),
+    None => None,
+  };
+  let options = Options {
+    mime: mime.map(str::to_owned),
+    // only the first frame is stored
+    frames: Frames::First,
+    codec,
+    compare: vec![],
+  };
+  let processed = pipeline
+    .process(file, &options)
+    .map_err(|err| err.to_string())?;
+
+  Ok(Prepared {
+    key: processed.key(),
+    width: processed.image.width(),
+    height: processed.image.height(),
+    hash: encode_hash(&processed.hash),
+    sha: processed.sha,
+    orientation: processed.orientation.map(u16::from),
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::Hasher;
+
+  /// The results in `image-core/testdata` are computed by `image-core` itself.
+  #[test]
+  fn matches_wk_image() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../image-core/testdata");
+    let uploads = std::fs::read_to_string(format!("{dir}/uploads.txt")).unwrap();
+    let hasher = Hasher::server_default();
+
+    for line in uploads.lines().filter(|line| !line.starts_with('#')) {
+      let [file, width, height, sha, hash] = line.split(' ').collect::<Vec<_>>()[..] else {
+        panic!("invalid line: {line}");
+      };
+      let file = std::fs::read(format!("{dir}/{file}")).unwrap();
+      let prepared = prepare(&hasher.pipeline, file.clone(), None, None).unwrap();
+
+      assert_eq!(
+        (
+          prepared.width.to_string(),
+          prepared.height.to_string(),
+          &*prepared.sha,
+          &*prepared.hash
+        ),
+        (width.into(), height.into(), sha, hash),
+        "{line}"
+      );
+      assert_eq!(prepared.key, format!("upload/{sha}.png"));
+
+      // the key follows the codec, even one this build can't encode
+      let prepared = prepare(&hasher.pipeline, file, None, Some("avif:80")).unwrap();
+      assert_eq!(prepared.key, format!("upload/{sha}.avif"));
+    }
+  }
+
+  #[test]
+  fn errors() {
+    let hasher = Hasher::server_default();
+    let err = |file: &[u8], mime| prepare(&hasher.pipeline, file.to_vec(), mime, None).err();
+    assert_eq!(
+      err(b"GIF89a", Some("text/plain")),
+      Some("Invalid MIME type".into())
+    );
+    assert_eq!(err(b"GIF89a", None), Some("Truncated image".into()));
+    assert_eq!(
+      err(b"not an image", None),
+      Some("Could not guess image format".into())
+    );
+    assert_eq!(
+      prepare(&hasher.pipeline, b"GIF89a".to_vec(), None, Some("gif")).err(),
+      Some("Invalid codec".into())
+    );
+  }
+}
@@ -156,10 +156,10 @@ export interface UploadV1ResultError {
    * `queue-full`: The queue is full.<br>
    * `fetch-failed`: The url could not be fetched. (only for `url` uploads)<br>
    * `file-too-large`: The file is too large. Currently the limit is 20MiB.<br>
-   * `resize-crash`: The resize process crashed, usually due to insufficient memory.
-   * This can happen if the image has very large dimensions, or if the image is corrupted,
-   * or if the image uses a color type outside of `RGB`, `RGBA`, `L`, `LA`.
-   * The general guideline is to keep the image dimensions below 4096x4096.<br>
+   * `resize-crash`: Processing the image failed on the server, see `cause`. (500)<br>
+   *
+   * When the image itself is rejected, `error` is the {@link ImageErrorCode} of `wk-image` with
+   * its 4xx status, e.g. `invalid-image`, `unsupported-format` or `image-too-large`.
    */
   error?: string & {} | null
   | 'group-limit'
@@ -167,9 +167,141 @@ export interface UploadV1ResultError {
   | 'fetch-failed'
   | 'file-too-large'
   | 'resize-crash'
+  | ImageErrorCode
+  /**
+   * For `resize-crash`, the {@link ImageErrorCode} of the failure: `encode-failed`,
+   * `store-failed`, or `internal` if processing crashed without a response.
+   */
+  cause?: ImageErrorCode
 }
 
 /**
  * The result of the `/task/upload/v1` endpoint.
  */
 export type UploadV1Result = UploadV1ResultSuccess | UploadV1ResultError
+
+/**
+ * The response of `wk-image` to a `POST` of a `multipart/form-data` body with a `file` and
+ * optional `mime`, `codec` and `compare` fields, when the upload was stored.
+ *
+ * `codec` overrides the format the image is stored in, see {@link ImageUploadSuccess.codec}.
+ * `compare` is a comma separated list of codecs to also encode the image in, to report their
+ * sizes in `sizes`.
+ */
+export interface ImageUploadSuccess {
+  /**
+   * Where the image is stored in the private bucket.
+   *
+   * @example 'upload/3b5d…e1.png'
+   */
+  key: string
+  width: number
+  height: number
+  /**
+   * Size of the stored image in bytes.
+   */
+  size: number
+  /**
+   * Format the image is stored in, which the extension of `key` follows: `png:fast`,
+   * `png:default` or `png:best`, lossless `webp`, or lossy `avif:<quality>`.
+   *
+   * @example 'png:fast'
+   */
+  codec: string
+  /**
+   * Size in bytes of the image encoded with `codec` and each of the codecs in the `compare`
+   * field. Absent without a `compare` field.
+   */
+  sizes?: Record<string, number>
+  /**
+   * Perceptual hash of the image, in hex.
+   */
+  hash: string
+  /**
+   * The hasher config the hashes were computed with.
+   */
+  hasher: string
+  /**
+   * Part of the image that was hashed, if the hasher trims borders.
+   */
+  crop?: { x: number, y: number, width: number, height: number }
+  /**
+   * The EXIF orientation the upload was rotated or flipped by before anything else, as the
+   * value of the EXIF `Orientation` tag from 2 to 8. Absent if the upload had no orientation
+   * or a normal one.
+   */
+  orientation?: number
+  /**
+   * sha256 of the stored pixels, in hex.
+   */
+  sha: string
+  /**
+   * Number of frames in the uploaded file, only the first one is stored.
+   */
+  frames: number
+  sequence_hash?: string
+  keyframe_hashes?: string[]
+}
+
+/**
+ * An identifier for why `wk-image` rejected an upload. These are stable; more may be added in
+ * the future.
+ *
+ * `invalid-form`: The body is not valid form data. (400)<br>
+ * `no-file`: The form has no `file`. (400)<br>
+ * `file-too-large`: The body is over the size limit of the server. (413)<br>
+ * `invalid-mime`: The `mime` field is not an image type. (400)<br>
+ * `invalid-codec`: The `codec` or `compare` field has a codec the server doesn't support. (400)<br>
+ * `unknown-format`: The format could not be guessed from the file. (400)<br>
+ * `unsupported-format`: The format or its variant can't be decoded. (400)<br>
+ * `truncated-image`: The file ends early. Some truncated files are reported as
+ * `invalid-image`. (400)<br>
+ * `invalid-image`: The file could not be decoded. (400)<br>
+ * `too-many-frames`: The animation has too many frames. (400)<br>
+ * `image-too-large`: The image is over the size limits. (413)<br>
+ * `encode-failed`: The image could not be encoded for storage. (500)<br>
+ * `store-failed`: The image could not be stored. (500)<br>
+ * `internal`: Processing crashed. (500)
+ */
+export type ImageErrorCode = string & {}
+  | 'invalid-form'
+  | 'no-file'
+  | 'file-too-large'
+  | 'invalid-mime'
+  | 'invalid-codec'
+  | 'unknown-format'
+  | 'unsupported-format'
+  | 'truncated-image'
+  | 'invalid-image'
+  | 'too-many-frames'
+  | 'image-too-large'
+  | 'encode-failed'
+  | 'store-failed'
+  | 'internal'
+
+/**
+ * The JSON body of an error response of `wk-image`, with the status listed for its code.
+ *
+ * On Workers, panics abort the request instead of responding with `internal`, so a failed
+ * request without such a body should be handled as `internal`.
+ */
+export interface ImageUploadError {
+  code: ImageErrorCode
+  /**
+   * A human readable message, which may change.
+   *
+   * @example 'Image too large'
+   */
+  message: string
+  /**
+   * What was known about the upload when it was rejected.
+   */
+  details: {
+    /**
+     * MIME type of the format the file was decoded as.
+     */
+    format?: string
+    width?: number
+    height?: number
+  }
+}
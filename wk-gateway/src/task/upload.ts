@@ -1,9 +1,10 @@
 import { z } from 'zod'
 import type { Handler, HonoRequest } from 'hono'
 import { ofetch } from 'ofetch'
+import type { FetchError } from 'ofetch'
 import { createId } from '@paralleldrive/cuid2'
 import { HTTPException } from 'hono/http-exception'
-import type { UploadV1Result } from '@cotrans/types'
+import type { ImageUploadError, ImageUploadSuccess, UploadV1Result } from '@cotrans/types'
 import type { Bindings } from '../types'
 import { dbEnum } from '../db'
 import type { MitSubmitParam } from '../mitWorker/dObject'
@@ -129,23 +130,35 @@ export const upload: Handler<{ Bindings: Bindings }> = async ({ env, req, json }
   if (param.mime)
     uploadForm.append('mime', param.mime)
 
-  const sourceInfo = await ofetch<{
-    key: string
-    width: number
-    height: number
-    size: number
-    hash: string
-    sha: string
-  }>('https://fake-host/', {
+  const sourceInfo = await ofetch<ImageUploadSuccess>('https://fake-host/', {
     fetcher: env.doImage.get(env.doImage.newUniqueId()),
     method: 'POST',
     body: uploadForm,
-  }).catch((_err) => {
+  }).catch((err: FetchError<ImageUploadError | string>) => {
+    const status = err.statusCode ?? 500
+    const code = typeof err.data === 'object' && typeof err.data?.code === 'string'
+      ? err.data.code
+      : undefined
+    // the file can't be processed, which is up to the client
+    if (code && status >= 400 && status < 500) {
+      throw new HTTPException(status, {
+        res: json({
+          id: null,
+          error: code,
+        } satisfies UploadV1Result, status),
+      })
+    }
+
+    // panics abort wk-image on Workers, so the fetch fails or has no JSON body then
+    const cause = code ?? 'internal'
+    // eslint-disable-next-line no-console
+    console.error('wk-image', status, cause, typeof err.data === 'object' ? err.data?.details : err.message)
     throw new HTTPException(500, {
       res: json({
         id: null,
         error: 'resize-crash',
-      } satisfies UploadV1Result),
+        cause,
+      } satisfies UploadV1Result, 500),
     })
   })
 
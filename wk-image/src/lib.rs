@@ -1,32 +1,92 @@
-use std::{io::Cursor, num::NonZeroU32};
-
+use async_trait::async_trait;
 use console_error_panic_hook::set_once as set_panic_hook;
-use fast_image_resize as fr;
-use image::{
-  codecs::png::{CompressionType, PngEncoder},
-  io::Reader as ImageReader,
-  DynamicImage, ImageEncoder, ImageFormat,
+use image_core::{
+  BlobStore, Codec, Error as UploadError, ImagePipeline, Limits, Options, StoreError,
+  DEFAULT_HASHER, DEFAULT_LIMITS,
 };
-use image_hasher::{Hasher, HasherConfig};
-use js_sys::{ArrayBuffer, Uint8Array};
-use once_cell::sync::Lazy;
-use serde::Serialize;
+use js_sys::{Object, Reflect, Uint8Array};
+use once_cell::sync::OnceCell;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use worker::*;
 use worker_sys::R2Bucket;
 
-static HASHER: Lazy<Hasher> = Lazy::new(|| {
-  HasherConfig::new()
-    .hash_size(10, 10)
-    .resize_filter(image_hasher::FilterType::Lanczos3)
-    .preproc_dct()
-    .to_hasher()
-});
-
-pub fn hash_image(image: &DynamicImage) -> String {
-  let hash = HASHER.hash_image(image);
-  hex::encode(hash.as_bytes())
+static PIPELINE: OnceCell<ImagePipeline> = OnceCell::new();
+
+/// Get the pipeline hashing with the config in the `HASHER` variable, limited by the
+/// `MAX_IMAGE_*` variables and storing images with the `CODEC` variable.
+fn pipeline(env: &Env) -> Result<&'static ImagePipeline> {
+  PIPELINE.get_or_try_init(|| {
+    let spec = env
+      .var("HASHER")
+      .map_or_else(|_| DEFAULT_HASHER.to_owned(), |var| var.to_string());
+    let pipeline = ImagePipeline::new(&spec)
+      .map_err(|err| Error::RustError(format!("invalid HASHER: {err}")))?;
+
+    let codec = match env.var("CODEC") {
+      Ok(var) => {
+        let var = var.to_string();
+        var
+          .parse::<Codec>()
+          .map_err(|err| Error::RustError(format!("invalid CODEC: {err}")))?
+      }
+      Err(_) => Codec::default(),
+    };
+
+    let limits = limits(|name| env.var(name).ok().map(|var| var.to_string()))?;
+    Ok(pipeline.with_limits(limits).with_codec(codec))
+  })
+}
+
+/// The limits in the `MAX_IMAGE_*` variables, read with `var`, or [`DEFAULT_LIMITS`]'s.
+fn limits(var: impl Fn(&str) -> Option<String>) -> Result<Limits> {
+  let default = DEFAULT_LIMITS;
+  Ok(Limits {
+    max_dimension: limit(&var, "MAX_IMAGE_DIMENSION")?.unwrap_or(default.max_dimension),
+    max_pixels: limit(&var, "MAX_IMAGE_PIXELS")?.unwrap_or(default.max_pixels),
+    max_alloc: limit(&var, "MAX_IMAGE_ALLOC")?.unwrap_or(default.max_alloc),
+  })
+}
+
+/// Parse an optional numeric variable.
+fn limit<T: std::str::FromStr>(
+  var: &impl Fn(&str) -> Option<String>,
+  name: &str,
+) -> Result<Option<T>> {
+  let Some(var) = var(name) else {
+    return Ok(None);
+  };
+  var
+    .parse()
+    .map(Some)
+    .map_err(|_| Error::RustError(format!("invalid {name}: {var}")))
+}
+
+/// Stores uploads in an R2 bucket.
+struct R2Store(R2Bucket);
+
+#[async_trait(?Send)]
+impl BlobStore for R2Store {
+  async fn put(
+    &self,
+    key: &str,
+    data: Vec<u8>,
+    content_type: &str,
+  ) -> std::result::Result<(), StoreError> {
+    let http_metadata = Object::new();
+    Reflect::set(&http_metadata, &"contentType".into(), &content_type.into()).unwrap();
+    let options = Object::new();
+    Reflect::set(&options, &"httpMetadata".into(), &http_metadata).unwrap();
+    let put = self.0.put(
+      key.to_owned(),
+      unsafe { Uint8Array::view(&data).into() },
+      options.into(),
+    );
+    JsFuture::from(put)
+      .await
+      .map_err(|err| format!("R2 put failed: {err:?}"))?;
+    Ok(())
+  }
 }
 
 #[durable_object]
@@ -45,209 +105,81 @@ impl DurableObject for DOImage {
   }
 }
 
-#[derive(Serialize)]
-struct ResponseJson {
-  key: String,
-  width: u32,
-  height: u32,
-  size: usize,
-  hash: String,
-  sha: String,
-}
-
 async fn handle(mut req: Request, env: &Env) -> Result<Response> {
+  // panics abort on Workers, so they are only logged and the request fails without a
+  // response
   set_panic_hook();
-  let worker: web_sys::WorkerGlobalScope = js_sys::global().unchecked_into();
+  let pipeline = pipeline(env)?;
   let bucket_pri: R2Bucket = js_sys::Reflect::get(env, &JsValue::from("BUCKET_PRI"))
     .unwrap()
     .unchecked_into();
 
-  let form = req.form_data().await?;
-
-  let Some(FormEntry::File(file)) = form.get("file") else {
-    return Response::error("No file found", 400);
-  };
-  let file = file.bytes().await?;
-
-  let mime = match form.get("mime") {
-    Some(FormEntry::Field(mime)) => Some(mime),
-    _ => None,
+  let result = match read_form(&mut req).await {
+    Ok((file, options)) => pipeline.upload(&R2Store(bucket_pri), file, &options).await,
+    Err(err) => Err(err),
   };
-
-  drop(form);
-
-  let cursor = Cursor::new(file);
-  let mut image = match mime {
-    Some(mime) if !mime.is_empty() => {
-      let Some(format) = ImageFormat::from_mime_type(mime) else {
-        return Response::error("Invalid MIME type", 400);
-      };
-      let Ok(image) = ImageReader::with_format(cursor, format).decode() else {
-        return Response::error("Invalid image", 400);
-      };
-      image
-    }
-    _ => {
-      let Ok(reader) = ImageReader::new(cursor).with_guessed_format() else {
-        return Response::error("Could not guess image format", 400);
-      };
-      let Ok(image) = reader.decode() else {
-        return Response::error("Invalid image", 400);
-      };
-      image
+  match result {
+    Ok(upload) => Response::from_json(&upload),
+    Err(err) => {
+      if err.status() >= 500 {
+        console_error!("{err}: {err:?}");
+      }
+      Ok(Response::from_json(&err)?.with_status(err.status()))
     }
-  };
+  }
+}
 
-  let mut width = image.width();
-  let mut height = image.height();
+/// Read the `file`, `mime`, `codec` and `compare` fields of an upload.
+async fn read_form(req: &mut Request) -> std::result::Result<(Vec<u8>, Options), UploadError> {
+  let form = req
+    .form_data()
+    .await
+    .map_err(|_| UploadError::InvalidForm)?;
 
-  image = match image {
-    DynamicImage::ImageRgb8(_) => image,
-    DynamicImage::ImageRgba8(_) => image,
-    DynamicImage::ImageLuma8(_) => image,
-    DynamicImage::ImageLumaA8(_) => image,
-    _ => DynamicImage::ImageRgba8(image.to_rgba8()),
+  let Some(FormEntry::File(file)) = form.get("file") else {
+    return Err(UploadError::NoFile);
   };
+  let file = file.bytes().await.map_err(|_| UploadError::InvalidForm)?;
 
-  // scale image to less than 6000x6000
-  if width > 6000 || height > 6000 {
-    let widthf: f64 = width as f64;
-    let heightf: f64 = height as f64;
-
-    let (nwidth, nheight) = if widthf > heightf {
-      (6000, (6000. / widthf * heightf).round() as u32)
-    } else {
-      ((6000. / heightf * widthf).round() as u32, 6000)
-    };
-
-    image = resize(image, nwidth, nheight);
-
-    width = nwidth;
-    height = nheight;
-  }
-
-  // sha using SubtleCrypto
-  let sha: ArrayBuffer = JsFuture::from(
-    worker
-      .crypto()?
-      .subtle()
-      .digest_with_str_and_buffer_source(
-        "SHA-256",
-        &unsafe { Uint8Array::view(image.as_bytes()) }.into(),
-      )?,
-  )
-  .await?
-  .unchecked_into();
-  let sha = hex::encode(Uint8Array::new(&sha).to_vec());
-
-  let mut png_buf: Vec<u8> = vec![];
-  if let Err(_) = PngEncoder::new_with_quality(
-    &mut Cursor::new(&mut png_buf),
-    CompressionType::Fast,
-    image::codecs::png::FilterType::default(),
-  )
-  .write_image(image.as_bytes(), width, height, image.color())
-  {
-    return Response::error("Could not encode image", 500);
+  let field = |name| match form.get(name) {
+    Some(FormEntry::Field(value)) => Some(value),
+    _ => None,
   };
 
-  let size = png_buf.len();
-
-  let key = "upload/".to_owned() + &sha + ".png";
-  let put_res = JsFuture::from(bucket_pri.put(
-    key.clone(),
-    unsafe { Uint8Array::view(&png_buf).into() },
-    JsValue::UNDEFINED,
-  ));
-
-  // hash the image while we wait for the upload to finish
-  let hash = hash_image(&image);
-  let _ = put_res.await?;
-
-  Response::from_json(&ResponseJson {
-    key,
-    width,
-    height,
-    size,
-    hash,
-    sha,
-  })
+  let options = Options::from_form(
+    field("mime"),
+    field("codec").as_deref(),
+    field("compare").as_deref(),
+  )?;
+  Ok((file, options))
 }
 
-fn resize(image: DynamicImage, width: u32, height: u32) -> DynamicImage {
-  let pixel_type = match image {
-    DynamicImage::ImageRgb8(_) => fr::PixelType::U8x3,
-    DynamicImage::ImageRgba8(_) => fr::PixelType::U8x4,
-    DynamicImage::ImageLuma8(_) => fr::PixelType::U8,
-    DynamicImage::ImageLumaA8(_) => fr::PixelType::U8x2,
-    _ => unreachable!(),
-  };
-
-  let mut src_img = fr::Image::from_vec_u8(
-    NonZeroU32::new(image.width()).unwrap(),
-    NonZeroU32::new(image.height()).unwrap(),
-    image.into_bytes(),
-    pixel_type,
-  )
-  .unwrap();
-
-  // multiple RGB channels of source image by alpha channel
-  // (not required for the Nearest algorithm)
-  let alpha_mul_div = fr::MulDiv::default();
-  if pixel_type == fr::PixelType::U8x4 || pixel_type == fr::PixelType::U8x2 {
-    alpha_mul_div
-      .multiply_alpha_inplace(&mut src_img.view_mut())
-      .unwrap();
-  }
-
-  let mut dst_img = fr::Image::new(
-    NonZeroU32::new(width).unwrap(),
-    NonZeroU32::new(height).unwrap(),
-    src_img.pixel_type(),
-  );
+#[cfg(test)]
+mod test {
+  use image_core::Options;
 
-  let mut dst_view = dst_img.view_mut();
+  use super::*;
 
-  let mut resizer = fr::Resizer::new(fr::ResizeAlg::Convolution(fr::FilterType::Lanczos3));
-  resizer.resize(&src_img.view(), &mut dst_view).unwrap();
+  #[test]
+  fn limits_uploads() {
+    let pipeline = ImagePipeline::new(DEFAULT_HASHER)
+      .unwrap()
+      .with_limits(limits(|_| None).unwrap());
+    assert_eq!(*pipeline.limits(), DEFAULT_LIMITS);
 
-  if pixel_type == fr::PixelType::U8x4 || pixel_type == fr::PixelType::U8x2 {
-    alpha_mul_div.divide_alpha_inplace(&mut dst_view).unwrap();
-  }
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../image-core/testdata");
+    for file in ["bomb.png", "bomb.gif", "bomb.webp", "bomb.jpg"] {
+      let file_data = std::fs::read(format!("{dir}/{file}")).unwrap();
+      let err = pipeline.process(file_data, &Options::default()).err();
+      assert_eq!(err.map(|err| err.code()), Some("image-too-large"), "{file}");
+    }
 
-  match pixel_type {
-    fr::PixelType::U8x3 => DynamicImage::ImageRgb8(
-      image::RgbImage::from_vec(
-        dst_img.width().get(),
-        dst_img.height().get(),
-        dst_img.into_vec(),
-      )
-      .unwrap(),
-    ),
-    fr::PixelType::U8x4 => DynamicImage::ImageRgba8(
-      image::RgbaImage::from_vec(
-        dst_img.width().get(),
-        dst_img.height().get(),
-        dst_img.into_vec(),
-      )
-      .unwrap(),
-    ),
-    fr::PixelType::U8 => DynamicImage::ImageLuma8(
-      image::GrayImage::from_vec(
-        dst_img.width().get(),
-        dst_img.height().get(),
-        dst_img.into_vec(),
-      )
-      .unwrap(),
-    ),
-    fr::PixelType::U8x2 => DynamicImage::ImageLumaA8(
-      image::GrayAlphaImage::from_vec(
-        dst_img.width().get(),
-        dst_img.height().get(),
-        dst_img.into_vec(),
-      )
-      .unwrap(),
-    ),
-    _ => unreachable!(),
+    let var = |name: &str| (name == "MAX_IMAGE_PIXELS").then(|| "1000".to_owned());
+    let custom = limits(var).unwrap();
+    assert_eq!(
+      (custom.max_pixels, custom.max_alloc),
+      (1000, DEFAULT_LIMITS.max_alloc)
+    );
+    assert!(limits(|_| Some("lots".into())).is_err());
   }
 }